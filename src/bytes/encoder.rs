@@ -0,0 +1,441 @@
+use super::{
+  instructions::{BlockType, Instruction},
+  module::Module,
+  section::SectionCode,
+  types::{
+    ConstExpr, CustomSection, DecodedBody, ExportDesc, FuncType, FunctionBody, GlobalType, ImportDesc, Limits,
+    TableType, ValueType,
+  },
+};
+
+// https://webassembly.github.io/spec/core/binary/modules.html#binary-module
+const SECTION_ORDER: [SectionCode; 11] = [
+  SectionCode::Type,
+  SectionCode::Import,
+  SectionCode::Function,
+  SectionCode::Table,
+  SectionCode::Memory,
+  SectionCode::Global,
+  SectionCode::Export,
+  SectionCode::Start,
+  SectionCode::Element,
+  SectionCode::Code,
+  SectionCode::Data,
+];
+
+pub fn encode_u32(out: &mut Vec<u8>, mut value: u32) {
+  loop {
+    let byte = (value & 0x7f) as u8;
+    value >>= 7;
+    if value == 0 {
+      out.push(byte);
+      return;
+    }
+    out.push(byte | 0x80);
+  }
+}
+
+pub fn encode_i64(out: &mut Vec<u8>, mut value: i64) {
+  loop {
+    let byte = (value & 0x7f) as u8;
+    value >>= 7;
+    let sign_bit_clear = byte & 0x40 == 0;
+    if (value == 0 && sign_bit_clear) || (value == -1 && !sign_bit_clear) {
+      out.push(byte);
+      return;
+    }
+    out.push(byte | 0x80);
+  }
+}
+
+pub fn encode_i32(out: &mut Vec<u8>, value: i32) {
+  encode_i64(out, value as i64);
+}
+
+fn encode_len(out: &mut Vec<u8>, len: usize) {
+  encode_u32(out, len as u32);
+}
+
+fn encode_vec<T>(out: &mut Vec<u8>, items: &[T], mut encode_item: impl FnMut(&mut Vec<u8>, &T)) {
+  encode_len(out, items.len());
+  for item in items {
+    encode_item(out, item);
+  }
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &str) {
+  encode_len(out, name.len());
+  out.extend_from_slice(name.as_bytes());
+}
+
+fn encode_value_type(out: &mut Vec<u8>, value_type: &ValueType) {
+  out.push(*value_type as u8);
+}
+
+fn encode_func_type(out: &mut Vec<u8>, func_type: &FuncType) {
+  out.push(0x60);
+  encode_vec(out, &func_type.params, encode_value_type);
+  encode_vec(out, &func_type.results, encode_value_type);
+}
+
+fn encode_limits(out: &mut Vec<u8>, limits: &Limits) {
+  match limits.max {
+    None => {
+      out.push(0x00);
+      encode_u32(out, limits.min);
+    }
+    Some(max) => {
+      out.push(0x01);
+      encode_u32(out, limits.min);
+      encode_u32(out, max);
+    }
+  }
+}
+
+fn encode_table_type(out: &mut Vec<u8>, table_type: &TableType) {
+  out.push(table_type.element_type as u8);
+  encode_limits(out, &table_type.limits);
+}
+
+fn encode_global_type(out: &mut Vec<u8>, global_type: &GlobalType) {
+  encode_value_type(out, &global_type.value_type);
+  out.push(global_type.mutable as u8);
+}
+
+fn encode_const_expr(out: &mut Vec<u8>, expr: &ConstExpr) {
+  match expr {
+    ConstExpr::I32Const(value) => {
+      out.push(0x41);
+      encode_i32(out, *value);
+    }
+    ConstExpr::I64Const(value) => {
+      out.push(0x42);
+      encode_i64(out, *value);
+    }
+    ConstExpr::F32Const(bits) => {
+      out.push(0x43);
+      out.extend_from_slice(&bits.to_le_bytes());
+    }
+    ConstExpr::F64Const(bits) => {
+      out.push(0x44);
+      out.extend_from_slice(&bits.to_le_bytes());
+    }
+    ConstExpr::GlobalGet(global_idx) => {
+      out.push(0x23);
+      encode_u32(out, *global_idx);
+    }
+  }
+  out.push(0x0b);
+}
+
+fn encode_block_type(out: &mut Vec<u8>, block_type: &BlockType) {
+  match block_type {
+    BlockType::Empty => out.push(0x40),
+    BlockType::Value(value_type) => encode_value_type(out, value_type),
+  }
+}
+
+pub fn encode_instruction(out: &mut Vec<u8>, instruction: &Instruction) {
+  match instruction {
+    Instruction::Unreachable => out.push(0x00),
+    Instruction::Nop => out.push(0x01),
+    Instruction::Block(block_type) => {
+      out.push(0x02);
+      encode_block_type(out, block_type);
+    }
+    Instruction::Loop(block_type) => {
+      out.push(0x03);
+      encode_block_type(out, block_type);
+    }
+    Instruction::If(block_type) => {
+      out.push(0x04);
+      encode_block_type(out, block_type);
+    }
+    Instruction::Else => out.push(0x05),
+    Instruction::End => out.push(0x0b),
+    Instruction::Br(label_idx) => {
+      out.push(0x0c);
+      encode_u32(out, *label_idx);
+    }
+    Instruction::BrIf(label_idx) => {
+      out.push(0x0d);
+      encode_u32(out, *label_idx);
+    }
+    Instruction::BrTable { labels, default } => {
+      out.push(0x0e);
+      encode_vec(out, labels, |out, label_idx| encode_u32(out, *label_idx));
+      encode_u32(out, *default);
+    }
+    Instruction::Return => out.push(0x0f),
+    Instruction::Call(func_idx) => {
+      out.push(0x10);
+      encode_u32(out, *func_idx);
+    }
+    Instruction::CallIndirect(type_idx) => {
+      out.push(0x11);
+      encode_u32(out, *type_idx);
+      out.push(0x00);
+    }
+    Instruction::Drop => out.push(0x1a),
+    Instruction::Select => out.push(0x1b),
+    Instruction::LocalGet(local_idx) => {
+      out.push(0x20);
+      encode_u32(out, *local_idx);
+    }
+    Instruction::LocalSet(local_idx) => {
+      out.push(0x21);
+      encode_u32(out, *local_idx);
+    }
+    Instruction::LocalTee(local_idx) => {
+      out.push(0x22);
+      encode_u32(out, *local_idx);
+    }
+    Instruction::GlobalGet(global_idx) => {
+      out.push(0x23);
+      encode_u32(out, *global_idx);
+    }
+    Instruction::GlobalSet(global_idx) => {
+      out.push(0x24);
+      encode_u32(out, *global_idx);
+    }
+    Instruction::Memory(op, memarg) => {
+      out.push(*op as u8);
+      encode_u32(out, memarg.align);
+      encode_u32(out, memarg.offset);
+    }
+    Instruction::MemorySize => out.extend_from_slice(&[0x3f, 0x00]),
+    Instruction::MemoryGrow => out.extend_from_slice(&[0x40, 0x00]),
+    Instruction::I32Const(value) => {
+      out.push(0x41);
+      encode_i32(out, *value);
+    }
+    Instruction::I64Const(value) => {
+      out.push(0x42);
+      encode_i64(out, *value);
+    }
+    Instruction::F32Const(bits) => {
+      out.push(0x43);
+      out.extend_from_slice(&bits.to_le_bytes());
+    }
+    Instruction::F64Const(bits) => {
+      out.push(0x44);
+      out.extend_from_slice(&bits.to_le_bytes());
+    }
+    Instruction::Numeric(op) => out.push(*op as u8),
+  }
+}
+
+fn encode_decoded_body(out: &mut Vec<u8>, decoded: &DecodedBody) {
+  encode_vec(out, &decoded.locals, |out, local| {
+    encode_u32(out, local.count);
+    encode_value_type(out, &local.value_type);
+  });
+  for instruction in &decoded.body {
+    encode_instruction(out, &instruction.instruction);
+  }
+}
+
+// bodies that still match their original bytes are copied verbatim
+fn encode_function_body(out: &mut Vec<u8>, body: &FunctionBody, bytes: &[u8]) {
+  let mut contents = vec![];
+  match &body.range {
+    Some(range) => contents.extend_from_slice(&bytes[range.start..range.end]),
+    None => encode_decoded_body(
+      &mut contents,
+      body.decoded().expect("a function body without bytes is always decoded"),
+    ),
+  }
+  encode_len(out, contents.len());
+  out.extend_from_slice(&contents);
+}
+
+fn encode_custom_section(out: &mut Vec<u8>, custom: &CustomSection) {
+  encode_name(out, &custom.name);
+  out.extend_from_slice(&custom.data);
+}
+
+impl Module {
+  pub fn encode(&self) -> Vec<u8> {
+    let mut out = b"\0asm".to_vec();
+    out.extend_from_slice(&self.version.to_le_bytes());
+    self.encode_custom_sections(&mut out, None);
+    for code in SECTION_ORDER {
+      let mut contents = vec![];
+      if !self.encode_section(&mut contents, code) {
+        continue;
+      }
+      out.push(code as u8);
+      encode_len(&mut out, contents.len());
+      out.extend_from_slice(&contents);
+      self.encode_custom_sections(&mut out, Some(code));
+    }
+    out
+  }
+
+  fn encode_custom_sections(&self, out: &mut Vec<u8>, after: Option<SectionCode>) {
+    for custom in self.custom_sections.iter().filter(|custom| custom.after == after) {
+      let mut contents = vec![];
+      encode_custom_section(&mut contents, custom);
+      out.push(SectionCode::Custom as u8);
+      encode_len(out, contents.len());
+      out.extend_from_slice(&contents);
+    }
+  }
+
+  // returns false when the module has no such section
+  fn encode_section(&self, out: &mut Vec<u8>, code: SectionCode) -> bool {
+    match code {
+      SectionCode::Type => {
+        let Some(types) = &self.type_section else { return false };
+        encode_vec(out, types, encode_func_type);
+      }
+      SectionCode::Import => {
+        let Some(imports) = &self.import_section else {
+          return false;
+        };
+        encode_vec(out, imports, |out, import| {
+          encode_name(out, &import.module);
+          encode_name(out, &import.name);
+          match &import.desc {
+            ImportDesc::Func(type_idx) => {
+              out.push(0x00);
+              encode_u32(out, *type_idx);
+            }
+            ImportDesc::Table(table_type) => {
+              out.push(0x01);
+              encode_table_type(out, table_type);
+            }
+            ImportDesc::Memory(memory_type) => {
+              out.push(0x02);
+              encode_limits(out, &memory_type.limits);
+            }
+            ImportDesc::Global(global_type) => {
+              out.push(0x03);
+              encode_global_type(out, global_type);
+            }
+          }
+        });
+      }
+      SectionCode::Function => {
+        let Some(functions) = &self.function_section else {
+          return false;
+        };
+        encode_vec(out, functions, |out, type_idx| encode_u32(out, *type_idx));
+      }
+      SectionCode::Table => {
+        let Some(tables) = &self.table_section else {
+          return false;
+        };
+        encode_vec(out, tables, encode_table_type);
+      }
+      SectionCode::Memory => {
+        let Some(memories) = &self.memory_section else {
+          return false;
+        };
+        encode_vec(out, memories, |out, memory_type| {
+          encode_limits(out, &memory_type.limits)
+        });
+      }
+      SectionCode::Global => {
+        let Some(globals) = &self.global_section else {
+          return false;
+        };
+        encode_vec(out, globals, |out, global| {
+          encode_global_type(out, &global.global_type);
+          encode_const_expr(out, &global.init);
+        });
+      }
+      SectionCode::Export => {
+        let Some(exports) = &self.export_section else {
+          return false;
+        };
+        encode_vec(out, exports, |out, export| {
+          encode_name(out, &export.name);
+          let (kind, index) = match export.desc {
+            ExportDesc::Func(index) => (0x00, index),
+            ExportDesc::Table(index) => (0x01, index),
+            ExportDesc::Memory(index) => (0x02, index),
+            ExportDesc::Global(index) => (0x03, index),
+          };
+          out.push(kind);
+          encode_u32(out, index);
+        });
+      }
+      SectionCode::Start => {
+        let Some(func_idx) = self.start_section else {
+          return false;
+        };
+        encode_u32(out, func_idx);
+      }
+      SectionCode::Element => {
+        let Some(elements) = &self.element_section else {
+          return false;
+        };
+        encode_vec(out, elements, |out, element| {
+          encode_u32(out, element.table_idx);
+          encode_const_expr(out, &element.offset);
+          encode_vec(out, &element.init, |out, func_idx| encode_u32(out, *func_idx));
+        });
+      }
+      SectionCode::Code => {
+        let Some(bodies) = &self.code_section else { return false };
+        let bytes = self.bytes.as_slice();
+        encode_vec(out, bodies, |out, body| encode_function_body(out, body, bytes));
+      }
+      SectionCode::Data => {
+        let Some(data) = &self.data_section else { return false };
+        encode_vec(out, data, |out, data| {
+          encode_u32(out, data.memory_idx);
+          encode_const_expr(out, &data.offset);
+          encode_len(out, data.init.len());
+          out.extend_from_slice(&data.init);
+        });
+      }
+      SectionCode::Custom => return false,
+    }
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn playground(name: &str) -> Vec<u8> {
+    wat::parse_file(format!("tests/playground/{}.wat", name)).unwrap()
+  }
+
+  // once every body is re-encoded from its instructions, nothing is copied from the original bytes
+  fn encode_decoded(bytes: &[u8]) -> Vec<u8> {
+    let mut module = Module::new(bytes).unwrap();
+    for index in 0..module.code_section.as_ref().map_or(0, Vec::len) {
+      module.function_body_mut(index).unwrap();
+    }
+    module.encode()
+  }
+
+  #[test]
+  fn roundtrip_canonical_modules() {
+    for name in ["add", "arithmetic", "factorial", "store_and_load", "use_external_add"] {
+      let bytes = playground(name);
+      assert_eq!(Module::new(&bytes).unwrap().encode(), bytes, "{}", name);
+      assert_eq!(encode_decoded(&bytes), bytes, "{}", name);
+    }
+  }
+
+  #[test]
+  fn roundtrip_custom_sections_between_sections() {
+    let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+    bytes.extend_from_slice(&[0x00, 0x04, 0x03, b'o', b'n', b'e']);
+    bytes.extend_from_slice(&[0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f]);
+    bytes.extend_from_slice(&[0x00, 0x05, 0x03, b't', b'w', b'o', 0xff]);
+    bytes.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]);
+    bytes.extend_from_slice(&[0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x2a, 0x0b]);
+    bytes.extend_from_slice(&[0x00, 0x06, 0x05, b't', b'h', b'r', b'e', b'e']);
+    let module = Module::new(&bytes).unwrap();
+    let after: Vec<Option<SectionCode>> = module.custom_sections.iter().map(|custom| custom.after).collect();
+    assert_eq!(after, vec![None, Some(SectionCode::Type), Some(SectionCode::Code)]);
+    assert_eq!(module.encode(), bytes);
+    assert_eq!(encode_decoded(&bytes), bytes);
+  }
+}
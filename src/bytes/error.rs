@@ -0,0 +1,53 @@
+use nom::error::{ContextError, ErrorKind, ParseError};
+
+pub const UNEXPECTED_END: &str = "unexpected end";
+
+pub type DecodeResult<'a, T> = nom::IResult<&'a [u8], T, DecodeError<'a>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError<'a> {
+  // the input ended before the construct did, a streaming decoder can wait for more bytes
+  Incomplete {
+    input: &'a [u8], // what was left of the input
+  },
+  Malformed {
+    input: &'a [u8], // bytes starting at the malformed construct
+    length: usize,   // how many of those bytes are malformed
+    cause: String,
+  },
+}
+
+impl<'a> DecodeError<'a> {
+  pub fn new(input: &'a [u8], length: usize, cause: &str) -> Self {
+    Self::Malformed { input, length, cause: cause.to_string() }
+  }
+}
+
+impl<'a> ParseError<&'a [u8]> for DecodeError<'a> {
+  fn from_error_kind(input: &'a [u8], kind: ErrorKind) -> Self {
+    match kind {
+      ErrorKind::Eof => Self::Incomplete { input },
+      ErrorKind::TooLarge => Self::new(input, 1, "integer representation too long"),
+      _ => Self::new(input, 1, "malformed module"),
+    }
+  }
+
+  // keep the innermost error, it points at the malformed bytes
+  fn append(_: &'a [u8], _: ErrorKind, other: Self) -> Self {
+    other
+  }
+}
+
+impl<'a> ContextError<&'a [u8]> for DecodeError<'a> {}
+
+pub fn decode_error<'a, T>(input: &'a [u8], cause: &str) -> DecodeResult<'a, T> {
+  Err(nom::Err::Error(DecodeError::new(input, 1, cause)))
+}
+
+pub fn decode_error_with_length<'a, T>(input: &'a [u8], length: usize, cause: &str) -> DecodeResult<'a, T> {
+  Err(nom::Err::Error(DecodeError::new(input, length, cause)))
+}
+
+pub fn decode_incomplete<T>(input: &[u8]) -> DecodeResult<'_, T> {
+  Err(nom::Err::Error(DecodeError::Incomplete { input }))
+}
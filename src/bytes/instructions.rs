@@ -0,0 +1,632 @@
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive as _;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::range::Range;
+
+use super::{
+  error::{decode_error, DecodeResult},
+  module::{
+    decode_f32_bits, decode_f64_bits, decode_i32, decode_i64, decode_u32, decode_u8, decode_value_type, decode_vec,
+    expect_byte,
+  },
+  types::ValueType,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockType {
+  Empty, // 0x40
+  Value(ValueType),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemArg {
+  pub align: u32,
+  pub offset: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, Serialize, Deserialize)]
+pub enum MemoryOp {
+  I32Load = 0x28,
+  I64Load = 0x29,
+  F32Load = 0x2a,
+  F64Load = 0x2b,
+  I32Load8S = 0x2c,
+  I32Load8U = 0x2d,
+  I32Load16S = 0x2e,
+  I32Load16U = 0x2f,
+  I64Load8S = 0x30,
+  I64Load8U = 0x31,
+  I64Load16S = 0x32,
+  I64Load16U = 0x33,
+  I64Load32S = 0x34,
+  I64Load32U = 0x35,
+  I32Store = 0x36,
+  I64Store = 0x37,
+  F32Store = 0x38,
+  F64Store = 0x39,
+  I32Store8 = 0x3a,
+  I32Store16 = 0x3b,
+  I64Store8 = 0x3c,
+  I64Store16 = 0x3d,
+  I64Store32 = 0x3e,
+}
+
+impl MemoryOp {
+  // how many bytes are accessed, which is also the largest valid alignment
+  pub fn natural_alignment(&self) -> u32 {
+    match self {
+      MemoryOp::I32Load8S | MemoryOp::I32Load8U | MemoryOp::I64Load8S | MemoryOp::I64Load8U => 1,
+      MemoryOp::I32Store8 | MemoryOp::I64Store8 => 1,
+      MemoryOp::I32Load16S | MemoryOp::I32Load16U | MemoryOp::I64Load16S | MemoryOp::I64Load16U => 2,
+      MemoryOp::I32Store16 | MemoryOp::I64Store16 => 2,
+      MemoryOp::I32Load | MemoryOp::F32Load | MemoryOp::I64Load32S | MemoryOp::I64Load32U => 4,
+      MemoryOp::I32Store | MemoryOp::F32Store | MemoryOp::I64Store32 => 4,
+      MemoryOp::I64Load | MemoryOp::F64Load | MemoryOp::I64Store | MemoryOp::F64Store => 8,
+    }
+  }
+
+  pub fn is_store(&self) -> bool {
+    matches!(
+      self,
+      MemoryOp::I32Store
+        | MemoryOp::I64Store
+        | MemoryOp::F32Store
+        | MemoryOp::F64Store
+        | MemoryOp::I32Store8
+        | MemoryOp::I32Store16
+        | MemoryOp::I64Store8
+        | MemoryOp::I64Store16
+        | MemoryOp::I64Store32
+    )
+  }
+
+  // the type loaded onto or stored from the stack
+  pub fn value_type(&self) -> ValueType {
+    match self {
+      MemoryOp::I32Load | MemoryOp::I32Load8S | MemoryOp::I32Load8U | MemoryOp::I32Load16S | MemoryOp::I32Load16U => {
+        ValueType::I32
+      }
+      MemoryOp::I32Store | MemoryOp::I32Store8 | MemoryOp::I32Store16 => ValueType::I32,
+      MemoryOp::F32Load | MemoryOp::F32Store => ValueType::F32,
+      MemoryOp::F64Load | MemoryOp::F64Store => ValueType::F64,
+      MemoryOp::I64Load | MemoryOp::I64Load8S | MemoryOp::I64Load8U | MemoryOp::I64Load16S | MemoryOp::I64Load16U => {
+        ValueType::I64
+      }
+      MemoryOp::I64Load32S | MemoryOp::I64Load32U => ValueType::I64,
+      MemoryOp::I64Store | MemoryOp::I64Store8 | MemoryOp::I64Store16 | MemoryOp::I64Store32 => ValueType::I64,
+    }
+  }
+
+  // the text format keyword, e.g. `i32.load`
+  pub fn name(&self) -> &'static str {
+    match self {
+      MemoryOp::I32Load => "i32.load",
+      MemoryOp::I64Load => "i64.load",
+      MemoryOp::F32Load => "f32.load",
+      MemoryOp::F64Load => "f64.load",
+      MemoryOp::I32Load8S => "i32.load8_s",
+      MemoryOp::I32Load8U => "i32.load8_u",
+      MemoryOp::I32Load16S => "i32.load16_s",
+      MemoryOp::I32Load16U => "i32.load16_u",
+      MemoryOp::I64Load8S => "i64.load8_s",
+      MemoryOp::I64Load8U => "i64.load8_u",
+      MemoryOp::I64Load16S => "i64.load16_s",
+      MemoryOp::I64Load16U => "i64.load16_u",
+      MemoryOp::I64Load32S => "i64.load32_s",
+      MemoryOp::I64Load32U => "i64.load32_u",
+      MemoryOp::I32Store => "i32.store",
+      MemoryOp::I64Store => "i64.store",
+      MemoryOp::F32Store => "f32.store",
+      MemoryOp::F64Store => "f64.store",
+      MemoryOp::I32Store8 => "i32.store8",
+      MemoryOp::I32Store16 => "i32.store16",
+      MemoryOp::I64Store8 => "i64.store8",
+      MemoryOp::I64Store16 => "i64.store16",
+      MemoryOp::I64Store32 => "i64.store32",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    (0x28..=0x3e).filter_map(Self::from_u8).find(|op| op.name() == name)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, Serialize, Deserialize)]
+pub enum NumericOp {
+  I32Eqz = 0x45,
+  I32Eq = 0x46,
+  I32Ne = 0x47,
+  I32LtS = 0x48,
+  I32LtU = 0x49,
+  I32GtS = 0x4a,
+  I32GtU = 0x4b,
+  I32LeS = 0x4c,
+  I32LeU = 0x4d,
+  I32GeS = 0x4e,
+  I32GeU = 0x4f,
+  I64Eqz = 0x50,
+  I64Eq = 0x51,
+  I64Ne = 0x52,
+  I64LtS = 0x53,
+  I64LtU = 0x54,
+  I64GtS = 0x55,
+  I64GtU = 0x56,
+  I64LeS = 0x57,
+  I64LeU = 0x58,
+  I64GeS = 0x59,
+  I64GeU = 0x5a,
+  F32Eq = 0x5b,
+  F32Ne = 0x5c,
+  F32Lt = 0x5d,
+  F32Gt = 0x5e,
+  F32Le = 0x5f,
+  F32Ge = 0x60,
+  F64Eq = 0x61,
+  F64Ne = 0x62,
+  F64Lt = 0x63,
+  F64Gt = 0x64,
+  F64Le = 0x65,
+  F64Ge = 0x66,
+  I32Clz = 0x67,
+  I32Ctz = 0x68,
+  I32Popcnt = 0x69,
+  I32Add = 0x6a,
+  I32Sub = 0x6b,
+  I32Mul = 0x6c,
+  I32DivS = 0x6d,
+  I32DivU = 0x6e,
+  I32RemS = 0x6f,
+  I32RemU = 0x70,
+  I32And = 0x71,
+  I32Or = 0x72,
+  I32Xor = 0x73,
+  I32Shl = 0x74,
+  I32ShrS = 0x75,
+  I32ShrU = 0x76,
+  I32Rotl = 0x77,
+  I32Rotr = 0x78,
+  I64Clz = 0x79,
+  I64Ctz = 0x7a,
+  I64Popcnt = 0x7b,
+  I64Add = 0x7c,
+  I64Sub = 0x7d,
+  I64Mul = 0x7e,
+  I64DivS = 0x7f,
+  I64DivU = 0x80,
+  I64RemS = 0x81,
+  I64RemU = 0x82,
+  I64And = 0x83,
+  I64Or = 0x84,
+  I64Xor = 0x85,
+  I64Shl = 0x86,
+  I64ShrS = 0x87,
+  I64ShrU = 0x88,
+  I64Rotl = 0x89,
+  I64Rotr = 0x8a,
+  F32Abs = 0x8b,
+  F32Neg = 0x8c,
+  F32Ceil = 0x8d,
+  F32Floor = 0x8e,
+  F32Trunc = 0x8f,
+  F32Nearest = 0x90,
+  F32Sqrt = 0x91,
+  F32Add = 0x92,
+  F32Sub = 0x93,
+  F32Mul = 0x94,
+  F32Div = 0x95,
+  F32Min = 0x96,
+  F32Max = 0x97,
+  F32Copysign = 0x98,
+  F64Abs = 0x99,
+  F64Neg = 0x9a,
+  F64Ceil = 0x9b,
+  F64Floor = 0x9c,
+  F64Trunc = 0x9d,
+  F64Nearest = 0x9e,
+  F64Sqrt = 0x9f,
+  F64Add = 0xa0,
+  F64Sub = 0xa1,
+  F64Mul = 0xa2,
+  F64Div = 0xa3,
+  F64Min = 0xa4,
+  F64Max = 0xa5,
+  F64Copysign = 0xa6,
+  I32WrapI64 = 0xa7,
+  I32TruncF32S = 0xa8,
+  I32TruncF32U = 0xa9,
+  I32TruncF64S = 0xaa,
+  I32TruncF64U = 0xab,
+  I64ExtendI32S = 0xac,
+  I64ExtendI32U = 0xad,
+  I64TruncF32S = 0xae,
+  I64TruncF32U = 0xaf,
+  I64TruncF64S = 0xb0,
+  I64TruncF64U = 0xb1,
+  F32ConvertI32S = 0xb2,
+  F32ConvertI32U = 0xb3,
+  F32ConvertI64S = 0xb4,
+  F32ConvertI64U = 0xb5,
+  F32DemoteF64 = 0xb6,
+  F64ConvertI32S = 0xb7,
+  F64ConvertI32U = 0xb8,
+  F64ConvertI64S = 0xb9,
+  F64ConvertI64U = 0xba,
+  F64PromoteF32 = 0xbb,
+  I32ReinterpretF32 = 0xbc,
+  I64ReinterpretF64 = 0xbd,
+  F32ReinterpretI32 = 0xbe,
+  F64ReinterpretI64 = 0xbf,
+}
+
+impl NumericOp {
+  // the text format keyword, e.g. `i32.eqz`
+  pub fn name(&self) -> &'static str {
+    match self {
+      NumericOp::I32Eqz => "i32.eqz",
+      NumericOp::I32Eq => "i32.eq",
+      NumericOp::I32Ne => "i32.ne",
+      NumericOp::I32LtS => "i32.lt_s",
+      NumericOp::I32LtU => "i32.lt_u",
+      NumericOp::I32GtS => "i32.gt_s",
+      NumericOp::I32GtU => "i32.gt_u",
+      NumericOp::I32LeS => "i32.le_s",
+      NumericOp::I32LeU => "i32.le_u",
+      NumericOp::I32GeS => "i32.ge_s",
+      NumericOp::I32GeU => "i32.ge_u",
+      NumericOp::I64Eqz => "i64.eqz",
+      NumericOp::I64Eq => "i64.eq",
+      NumericOp::I64Ne => "i64.ne",
+      NumericOp::I64LtS => "i64.lt_s",
+      NumericOp::I64LtU => "i64.lt_u",
+      NumericOp::I64GtS => "i64.gt_s",
+      NumericOp::I64GtU => "i64.gt_u",
+      NumericOp::I64LeS => "i64.le_s",
+      NumericOp::I64LeU => "i64.le_u",
+      NumericOp::I64GeS => "i64.ge_s",
+      NumericOp::I64GeU => "i64.ge_u",
+      NumericOp::F32Eq => "f32.eq",
+      NumericOp::F32Ne => "f32.ne",
+      NumericOp::F32Lt => "f32.lt",
+      NumericOp::F32Gt => "f32.gt",
+      NumericOp::F32Le => "f32.le",
+      NumericOp::F32Ge => "f32.ge",
+      NumericOp::F64Eq => "f64.eq",
+      NumericOp::F64Ne => "f64.ne",
+      NumericOp::F64Lt => "f64.lt",
+      NumericOp::F64Gt => "f64.gt",
+      NumericOp::F64Le => "f64.le",
+      NumericOp::F64Ge => "f64.ge",
+      NumericOp::I32Clz => "i32.clz",
+      NumericOp::I32Ctz => "i32.ctz",
+      NumericOp::I32Popcnt => "i32.popcnt",
+      NumericOp::I32Add => "i32.add",
+      NumericOp::I32Sub => "i32.sub",
+      NumericOp::I32Mul => "i32.mul",
+      NumericOp::I32DivS => "i32.div_s",
+      NumericOp::I32DivU => "i32.div_u",
+      NumericOp::I32RemS => "i32.rem_s",
+      NumericOp::I32RemU => "i32.rem_u",
+      NumericOp::I32And => "i32.and",
+      NumericOp::I32Or => "i32.or",
+      NumericOp::I32Xor => "i32.xor",
+      NumericOp::I32Shl => "i32.shl",
+      NumericOp::I32ShrS => "i32.shr_s",
+      NumericOp::I32ShrU => "i32.shr_u",
+      NumericOp::I32Rotl => "i32.rotl",
+      NumericOp::I32Rotr => "i32.rotr",
+      NumericOp::I64Clz => "i64.clz",
+      NumericOp::I64Ctz => "i64.ctz",
+      NumericOp::I64Popcnt => "i64.popcnt",
+      NumericOp::I64Add => "i64.add",
+      NumericOp::I64Sub => "i64.sub",
+      NumericOp::I64Mul => "i64.mul",
+      NumericOp::I64DivS => "i64.div_s",
+      NumericOp::I64DivU => "i64.div_u",
+      NumericOp::I64RemS => "i64.rem_s",
+      NumericOp::I64RemU => "i64.rem_u",
+      NumericOp::I64And => "i64.and",
+      NumericOp::I64Or => "i64.or",
+      NumericOp::I64Xor => "i64.xor",
+      NumericOp::I64Shl => "i64.shl",
+      NumericOp::I64ShrS => "i64.shr_s",
+      NumericOp::I64ShrU => "i64.shr_u",
+      NumericOp::I64Rotl => "i64.rotl",
+      NumericOp::I64Rotr => "i64.rotr",
+      NumericOp::F32Abs => "f32.abs",
+      NumericOp::F32Neg => "f32.neg",
+      NumericOp::F32Ceil => "f32.ceil",
+      NumericOp::F32Floor => "f32.floor",
+      NumericOp::F32Trunc => "f32.trunc",
+      NumericOp::F32Nearest => "f32.nearest",
+      NumericOp::F32Sqrt => "f32.sqrt",
+      NumericOp::F32Add => "f32.add",
+      NumericOp::F32Sub => "f32.sub",
+      NumericOp::F32Mul => "f32.mul",
+      NumericOp::F32Div => "f32.div",
+      NumericOp::F32Min => "f32.min",
+      NumericOp::F32Max => "f32.max",
+      NumericOp::F32Copysign => "f32.copysign",
+      NumericOp::F64Abs => "f64.abs",
+      NumericOp::F64Neg => "f64.neg",
+      NumericOp::F64Ceil => "f64.ceil",
+      NumericOp::F64Floor => "f64.floor",
+      NumericOp::F64Trunc => "f64.trunc",
+      NumericOp::F64Nearest => "f64.nearest",
+      NumericOp::F64Sqrt => "f64.sqrt",
+      NumericOp::F64Add => "f64.add",
+      NumericOp::F64Sub => "f64.sub",
+      NumericOp::F64Mul => "f64.mul",
+      NumericOp::F64Div => "f64.div",
+      NumericOp::F64Min => "f64.min",
+      NumericOp::F64Max => "f64.max",
+      NumericOp::F64Copysign => "f64.copysign",
+      NumericOp::I32WrapI64 => "i32.wrap_i64",
+      NumericOp::I32TruncF32S => "i32.trunc_f32_s",
+      NumericOp::I32TruncF32U => "i32.trunc_f32_u",
+      NumericOp::I32TruncF64S => "i32.trunc_f64_s",
+      NumericOp::I32TruncF64U => "i32.trunc_f64_u",
+      NumericOp::I64ExtendI32S => "i64.extend_i32_s",
+      NumericOp::I64ExtendI32U => "i64.extend_i32_u",
+      NumericOp::I64TruncF32S => "i64.trunc_f32_s",
+      NumericOp::I64TruncF32U => "i64.trunc_f32_u",
+      NumericOp::I64TruncF64S => "i64.trunc_f64_s",
+      NumericOp::I64TruncF64U => "i64.trunc_f64_u",
+      NumericOp::F32ConvertI32S => "f32.convert_i32_s",
+      NumericOp::F32ConvertI32U => "f32.convert_i32_u",
+      NumericOp::F32ConvertI64S => "f32.convert_i64_s",
+      NumericOp::F32ConvertI64U => "f32.convert_i64_u",
+      NumericOp::F32DemoteF64 => "f32.demote_f64",
+      NumericOp::F64ConvertI32S => "f64.convert_i32_s",
+      NumericOp::F64ConvertI32U => "f64.convert_i32_u",
+      NumericOp::F64ConvertI64S => "f64.convert_i64_s",
+      NumericOp::F64ConvertI64U => "f64.convert_i64_u",
+      NumericOp::F64PromoteF32 => "f64.promote_f32",
+      NumericOp::I32ReinterpretF32 => "i32.reinterpret_f32",
+      NumericOp::I64ReinterpretF64 => "i64.reinterpret_f64",
+      NumericOp::F32ReinterpretI32 => "f32.reinterpret_i32",
+      NumericOp::F64ReinterpretI64 => "f64.reinterpret_i64",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    (0x45..=0xbf).filter_map(Self::from_u8).find(|op| op.name() == name)
+  }
+
+  // operand types and result type
+  pub fn signature(&self) -> (&'static [ValueType], ValueType) {
+    use ValueType::{F32, F64, I32, I64};
+    match self {
+      NumericOp::I32Eqz => (&[I32], I32),
+      NumericOp::I32Eq
+      | NumericOp::I32Ne
+      | NumericOp::I32LtS
+      | NumericOp::I32LtU
+      | NumericOp::I32GtS
+      | NumericOp::I32GtU
+      | NumericOp::I32LeS
+      | NumericOp::I32LeU
+      | NumericOp::I32GeS
+      | NumericOp::I32GeU => (&[I32, I32], I32),
+      NumericOp::I64Eqz => (&[I64], I32),
+      NumericOp::I64Eq
+      | NumericOp::I64Ne
+      | NumericOp::I64LtS
+      | NumericOp::I64LtU
+      | NumericOp::I64GtS
+      | NumericOp::I64GtU
+      | NumericOp::I64LeS
+      | NumericOp::I64LeU
+      | NumericOp::I64GeS
+      | NumericOp::I64GeU => (&[I64, I64], I32),
+      NumericOp::F32Eq
+      | NumericOp::F32Ne
+      | NumericOp::F32Lt
+      | NumericOp::F32Gt
+      | NumericOp::F32Le
+      | NumericOp::F32Ge => (&[F32, F32], I32),
+      NumericOp::F64Eq
+      | NumericOp::F64Ne
+      | NumericOp::F64Lt
+      | NumericOp::F64Gt
+      | NumericOp::F64Le
+      | NumericOp::F64Ge => (&[F64, F64], I32),
+      NumericOp::I32Clz | NumericOp::I32Ctz | NumericOp::I32Popcnt => (&[I32], I32),
+      NumericOp::I32Add
+      | NumericOp::I32Sub
+      | NumericOp::I32Mul
+      | NumericOp::I32DivS
+      | NumericOp::I32DivU
+      | NumericOp::I32RemS
+      | NumericOp::I32RemU
+      | NumericOp::I32And
+      | NumericOp::I32Or
+      | NumericOp::I32Xor
+      | NumericOp::I32Shl
+      | NumericOp::I32ShrS
+      | NumericOp::I32ShrU
+      | NumericOp::I32Rotl
+      | NumericOp::I32Rotr => (&[I32, I32], I32),
+      NumericOp::I64Clz | NumericOp::I64Ctz | NumericOp::I64Popcnt => (&[I64], I64),
+      NumericOp::I64Add
+      | NumericOp::I64Sub
+      | NumericOp::I64Mul
+      | NumericOp::I64DivS
+      | NumericOp::I64DivU
+      | NumericOp::I64RemS
+      | NumericOp::I64RemU
+      | NumericOp::I64And
+      | NumericOp::I64Or
+      | NumericOp::I64Xor
+      | NumericOp::I64Shl
+      | NumericOp::I64ShrS
+      | NumericOp::I64ShrU
+      | NumericOp::I64Rotl
+      | NumericOp::I64Rotr => (&[I64, I64], I64),
+      NumericOp::F32Abs
+      | NumericOp::F32Neg
+      | NumericOp::F32Ceil
+      | NumericOp::F32Floor
+      | NumericOp::F32Trunc
+      | NumericOp::F32Nearest
+      | NumericOp::F32Sqrt => (&[F32], F32),
+      NumericOp::F32Add
+      | NumericOp::F32Sub
+      | NumericOp::F32Mul
+      | NumericOp::F32Div
+      | NumericOp::F32Min
+      | NumericOp::F32Max
+      | NumericOp::F32Copysign => (&[F32, F32], F32),
+      NumericOp::F64Abs
+      | NumericOp::F64Neg
+      | NumericOp::F64Ceil
+      | NumericOp::F64Floor
+      | NumericOp::F64Trunc
+      | NumericOp::F64Nearest
+      | NumericOp::F64Sqrt => (&[F64], F64),
+      NumericOp::F64Add
+      | NumericOp::F64Sub
+      | NumericOp::F64Mul
+      | NumericOp::F64Div
+      | NumericOp::F64Min
+      | NumericOp::F64Max
+      | NumericOp::F64Copysign => (&[F64, F64], F64),
+      // conversions
+      NumericOp::I32WrapI64 => (&[I64], I32),
+      NumericOp::I32TruncF32S | NumericOp::I32TruncF32U => (&[F32], I32),
+      NumericOp::I32TruncF64S | NumericOp::I32TruncF64U => (&[F64], I32),
+      NumericOp::I64ExtendI32S | NumericOp::I64ExtendI32U => (&[I32], I64),
+      NumericOp::I64TruncF32S | NumericOp::I64TruncF32U => (&[F32], I64),
+      NumericOp::I64TruncF64S | NumericOp::I64TruncF64U => (&[F64], I64),
+      NumericOp::F32ConvertI32S | NumericOp::F32ConvertI32U => (&[I32], F32),
+      NumericOp::F32ConvertI64S | NumericOp::F32ConvertI64U => (&[I64], F32),
+      NumericOp::F32DemoteF64 => (&[F64], F32),
+      NumericOp::F64ConvertI32S | NumericOp::F64ConvertI32U => (&[I32], F64),
+      NumericOp::F64ConvertI64S | NumericOp::F64ConvertI64U => (&[I64], F64),
+      NumericOp::F64PromoteF32 => (&[F32], F64),
+      NumericOp::I32ReinterpretF32 => (&[F32], I32),
+      NumericOp::I64ReinterpretF64 => (&[F64], I64),
+      NumericOp::F32ReinterpretI32 => (&[I32], F32),
+      NumericOp::F64ReinterpretI64 => (&[I64], F64),
+    }
+  }
+}
+
+// https://webassembly.github.io/spec/core/binary/instructions.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+  // control
+  Unreachable,
+  Nop,
+  Block(BlockType),
+  Loop(BlockType),
+  If(BlockType),
+  Else,
+  End,
+  Br(u32),
+  BrIf(u32),
+  BrTable { labels: Vec<u32>, default: u32 },
+  Return,
+  Call(u32),
+  CallIndirect(u32), // type index, the table is always 0
+  // parametric
+  Drop,
+  Select,
+  // variable
+  LocalGet(u32),
+  LocalSet(u32),
+  LocalTee(u32),
+  GlobalGet(u32),
+  GlobalSet(u32),
+  // memory
+  Memory(MemoryOp, MemArg),
+  MemorySize,
+  MemoryGrow,
+  // numeric
+  I32Const(i32),
+  I64Const(i64),
+  F32Const(u32), // raw bits
+  F64Const(u64), // raw bits
+  Numeric(NumericOp),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInstruction {
+  pub instruction: Instruction,
+  pub range: Range, // the opcode and immediates in the module bytes, or the instruction text of a compiled module
+}
+
+fn decode_block_type(input: &[u8]) -> DecodeResult<'_, BlockType> {
+  if let Some((0x40, rest)) = input.split_first() {
+    return Ok((rest, BlockType::Empty));
+  }
+  let (rest, value_type) = decode_value_type(input)?;
+  Ok((rest, BlockType::Value(value_type)))
+}
+
+fn decode_memarg(input: &[u8]) -> DecodeResult<'_, MemArg> {
+  let (input, align) = decode_u32(input)?;
+  let (input, offset) = decode_u32(input)?;
+  Ok((input, MemArg { align, offset }))
+}
+
+pub fn decode_instruction(input: &[u8]) -> DecodeResult<'_, Instruction> {
+  let (rest, opcode) = decode_u8(input)?;
+  let (rest, instruction) = match opcode {
+    0x00 => (rest, Instruction::Unreachable),
+    0x01 => (rest, Instruction::Nop),
+    0x02 => decode_block_type(rest).map(|(rest, block_type)| (rest, Instruction::Block(block_type)))?,
+    0x03 => decode_block_type(rest).map(|(rest, block_type)| (rest, Instruction::Loop(block_type)))?,
+    0x04 => decode_block_type(rest).map(|(rest, block_type)| (rest, Instruction::If(block_type)))?,
+    0x05 => (rest, Instruction::Else),
+    0x0b => (rest, Instruction::End),
+    0x0c => decode_u32(rest).map(|(rest, label_idx)| (rest, Instruction::Br(label_idx)))?,
+    0x0d => decode_u32(rest).map(|(rest, label_idx)| (rest, Instruction::BrIf(label_idx)))?,
+    0x0e => {
+      let (rest, labels) = decode_vec(rest, decode_u32)?;
+      let (rest, default) = decode_u32(rest)?;
+      (rest, Instruction::BrTable { labels, default })
+    }
+    0x0f => (rest, Instruction::Return),
+    0x10 => decode_u32(rest).map(|(rest, func_idx)| (rest, Instruction::Call(func_idx)))?,
+    0x11 => {
+      let (rest, type_idx) = decode_u32(rest)?;
+      let (rest, _) = expect_byte(rest, 0x00, "zero byte expected")?;
+      (rest, Instruction::CallIndirect(type_idx))
+    }
+    0x1a => (rest, Instruction::Drop),
+    0x1b => (rest, Instruction::Select),
+    0x20 => decode_u32(rest).map(|(rest, local_idx)| (rest, Instruction::LocalGet(local_idx)))?,
+    0x21 => decode_u32(rest).map(|(rest, local_idx)| (rest, Instruction::LocalSet(local_idx)))?,
+    0x22 => decode_u32(rest).map(|(rest, local_idx)| (rest, Instruction::LocalTee(local_idx)))?,
+    0x23 => decode_u32(rest).map(|(rest, global_idx)| (rest, Instruction::GlobalGet(global_idx)))?,
+    0x24 => decode_u32(rest).map(|(rest, global_idx)| (rest, Instruction::GlobalSet(global_idx)))?,
+    0x28..=0x3e => {
+      let op = MemoryOp::from_u8(opcode).unwrap();
+      decode_memarg(rest).map(|(rest, memarg)| (rest, Instruction::Memory(op, memarg)))?
+    }
+    0x3f => expect_byte(rest, 0x00, "zero byte expected").map(|(rest, _)| (rest, Instruction::MemorySize))?,
+    0x40 => expect_byte(rest, 0x00, "zero byte expected").map(|(rest, _)| (rest, Instruction::MemoryGrow))?,
+    0x41 => decode_i32(rest).map(|(rest, value)| (rest, Instruction::I32Const(value)))?,
+    0x42 => decode_i64(rest).map(|(rest, value)| (rest, Instruction::I64Const(value)))?,
+    0x43 => decode_f32_bits(rest).map(|(rest, bits)| (rest, Instruction::F32Const(bits)))?,
+    0x44 => decode_f64_bits(rest).map(|(rest, bits)| (rest, Instruction::F64Const(bits)))?,
+    0x45..=0xbf => (rest, Instruction::Numeric(NumericOp::from_u8(opcode).unwrap())),
+    _ => return decode_error(input, &format!("illegal opcode 0x{:02x}", opcode)),
+  };
+  Ok((rest, instruction))
+}
+
+// decodes a whole expression, `offset` is the position of `input` in the module bytes
+pub fn decode_instructions(input: &[u8], offset: usize) -> DecodeResult<'_, Vec<DecodedInstruction>> {
+  let mut instructions = vec![];
+  let mut remaining = input;
+  while !remaining.is_empty() {
+    let instruction_offset = offset + (input.len() - remaining.len());
+    let (rest, instruction) = decode_instruction(remaining)?;
+    let range = Range::new(instruction_offset, instruction_offset + (remaining.len() - rest.len()));
+    instructions.push(DecodedInstruction { instruction, range });
+    remaining = rest;
+  }
+  Ok((remaining, instructions))
+}
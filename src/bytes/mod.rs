@@ -1,3 +1,9 @@
+#![allow(dead_code)]
+pub mod encoder;
+pub mod error;
+pub mod instructions;
 pub mod module;
+pub mod names;
 pub mod section;
+pub mod stream;
 pub mod types;
@@ -1,79 +1,714 @@
-use crate::diagnostics::{ResultWithDiagnostics, RuntimeError};
+use crate::{
+  diagnostics::{ResultWithDiagnostics, RuntimeError},
+  utils::range::Range,
+};
 use nom::{
-  bytes::complete::{tag, take},
-  number::complete::{le_u32, le_u8},
-  sequence::pair,
-  IResult,
+  multi::count,
+  number::complete::{le_u32, le_u64, le_u8},
 };
-use nom_leb128::leb128_u32;
+use nom_leb128::{leb128_i32, leb128_i64, leb128_u32};
 use num_traits::FromPrimitive as _;
 
-use super::{section::SectionCode, types::FuncType};
+use super::{
+  error::{decode_error, decode_error_with_length, decode_incomplete, DecodeError, DecodeResult, UNEXPECTED_END},
+  instructions::{decode_instructions, Instruction},
+  names::{decode_name_section, NameSection},
+  section::SectionCode,
+  types::{
+    ConstExpr, CustomSection, Data, DecodedBody, Element, ElementType, Export, ExportDesc, FuncType, FunctionBody,
+    Global, GlobalType, Import, ImportDesc, Limits, Local, MemoryType, RawBytes, TableType, ValueType,
+  },
+};
 
 type TypeSection = Vec<FuncType>;
+type ImportSection = Vec<Import>;
+type FunctionSection = Vec<u32>; // type index of each function
+type TableSection = Vec<TableType>;
+type MemorySection = Vec<MemoryType>;
+type GlobalSection = Vec<Global>;
+type ExportSection = Vec<Export>;
+type ElementSection = Vec<Element>;
+type CodeSection = Vec<FunctionBody>;
+type DataSection = Vec<Data>;
+
+// position of `input` inside the `module` bytes it was sliced from
+pub(super) fn offset_of(module: &[u8], input: &[u8]) -> usize {
+  input.as_ptr() as usize - module.as_ptr() as usize
+}
+
+// the leb128 decoders report a truncated integer as incomplete input
+fn complete_leb128<'a, T>(input: &'a [u8], result: DecodeResult<'a, T>) -> DecodeResult<'a, T> {
+  match result {
+    Err(nom::Err::Incomplete(_)) => decode_incomplete(input),
+    result => result,
+  }
+}
+
+pub(super) fn decode_u8(input: &[u8]) -> DecodeResult<'_, u8> {
+  le_u8(input)
+}
+
+pub(super) fn decode_u32(input: &[u8]) -> DecodeResult<'_, u32> {
+  complete_leb128(input, leb128_u32(input))
+}
+
+pub(super) fn decode_i32(input: &[u8]) -> DecodeResult<'_, i32> {
+  complete_leb128(input, leb128_i32(input))
+}
+
+pub(super) fn decode_i64(input: &[u8]) -> DecodeResult<'_, i64> {
+  complete_leb128(input, leb128_i64(input))
+}
+
+pub(super) fn decode_f32_bits(input: &[u8]) -> DecodeResult<'_, u32> {
+  le_u32(input)
+}
+
+pub(super) fn decode_f64_bits(input: &[u8]) -> DecodeResult<'_, u64> {
+  le_u64(input)
+}
+
+pub(super) fn decode_bytes(input: &[u8], size: u32) -> DecodeResult<'_, &[u8]> {
+  if input.len() < size as usize {
+    return decode_incomplete(input);
+  }
+  let (bytes, rest) = input.split_at(size as usize);
+  Ok((rest, bytes))
+}
+
+pub(super) fn expect_byte<'a>(input: &'a [u8], expected: u8, cause: &str) -> DecodeResult<'a, ()> {
+  let (rest, byte) = decode_u8(input)?;
+  if byte != expected {
+    return decode_error(input, cause);
+  }
+  Ok((rest, ()))
+}
+
+pub(super) fn decode_vec<'a, T>(
+  input: &'a [u8],
+  decode_item: impl FnMut(&'a [u8]) -> DecodeResult<'a, T>,
+) -> DecodeResult<'a, Vec<T>> {
+  let (input, size) = decode_u32(input)?;
+  count(decode_item, size as usize)(input)
+}
+
+pub(super) fn decode_name(input: &[u8]) -> DecodeResult<'_, String> {
+  let (rest, size) = decode_u32(input)?;
+  let (rest, bytes) = decode_bytes(rest, size)?;
+  match std::str::from_utf8(bytes) {
+    Ok(name) => Ok((rest, name.to_string())),
+    Err(_) => decode_error_with_length(bytes, bytes.len(), "malformed UTF-8 encoding"),
+  }
+}
+
+pub(super) fn decode_value_type(input: &[u8]) -> DecodeResult<'_, ValueType> {
+  let (rest, byte) = decode_u8(input)?;
+  match ValueType::from_u8(byte) {
+    Some(value_type) => Ok((rest, value_type)),
+    None => decode_error(input, &format!("malformed value type 0x{:02x}", byte)),
+  }
+}
+
+fn decode_element_type(input: &[u8]) -> DecodeResult<'_, ElementType> {
+  let (rest, byte) = decode_u8(input)?;
+  match ElementType::from_u8(byte) {
+    Some(element_type) => Ok((rest, element_type)),
+    None => decode_error(input, &format!("malformed reference type 0x{:02x}", byte)),
+  }
+}
+
+pub(super) fn decode_func_type(input: &[u8]) -> DecodeResult<'_, FuncType> {
+  let (input, _) = expect_byte(input, 0x60, "malformed function type")?;
+  let (input, params) = decode_vec(input, decode_value_type)?;
+  let (input, results) = decode_vec(input, decode_value_type)?;
+  Ok((input, FuncType { params, results }))
+}
+
+fn decode_limits(input: &[u8]) -> DecodeResult<'_, Limits> {
+  let (rest, flag) = decode_u8(input)?;
+  match flag {
+    0x00 => {
+      let (rest, min) = decode_u32(rest)?;
+      Ok((rest, Limits { min, max: None }))
+    }
+    0x01 => {
+      let (rest, min) = decode_u32(rest)?;
+      let (rest, max) = decode_u32(rest)?;
+      Ok((rest, Limits { min, max: Some(max) }))
+    }
+    _ => decode_error(input, "malformed limits flags"),
+  }
+}
+
+fn decode_table_type(input: &[u8]) -> DecodeResult<'_, TableType> {
+  let (input, element_type) = decode_element_type(input)?;
+  let (input, limits) = decode_limits(input)?;
+  Ok((input, TableType { element_type, limits }))
+}
+
+fn decode_memory_type(input: &[u8]) -> DecodeResult<'_, MemoryType> {
+  let (input, limits) = decode_limits(input)?;
+  Ok((input, MemoryType { limits }))
+}
+
+fn decode_global_type(input: &[u8]) -> DecodeResult<'_, GlobalType> {
+  let (input, value_type) = decode_value_type(input)?;
+  let (rest, mutability) = decode_u8(input)?;
+  let mutable = match mutability {
+    0x00 => false,
+    0x01 => true,
+    _ => return decode_error(input, "malformed mutability"),
+  };
+  Ok((rest, GlobalType { value_type, mutable }))
+}
+
+fn decode_const_expr(input: &[u8]) -> DecodeResult<'_, ConstExpr> {
+  let (rest, opcode) = decode_u8(input)?;
+  let (rest, expr) = match opcode {
+    0x41 => decode_i32(rest).map(|(rest, value)| (rest, ConstExpr::I32Const(value)))?,
+    0x42 => decode_i64(rest).map(|(rest, value)| (rest, ConstExpr::I64Const(value)))?,
+    0x43 => decode_f32_bits(rest).map(|(rest, bits)| (rest, ConstExpr::F32Const(bits)))?,
+    0x44 => decode_f64_bits(rest).map(|(rest, bits)| (rest, ConstExpr::F64Const(bits)))?,
+    0x23 => decode_u32(rest).map(|(rest, index)| (rest, ConstExpr::GlobalGet(index)))?,
+    _ => return decode_error(input, "constant expression required"),
+  };
+  let (rest, _) = expect_byte(rest, 0x0b, "END opcode expected")?;
+  Ok((rest, expr))
+}
+
+fn decode_type_section(input: &[u8]) -> DecodeResult<'_, TypeSection> {
+  decode_vec(input, decode_func_type)
+}
+
+fn decode_import(input: &[u8]) -> DecodeResult<'_, Import> {
+  let (input, module) = decode_name(input)?;
+  let (input, name) = decode_name(input)?;
+  let (rest, kind) = decode_u8(input)?;
+  let (rest, desc) = match kind {
+    0x00 => decode_u32(rest).map(|(rest, type_idx)| (rest, ImportDesc::Func(type_idx)))?,
+    0x01 => decode_table_type(rest).map(|(rest, table_type)| (rest, ImportDesc::Table(table_type)))?,
+    0x02 => decode_memory_type(rest).map(|(rest, memory_type)| (rest, ImportDesc::Memory(memory_type)))?,
+    0x03 => decode_global_type(rest).map(|(rest, global_type)| (rest, ImportDesc::Global(global_type)))?,
+    _ => return decode_error(input, "malformed import kind"),
+  };
+  Ok((rest, Import { module, name, desc }))
+}
+
+fn decode_import_section(input: &[u8]) -> DecodeResult<'_, ImportSection> {
+  decode_vec(input, decode_import)
+}
+
+fn decode_function_section(input: &[u8]) -> DecodeResult<'_, FunctionSection> {
+  decode_vec(input, decode_u32)
+}
+
+fn decode_table_section(input: &[u8]) -> DecodeResult<'_, TableSection> {
+  decode_vec(input, decode_table_type)
+}
+
+fn decode_memory_section(input: &[u8]) -> DecodeResult<'_, MemorySection> {
+  decode_vec(input, decode_memory_type)
+}
+
+fn decode_global(input: &[u8]) -> DecodeResult<'_, Global> {
+  let (input, global_type) = decode_global_type(input)?;
+  let (input, init) = decode_const_expr(input)?;
+  Ok((input, Global { global_type, init }))
+}
+
+fn decode_global_section(input: &[u8]) -> DecodeResult<'_, GlobalSection> {
+  decode_vec(input, decode_global)
+}
+
+fn decode_export(input: &[u8]) -> DecodeResult<'_, Export> {
+  let (input, name) = decode_name(input)?;
+  let (rest, kind) = decode_u8(input)?;
+  let (rest, index) = decode_u32(rest)?;
+  let desc = match kind {
+    0x00 => ExportDesc::Func(index),
+    0x01 => ExportDesc::Table(index),
+    0x02 => ExportDesc::Memory(index),
+    0x03 => ExportDesc::Global(index),
+    _ => return decode_error(input, "malformed export kind"),
+  };
+  Ok((rest, Export { name, desc }))
+}
+
+fn decode_export_section(input: &[u8]) -> DecodeResult<'_, ExportSection> {
+  decode_vec(input, decode_export)
+}
+
+fn decode_start_section(input: &[u8]) -> DecodeResult<'_, u32> {
+  decode_u32(input)
+}
+
+fn decode_element(input: &[u8]) -> DecodeResult<'_, Element> {
+  let (input, table_idx) = decode_u32(input)?;
+  let (input, offset) = decode_const_expr(input)?;
+  let (input, init) = decode_vec(input, decode_u32)?;
+  Ok((input, Element { table_idx, offset, init }))
+}
+
+fn decode_element_section(input: &[u8]) -> DecodeResult<'_, ElementSection> {
+  decode_vec(input, decode_element)
+}
+
+fn decode_local(input: &[u8]) -> DecodeResult<'_, Local> {
+  let (input, count) = decode_u32(input)?;
+  let (input, value_type) = decode_value_type(input)?;
+  Ok((input, Local { count, value_type }))
+}
+
+// only records where the body is, see `decode_body`
+pub(super) fn decode_function_body<'a>(module: &[u8], input: &'a [u8]) -> DecodeResult<'a, FunctionBody> {
+  let (input, size) = decode_u32(input)?;
+  let (rest, body) = decode_bytes(input, size)?;
+  let start = offset_of(module, body);
+  Ok((rest, FunctionBody::new(Range::new(start, start + body.len()))))
+}
+
+pub(super) fn decode_body<'a>(module: &'a [u8], range: &Range) -> DecodeResult<'a, DecodedBody> {
+  let body = &module[range.start..range.end];
+  let (code, locals) = decode_vec(body, decode_local)?;
+  if locals.iter().map(|local| local.count as u64).sum::<u64>() > u32::MAX as u64 {
+    return decode_error_with_length(body, body.len() - code.len(), "too many locals");
+  }
+  let (rest, body) = decode_instructions(code, offset_of(module, code))?;
+  if body.last().map(|last| &last.instruction) != Some(&Instruction::End) {
+    return decode_error_with_length(rest, 0, "END opcode expected");
+  }
+  Ok((rest, DecodedBody { locals, body }))
+}
+
+fn decode_code_section<'a>(module: &[u8], input: &'a [u8]) -> DecodeResult<'a, CodeSection> {
+  decode_vec(input, |input| decode_function_body(module, input))
+}
 
-fn decode_type_section(_input: &[u8]) -> IResult<&[u8], TypeSection> {
-  let func_types = vec![FuncType::default()];
-  Ok((&[], func_types))
+fn decode_data(input: &[u8]) -> DecodeResult<'_, Data> {
+  let (input, memory_idx) = decode_u32(input)?;
+  let (input, offset) = decode_const_expr(input)?;
+  let (input, size) = decode_u32(input)?;
+  let (input, init) = decode_bytes(input, size)?;
+  Ok((input, Data { memory_idx, offset, init: init.to_vec() }))
 }
+
+fn decode_data_section(input: &[u8]) -> DecodeResult<'_, DataSection> {
+  decode_vec(input, decode_data)
+}
+
+fn decode_custom_section(input: &[u8]) -> DecodeResult<'_, (String, Vec<u8>)> {
+  let (data, name) = decode_name(input)?;
+  Ok((&data[data.len()..], (name, data.to_vec())))
+}
+
+pub(super) fn decode_section_header(input: &[u8]) -> DecodeResult<'_, (SectionCode, u32)> {
+  let (rest, code) = decode_u8(input)?;
+  let Some(section_code) = SectionCode::from_u8(code) else {
+    return decode_error(input, "malformed section id");
+  };
+  let (rest, size) = decode_u32(rest)?;
+  Ok((rest, (section_code, size)))
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Module {
   pub magic: String,
   pub version: u32,
+  pub bytes: RawBytes,
   pub type_section: Option<TypeSection>,
+  pub import_section: Option<ImportSection>,
+  pub function_section: Option<FunctionSection>,
+  pub table_section: Option<TableSection>,
+  pub memory_section: Option<MemorySection>,
+  pub global_section: Option<GlobalSection>,
+  pub export_section: Option<ExportSection>,
+  pub start_section: Option<u32>,
+  pub element_section: Option<ElementSection>,
+  pub code_section: Option<CodeSection>,
+  pub data_section: Option<DataSection>,
+  pub custom_sections: Vec<CustomSection>,
+  pub names: Option<NameSection>,
 }
 // https://webassembly.github.io/spec/core/binary/modules.html#binary-module
 impl Default for Module {
   fn default() -> Self {
-    Self { magic: "\0asm".to_string(), version: 1, type_section: None }
+    Self {
+      magic: "\0asm".to_string(),
+      version: 1,
+      bytes: RawBytes::default(),
+      type_section: None,
+      import_section: None,
+      function_section: None,
+      table_section: None,
+      memory_section: None,
+      global_section: None,
+      export_section: None,
+      start_section: None,
+      element_section: None,
+      code_section: None,
+      data_section: None,
+      custom_sections: vec![],
+      names: None,
+    }
   }
 }
 
 impl Module {
+  pub fn function_name(&self, func_idx: u32) -> Option<&str> {
+    self.names.as_ref()?.function_name(func_idx)
+  }
+
+  // `$factorial` when the name section knows the function, `func[0]` otherwise
+  pub fn display_function(&self, func_idx: u32) -> String {
+    match self.function_name(func_idx) {
+      Some(name) => format!("${}", name),
+      None => format!("func[{}]", func_idx),
+    }
+  }
+
   pub fn new(input: &[u8]) -> ResultWithDiagnostics<Module> {
-    let (_, module) = Module::decode(input).map_err(|error| {
-      let cause = format!("{}", error);
-      let runtime_error = RuntimeError::FailedToDecodeModule { range: None, cause };
-      return runtime_error;
-    })?;
+    let (_, mut module) = Module::decode(input).map_err(|error| decode_failure(input, error))?;
+    module.bytes = RawBytes::from(input);
     Ok(module)
   }
 
-  fn decode(input: &[u8]) -> IResult<&[u8], Module> {
-    let (input, _) = tag(b"\0asm")(input)?;
-    let (input, version) = le_u32(input)?;
-    let module = Module { magic: "\0asm".into(), version, ..Default::default() };
-    Ok((input, module))
+  // decodes the body of a defined function on first use, `index` is its position in the code section
+  pub fn function_body(&self, index: usize) -> ResultWithDiagnostics<&DecodedBody> {
+    let Some(body) = self.code_section.as_ref().and_then(|bodies| bodies.get(index)) else {
+      let name = format!("func[{}]", index);
+      return Err(RuntimeError::UnknownFunction { name, range: None }.into());
+    };
+    body.decode(self.bytes.as_slice())
   }
 
-  fn decode_section_header(&self, input: &[u8]) -> IResult<&[u8], (SectionCode, u32)> {
-    let (input, (code, size)) = pair(le_u8, leb128_u32)(input)?;
-    let section_code = SectionCode::from_u8(code);
+  pub fn function_body_mut(&mut self, index: usize) -> ResultWithDiagnostics<&mut DecodedBody> {
+    let Some(body) = self.code_section.as_mut().and_then(|bodies| bodies.get_mut(index)) else {
+      let name = format!("func[{}]", index);
+      return Err(RuntimeError::UnknownFunction { name, range: None }.into());
+    };
+    body.decode_mut(self.bytes.as_slice())
+  }
 
-    if section_code.is_none() {
-      return Err(nom::Err::Error(nom::error::make_error(
-        input,
-        nom::error::ErrorKind::Tag,
-      )));
+  // forces every lazily decoded function body, reporting the first malformed one
+  pub fn decode_function_bodies(&self) -> ResultWithDiagnostics<()> {
+    for body in self.code_section.iter().flatten() {
+      body.decode(self.bytes.as_slice())?;
     }
-    let mut remaining = input;
+    Ok(())
+  }
+
+  fn decode(bytes: &[u8]) -> DecodeResult<'_, Module> {
+    let (input, version) = decode_module_header(bytes)?;
+    let mut module = Module { magic: "\0asm".into(), version, ..Default::default() };
 
+    let mut seen_sections: Vec<SectionCode> = vec![];
+    let mut remaining = input;
     while !remaining.is_empty() {
-      match self.decode_section_header(remaining) {
-        Ok((input, (code, size))) => {
-          let (rest, section_contents) = take(size)(input)?;
-
-          match code {
-            SectionCode::Type => {
-              let (rest, types) = decode_type_section(section_contents)?;
-            }
-            _ => todo!(),
-          };
-          remaining = rest;
+      let (input, (code, size)) = decode_section_header(remaining)?;
+      let header = &remaining[..remaining.len() - input.len()];
+      let after = seen_sections.last().copied();
+      check_section_order(&mut seen_sections, code, header)?;
+
+      if size as usize > input.len() {
+        let cause = format!(
+          "section size mismatch, declared {} bytes but only {} remain",
+          size,
+          input.len()
+        );
+        return decode_error_with_length(header, header.len(), &cause);
+      }
+      let (section_contents, rest) = input.split_at(size as usize);
+      module.decode_section(bytes, code, section_contents, header, after)?;
+      remaining = rest;
+    }
+    module.check_function_count(remaining)?;
+    Ok((remaining, module))
+  }
+
+  // decodes the whole payload of a section into the module, `bytes` are the module bytes
+  pub(super) fn decode_section<'a>(
+    &mut self,
+    bytes: &[u8],
+    code: SectionCode,
+    contents: &'a [u8],
+    header: &'a [u8],
+    after: Option<SectionCode>,
+  ) -> DecodeResult<'a, ()> {
+    match self.decode_section_contents(bytes, code, contents, header, after) {
+      Err(nom::Err::Error(DecodeError::Incomplete { .. })) => Err(section_too_small(header, contents)),
+      result => result,
+    }
+  }
+
+  fn decode_section_contents<'a>(
+    &mut self,
+    bytes: &[u8],
+    code: SectionCode,
+    contents: &'a [u8],
+    header: &'a [u8],
+    after: Option<SectionCode>,
+  ) -> DecodeResult<'a, ()> {
+    let leftover = match code {
+      SectionCode::Type => {
+        let (leftover, types) = decode_type_section(contents)?;
+        self.type_section = Some(types);
+        leftover
+      }
+      SectionCode::Import => {
+        let (leftover, imports) = decode_import_section(contents)?;
+        self.import_section = Some(imports);
+        leftover
+      }
+      SectionCode::Function => {
+        let (leftover, functions) = decode_function_section(contents)?;
+        self.function_section = Some(functions);
+        leftover
+      }
+      SectionCode::Table => {
+        let (leftover, tables) = decode_table_section(contents)?;
+        self.table_section = Some(tables);
+        leftover
+      }
+      SectionCode::Memory => {
+        let (leftover, memories) = decode_memory_section(contents)?;
+        self.memory_section = Some(memories);
+        leftover
+      }
+      SectionCode::Global => {
+        let (leftover, globals) = decode_global_section(contents)?;
+        self.global_section = Some(globals);
+        leftover
+      }
+      SectionCode::Export => {
+        let (leftover, exports) = decode_export_section(contents)?;
+        self.export_section = Some(exports);
+        leftover
+      }
+      SectionCode::Start => {
+        let (leftover, func_idx) = decode_start_section(contents)?;
+        self.start_section = Some(func_idx);
+        leftover
+      }
+      SectionCode::Element => {
+        let (leftover, elements) = decode_element_section(contents)?;
+        self.element_section = Some(elements);
+        leftover
+      }
+      SectionCode::Code => {
+        let (leftover, bodies) = decode_code_section(bytes, contents)?;
+        self.check_code_count(bodies.len() as u32, header)?;
+        self.code_section = Some(bodies);
+        leftover
+      }
+      SectionCode::Data => {
+        let (leftover, data) = decode_data_section(contents)?;
+        self.data_section = Some(data);
+        leftover
+      }
+      SectionCode::Custom => {
+        let (leftover, (name, data)) = decode_custom_section(contents)?;
+        // a malformed name section must not invalidate the module, it is kept only as raw bytes
+        if name == "name" {
+          self.names = decode_name_section(&data).ok().map(|(_, names)| names);
         }
-        Err(err) => return Err(err),
+        self.custom_sections.push(CustomSection { name, data, after });
+        leftover
       }
+    };
+    if !leftover.is_empty() {
+      return decode_error_with_length(leftover, leftover.len(), "section size mismatch");
     }
-    Ok((input, section))
+    Ok((leftover, ()))
+  }
+
+  pub(super) fn check_code_count<'a>(&self, bodies: u32, header: &'a [u8]) -> DecodeResult<'a, ()> {
+    if bodies as usize != self.function_section.as_ref().map_or(0, |functions| functions.len()) {
+      let cause = "function and code section have inconsistent lengths";
+      return decode_error_with_length(header, header.len(), cause);
+    }
+    Ok((header, ()))
+  }
+
+  // a function section without its code section, `end` is the end of the module
+  pub(super) fn check_function_count<'a>(&self, end: &'a [u8]) -> DecodeResult<'a, ()> {
+    let functions = self.function_section.as_ref().map_or(0, |functions| functions.len());
+    if functions > 0 && self.code_section.is_none() {
+      return decode_error_with_length(end, 0, "function and code section have inconsistent lengths");
+    }
+    Ok((end, ()))
+  }
+}
+
+// the contents run past the declared size, reported over the whole section like a too large size
+pub(super) fn section_too_small<'a>(header: &'a [u8], contents: &'a [u8]) -> nom::Err<DecodeError<'a>> {
+  let cause = format!(
+    "section size mismatch, declared {} bytes but the contents need more",
+    contents.len()
+  );
+  nom::Err::Error(DecodeError::new(header, header.len() + contents.len(), &cause))
+}
+
+pub(super) fn decode_failure(bytes: &[u8], error: nom::Err<DecodeError>) -> RuntimeError {
+  let (nom::Err::Error(error) | nom::Err::Failure(error)) = error else {
+    return RuntimeError::FailedToDecodeModule { range: None, cause: UNEXPECTED_END.into() };
+  };
+  match error {
+    DecodeError::Incomplete { input } => {
+      let start = offset_of(bytes, input);
+      let range = Range::new(start, start + input.len());
+      RuntimeError::FailedToDecodeModule { range: Some(range), cause: UNEXPECTED_END.into() }
+    }
+    DecodeError::Malformed { input, length, cause } => {
+      let start = offset_of(bytes, input);
+      RuntimeError::FailedToDecodeModule { range: Some(Range::new(start, start + length)), cause }
+    }
+  }
+}
+
+pub(super) fn decode_module_header(bytes: &[u8]) -> DecodeResult<'_, u32> {
+  if !bytes.starts_with(b"\0asm") {
+    return decode_error_with_length(bytes, bytes.len().min(4), "magic header not detected");
+  }
+  let (input, version) = le_u32(&bytes[4..])?;
+  if version != 1 {
+    return decode_error_with_length(&bytes[4..], 4, "unknown binary version");
+  }
+  Ok((input, version))
+}
+
+// non-custom sections must appear at most once and in increasing id order
+pub(super) fn check_section_order<'a>(
+  seen_sections: &mut Vec<SectionCode>,
+  code: SectionCode,
+  header: &'a [u8],
+) -> DecodeResult<'a, ()> {
+  if code == SectionCode::Custom {
+    return Ok((header, ()));
+  }
+  if seen_sections.contains(&code) {
+    let cause = format!("duplicate {:?} section", code);
+    return decode_error_with_length(header, header.len(), &cause);
+  }
+  if seen_sections.last().is_some_and(|last| (*last as u8) > (code as u8)) {
+    let cause = format!("unexpected {:?} section, sections are out of order", code);
+    return decode_error_with_length(header, header.len(), &cause);
+  }
+  seen_sections.push(code);
+  Ok((header, ()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TYPE_SECTION: &[u8] = &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+  const FUNCTION_SECTION: &[u8] = &[0x03, 0x02, 0x01, 0x00];
+
+  fn module(sections: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+    sections.iter().for_each(|section| bytes.extend_from_slice(section));
+    bytes
+  }
+
+  fn decode_error(bytes: &[u8]) -> (String, Option<Range>) {
+    let diagnostic = Module::new(bytes).unwrap_err();
+    (diagnostic.message, diagnostic.range)
+  }
+
+  #[test]
+  fn duplicate_section() {
+    let (message, range) = decode_error(&module(&[TYPE_SECTION, TYPE_SECTION]));
+    assert_eq!(message, "failed to decode module: duplicate Type section");
+    assert_eq!(range, Some(Range::new(14, 16)));
+  }
+
+  #[test]
+  fn out_of_order_section() {
+    let (message, range) = decode_error(&module(&[FUNCTION_SECTION, TYPE_SECTION]));
+    assert_eq!(
+      message,
+      "failed to decode module: unexpected Type section, sections are out of order"
+    );
+    assert_eq!(range, Some(Range::new(12, 14)));
+  }
+
+  #[test]
+  fn custom_sections_are_allowed_anywhere() {
+    let custom: &[u8] = &[0x00, 0x03, 0x02, b'h', b'i'];
+    let module = Module::new(&module(&[custom, TYPE_SECTION, custom])).unwrap();
+    assert_eq!(module.custom_sections.len(), 2);
+    assert_eq!(module.custom_sections[1].after, Some(SectionCode::Type));
+  }
+
+  #[test]
+  fn section_larger_than_input() {
+    let (message, range) = decode_error(&module(&[&[0x01, 0x05, 0x00]]));
+    assert_eq!(
+      message,
+      "failed to decode module: section size mismatch, declared 5 bytes but only 1 remain"
+    );
+    assert_eq!(range, Some(Range::new(8, 10)));
+  }
+
+  #[test]
+  fn section_smaller_than_contents() {
+    let (message, range) = decode_error(&module(&[&[0x01, 0x02, 0x01, 0x60, 0x00, 0x00]]));
+    assert_eq!(
+      message,
+      "failed to decode module: section size mismatch, declared 2 bytes but the contents need more"
+    );
+    assert_eq!(range, Some(Range::new(8, 12)));
+  }
+
+  #[test]
+  fn section_with_leftover_bytes() {
+    let (message, range) = decode_error(&module(&[&[0x01, 0x05, 0x01, 0x60, 0x00, 0x00, 0xff]]));
+    assert_eq!(message, "failed to decode module: section size mismatch");
+    assert_eq!(range, Some(Range::new(14, 15)));
+  }
+
+  #[test]
+  fn truncated_input() {
+    let (message, _) = decode_error(b"\0asm\x01\0\0");
+    assert_eq!(message, "failed to decode module: unexpected end");
+    let (message, range) = decode_error(&module(&[&[0x01]]));
+    assert_eq!(message, "failed to decode module: unexpected end");
+    assert_eq!(range, Some(Range::new(9, 9)));
+  }
+
+  #[test]
+  fn function_without_code_section() {
+    let bytes = module(&[TYPE_SECTION, FUNCTION_SECTION]);
+    let (message, range) = decode_error(&bytes);
+    assert_eq!(
+      message,
+      "failed to decode module: function and code section have inconsistent lengths"
+    );
+    assert_eq!(range, Some(Range::new(bytes.len(), bytes.len())));
+  }
+
+  #[test]
+  fn function_and_code_counts_differ() {
+    let (message, range) = decode_error(&module(&[TYPE_SECTION, FUNCTION_SECTION, &[0x0a, 0x01, 0x00]]));
+    assert_eq!(
+      message,
+      "failed to decode module: function and code section have inconsistent lengths"
+    );
+    assert_eq!(range, Some(Range::new(18, 20)));
+  }
+
+  #[test]
+  fn function_bodies_are_decoded_on_demand() {
+    let text = "(module (func) (func (result i32) i32.const 1) (func (param i64) local.get 0 drop))";
+    let module = Module::new(&wat::parse_str(text).unwrap()).unwrap();
+    let bodies = module.code_section.as_ref().unwrap();
+    assert!(bodies.iter().all(|body| body.decoded().is_none()));
+
+    let body = module.function_body(1).unwrap();
+    assert_eq!(body.body.len(), 2);
+    assert!(bodies[1].decoded().is_some());
+    assert!(bodies[0].decoded().is_none() && bodies[2].decoded().is_none());
+    // the cached body is returned from then on
+    assert!(std::ptr::eq(body, module.function_body(1).unwrap()));
   }
 }
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+use super::{
+  error::DecodeResult,
+  module::{decode_bytes, decode_name, decode_u32, decode_u8, decode_vec},
+};
+
+type NameMap = BTreeMap<u32, String>;
+
+// https://webassembly.github.io/spec/core/appendix/custom.html#name-section
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NameSection {
+  pub module: Option<String>,
+  pub functions: NameMap,
+  pub locals: BTreeMap<u32, NameMap>, // function index -> local names
+}
+
+impl NameSection {
+  pub fn function_name(&self, func_idx: u32) -> Option<&str> {
+    self.functions.get(&func_idx).map(String::as_str)
+  }
+
+  pub fn local_name(&self, func_idx: u32, local_idx: u32) -> Option<&str> {
+    self.locals.get(&func_idx)?.get(&local_idx).map(String::as_str)
+  }
+}
+
+fn decode_name_assoc(input: &[u8]) -> DecodeResult<'_, (u32, String)> {
+  let (input, index) = decode_u32(input)?;
+  let (input, name) = decode_name(input)?;
+  Ok((input, (index, name)))
+}
+
+fn decode_name_map(input: &[u8]) -> DecodeResult<'_, NameMap> {
+  let (input, names) = decode_vec(input, decode_name_assoc)?;
+  Ok((input, names.into_iter().collect()))
+}
+
+fn decode_indirect_name_assoc(input: &[u8]) -> DecodeResult<'_, (u32, NameMap)> {
+  let (input, index) = decode_u32(input)?;
+  let (input, names) = decode_name_map(input)?;
+  Ok((input, (index, names)))
+}
+
+pub fn decode_name_section(input: &[u8]) -> DecodeResult<'_, NameSection> {
+  let mut names = NameSection::default();
+  let mut remaining = input;
+  while !remaining.is_empty() {
+    let (rest, id) = decode_u8(remaining)?;
+    let (rest, size) = decode_u32(rest)?;
+    let (rest, contents) = decode_bytes(rest, size)?;
+    match id {
+      0x00 => names.module = Some(decode_name(contents)?.1),
+      0x01 => names.functions = decode_name_map(contents)?.1,
+      0x02 => names.locals = decode_vec(contents, decode_indirect_name_assoc)?.1.into_iter().collect(),
+      _ => {} // subsections from later proposals
+    }
+    remaining = rest;
+  }
+  Ok((remaining, names))
+}
@@ -0,0 +1,312 @@
+use crate::{
+  diagnostics::{ResultWithDiagnostics, RuntimeError},
+  utils::range::Range,
+};
+
+use super::{
+  error::{DecodeError, DecodeResult, UNEXPECTED_END},
+  module::{
+    check_section_order, decode_failure, decode_func_type, decode_function_body, decode_module_header,
+    decode_section_header, decode_u32, offset_of, section_too_small, Module,
+  },
+  section::SectionCode,
+  types::{FuncType, FunctionBody, ImportDesc, RawBytes},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecoderEvent {
+  Header { version: u32 },
+  Section { code: SectionCode, range: Range }, // emitted as soon as the section header is read
+  TypeEntry { type_idx: u32, func_type: FuncType },
+  FunctionBody { func_idx: u32, body: FunctionBody },
+}
+
+enum State {
+  Header,
+  SectionHeader,
+  Section {
+    code: SectionCode,
+    header: Range,
+    end: usize,
+    after: Option<SectionCode>,
+  },
+  // the entry count of a type or code section
+  Count {
+    code: SectionCode,
+    header: Range,
+    end: usize,
+  },
+  // type and code section entries are handled one by one, as soon as their bytes arrive
+  Entries {
+    code: SectionCode,
+    header: Range,
+    end: usize,
+    remaining: u32,
+    index: u32, // type index or function index of the next entry
+  },
+}
+
+// push-style decoder, bytes can arrive in chunks of any size
+pub struct StreamingDecoder {
+  buffer: Vec<u8>,
+  position: usize,
+  state: State,
+  module: Module,
+  seen_sections: Vec<SectionCode>,
+}
+
+// `Ok(None)` when the input ended before the construct did, so the decoder can wait for more bytes,
+// unless `section` (its header and contents) is already complete, then its declared size is too small
+fn wait_for_more<'a, T>(
+  result: DecodeResult<'a, T>,
+  section: Option<(&'a [u8], &'a [u8])>,
+) -> Result<Option<(&'a [u8], T)>, nom::Err<DecodeError<'a>>> {
+  match result {
+    Ok(value) => Ok(Some(value)),
+    Err(nom::Err::Error(DecodeError::Incomplete { .. })) => match section {
+      Some((header, contents)) => Err(section_too_small(header, contents)),
+      None => Ok(None),
+    },
+    Err(error) => Err(error),
+  }
+}
+
+impl StreamingDecoder {
+  pub fn new() -> Self {
+    Self { buffer: vec![], position: 0, state: State::Header, module: Module::default(), seen_sections: vec![] }
+  }
+
+  pub fn bytes(&self) -> &[u8] {
+    &self.buffer
+  }
+
+  pub fn push(&mut self, chunk: &[u8]) -> ResultWithDiagnostics<Vec<DecoderEvent>> {
+    self.buffer.extend_from_slice(chunk);
+    let mut events = vec![];
+    while let Some(mut step_events) = self.step()? {
+      events.append(&mut step_events);
+    }
+    Ok(events)
+  }
+
+  // how many more bytes are required before the current section or function body is complete,
+  // zero means the bytes received so far end exactly at a section boundary
+  pub fn needed(&self) -> usize {
+    let available = self.buffer.len();
+    match self.state {
+      State::Header => 8usize.saturating_sub(available),
+      State::SectionHeader => usize::from(self.position < available),
+      State::Section { end, .. } => end.saturating_sub(available),
+      State::Count { .. } => 1,
+      State::Entries { code: SectionCode::Code, end, remaining: 1.., .. } => {
+        match decode_u32(&self.buffer[self.position..end.min(available)]) {
+          Ok((body, size)) => (offset_of(&self.buffer, body) + size as usize).min(end).saturating_sub(available).max(1),
+          Err(_) => 1,
+        }
+      }
+      State::Entries { end, .. } => end.saturating_sub(available),
+    }
+  }
+
+  pub fn finish(self) -> ResultWithDiagnostics<Module> {
+    let bytes = &self.buffer[..];
+    let at_boundary = matches!(self.state, State::SectionHeader) && self.position == bytes.len();
+    if !at_boundary {
+      let range = Range::new(bytes.len(), bytes.len());
+      return Err(RuntimeError::FailedToDecodeModule { range: Some(range), cause: UNEXPECTED_END.into() }.into());
+    }
+    self.module.check_function_count(&bytes[bytes.len()..]).map_err(|error| decode_failure(bytes, error))?;
+    let mut module = self.module;
+    module.bytes = RawBytes::from(self.buffer);
+    Ok(module)
+  }
+
+  fn step(&mut self) -> ResultWithDiagnostics<Option<Vec<DecoderEvent>>> {
+    let bytes = &self.buffer[..];
+    let input = &bytes[self.position..];
+    let fail = |error| decode_failure(bytes, error);
+    match self.state {
+      State::Header => {
+        if bytes.len() < 8 {
+          return Ok(None);
+        }
+        let (_, version) = decode_module_header(bytes).map_err(fail)?;
+        self.module.version = version;
+        self.position = 8;
+        self.state = State::SectionHeader;
+        Ok(Some(vec![DecoderEvent::Header { version }]))
+      }
+      State::SectionHeader => {
+        if input.is_empty() {
+          return Ok(None);
+        }
+        let Some((rest, (code, size))) = wait_for_more(decode_section_header(input), None).map_err(fail)? else {
+          return Ok(None);
+        };
+        let header = &input[..input.len() - rest.len()];
+        let after = self.seen_sections.last().copied();
+        check_section_order(&mut self.seen_sections, code, header).map_err(fail)?;
+
+        let start = offset_of(bytes, rest);
+        let end = start + size as usize;
+        let header = Range::new(self.position, start);
+        self.position = start;
+        self.state = match code {
+          SectionCode::Type | SectionCode::Code => State::Count { code, header, end },
+          _ => State::Section { code, header, end, after },
+        };
+        Ok(Some(vec![DecoderEvent::Section {
+          code,
+          range: Range::new(start, end),
+        }]))
+      }
+      State::Section { code, ref header, end, after } => {
+        if bytes.len() < end {
+          return Ok(None);
+        }
+        let header = &bytes[header.start..header.end];
+        self.module.decode_section(bytes, code, &bytes[self.position..end], header, after).map_err(fail)?;
+        self.position = end;
+        self.state = State::SectionHeader;
+        Ok(Some(vec![]))
+      }
+      State::Count { code, ref header, end } => {
+        let section = (bytes.len() >= end).then(|| (&bytes[header.start..header.end], &bytes[header.end..end]));
+        let available = &bytes[self.position..end.min(bytes.len())];
+        let Some((rest, count)) = wait_for_more(decode_u32(available), section).map_err(fail)? else {
+          return Ok(None);
+        };
+        let index = match code {
+          SectionCode::Code => {
+            self.module.check_code_count(count, &bytes[header.start..header.end]).map_err(fail)?;
+            self.module.code_section = Some(vec![]);
+            let imports = self.module.import_section.iter().flatten();
+            imports.filter(|import| matches!(import.desc, ImportDesc::Func(_))).count() as u32
+          }
+          _ => {
+            self.module.type_section = Some(vec![]);
+            0
+          }
+        };
+        self.position = offset_of(bytes, rest);
+        self.state = State::Entries { code, header: header.clone(), end, remaining: count, index };
+        Ok(Some(vec![]))
+      }
+      State::Entries { end, remaining: 0, .. } => {
+        if self.position == end {
+          self.state = State::SectionHeader;
+          return Ok(Some(vec![]));
+        }
+        if bytes.len() < end {
+          return Ok(None);
+        }
+        let leftover = &bytes[self.position..end];
+        Err(
+          fail(nom::Err::Error(DecodeError::new(
+            leftover,
+            leftover.len(),
+            "section size mismatch",
+          )))
+          .into(),
+        )
+      }
+      State::Entries { code, ref header, end, remaining, index } => {
+        let section = (bytes.len() >= end).then(|| (&bytes[header.start..header.end], &bytes[header.end..end]));
+        let available = &bytes[self.position..end.min(bytes.len())];
+        let (rest, event) = if code == SectionCode::Code {
+          // only the range of the body is recorded, it is decoded when first needed
+          let Some((rest, body)) = wait_for_more(decode_function_body(bytes, available), section).map_err(fail)? else {
+            return Ok(None);
+          };
+          self.module.code_section.get_or_insert_with(Vec::new).push(body.clone());
+          (rest, DecoderEvent::FunctionBody { func_idx: index, body })
+        } else {
+          let Some((rest, func_type)) = wait_for_more(decode_func_type(available), section).map_err(fail)? else {
+            return Ok(None);
+          };
+          self.module.type_section.get_or_insert_with(Vec::new).push(func_type.clone());
+          (rest, DecoderEvent::TypeEntry { type_idx: index, func_type })
+        };
+        self.position = offset_of(bytes, rest);
+        self.state = State::Entries { code, header: header.clone(), end, remaining: remaining - 1, index: index + 1 };
+        Ok(Some(vec![event]))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn stream(bytes: &[u8], chunk_size: usize) -> (Vec<(usize, DecoderEvent)>, Module) {
+    let mut decoder = StreamingDecoder::new();
+    let mut events = vec![];
+    for chunk in bytes.chunks(chunk_size) {
+      let received = decoder.bytes().len() + chunk.len();
+      events.extend(decoder.push(chunk).unwrap().into_iter().map(|event| (received, event)));
+    }
+    assert_eq!(decoder.needed(), 0);
+    (events, decoder.finish().unwrap())
+  }
+
+  #[test]
+  fn byte_by_byte_matches_module_new() {
+    for file in ["add", "factorial", "use_external_add"] {
+      let bytes = wat::parse_file(format!("tests/playground/{}.wat", file)).unwrap();
+      let (_, module) = stream(&bytes, 1);
+      assert_eq!(module, Module::new(&bytes).unwrap(), "{}", file);
+    }
+  }
+
+  #[test]
+  fn entries_are_emitted_as_soon_as_they_are_complete() {
+    let bytes = wat::parse_str("(module (type (func)) (type (func (param i32))) (func) (func (type 1)))").unwrap();
+    let (events, module) = stream(&bytes, 1);
+    let section_end = |code| {
+      events.iter().find_map(|(_, event)| match event {
+        DecoderEvent::Section { code: found, range } if *found == code => Some(range.end),
+        _ => None,
+      })
+    };
+    let types = events.iter().filter(|(_, event)| matches!(event, DecoderEvent::TypeEntry { .. }));
+    let received: Vec<usize> = types.map(|(received, _)| *received).collect();
+    // `60 00 00` then `60 01 7f 00`, right after the section header and count
+    assert_eq!(received, vec![14, 18]);
+    assert_eq!(section_end(SectionCode::Type), Some(18));
+
+    let bodies = events.iter().filter_map(|(received, event)| match event {
+      DecoderEvent::FunctionBody { func_idx, body } => Some((*received, *func_idx, body)),
+      _ => None,
+    });
+    let bodies: Vec<_> = bodies.collect();
+    assert_eq!(bodies.len(), 2);
+    assert!(bodies[0].0 < section_end(SectionCode::Code).unwrap());
+    assert!(bodies.iter().all(|(_, _, body)| body.decoded().is_none()));
+    assert!(module.code_section.iter().flatten().all(|body| body.decoded().is_none()));
+  }
+
+  #[test]
+  fn section_smaller_than_entries() {
+    let mut decoder = StreamingDecoder::new();
+    let bytes = b"\0asm\x01\0\0\0\x01\x02\x01\x60\x00\x00";
+    let diagnostic = bytes.chunks(1).find_map(|chunk| decoder.push(chunk).err()).unwrap();
+    assert_eq!(
+      diagnostic.message,
+      "failed to decode module: section size mismatch, declared 2 bytes but the contents need more"
+    );
+    assert_eq!(diagnostic.range, Some(Range::new(8, 12)));
+  }
+
+  #[test]
+  fn truncated_stream() {
+    let bytes = wat::parse_file("tests/playground/add.wat").unwrap();
+    let mut decoder = StreamingDecoder::new();
+    decoder.push(&bytes[..bytes.len() - 1]).unwrap();
+    assert_eq!(decoder.needed(), 1);
+    assert_eq!(
+      decoder.finish().unwrap_err().message,
+      "failed to decode module: unexpected end"
+    );
+  }
+}
@@ -1,21 +1,293 @@
+use std::{cell::OnceCell, rc::Rc};
+
+use num_derive::FromPrimitive;
+
+use crate::{diagnostics::ResultWithDiagnostics, utils::range::Range};
+
+use super::{
+  instructions::DecodedInstruction,
+  module::{decode_body, decode_failure},
+  section::SectionCode,
+};
+
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct FuncType {
   pub params: Vec<ValueType>,
   pub results: Vec<ValueType>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+// `[i32] -> [i32]`
+impl std::fmt::Display for FuncType {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{} -> {}",
+      format_value_types(&self.params),
+      format_value_types(&self.results)
+    )
+  }
+}
+
+// `[i32 i64]`
+pub fn format_value_types(types: &[ValueType]) -> String {
+  let names: Vec<String> = types.iter().map(ValueType::to_string).collect();
+  format!("[{}]", names.join(" "))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
 pub enum ValueType {
-  I32, // 0x7F
-  I64, // 0x7E
+  I32 = 0x7F,
+  I64 = 0x7E,
+  F32 = 0x7D,
+  F64 = 0x7C,
+  V128 = 0x7B,
+  FuncRef = 0x70,
+  ExternRef = 0x6F,
+}
+
+impl std::fmt::Display for ValueType {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let name = match self {
+      ValueType::I32 => "i32",
+      ValueType::I64 => "i64",
+      ValueType::F32 => "f32",
+      ValueType::F64 => "f64",
+      ValueType::V128 => "v128",
+      ValueType::FuncRef => "funcref",
+      ValueType::ExternRef => "externref",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+// the reference types a table can hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum ElementType {
+  FuncRef = 0x70,
+  ExternRef = 0x6F,
+}
+
+impl From<ElementType> for ValueType {
+  fn from(element_type: ElementType) -> Self {
+    match element_type {
+      ElementType::FuncRef => Self::FuncRef,
+      ElementType::ExternRef => Self::ExternRef,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+  pub min: u32,
+  pub max: Option<u32>,
+}
+
+impl Limits {
+  // https://webassembly.github.io/spec/core/valid/types.html#limits, an import can be larger and stricter
+  pub fn matches(&self, expected: &Limits) -> bool {
+    let max_matches = match (self.max, expected.max) {
+      (_, None) => true,
+      (Some(max), Some(expected_max)) => max <= expected_max,
+      (None, Some(_)) => false,
+    };
+    self.min >= expected.min && max_matches
+  }
+}
+
+// `1` or `1 2`, like in the text format
+impl std::fmt::Display for Limits {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self.max {
+      Some(max) => write!(f, "{} {}", self.min, max),
+      None => write!(f, "{}", self.min),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableType {
+  pub element_type: ElementType,
+  pub limits: Limits,
+}
+
+impl std::fmt::Display for TableType {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "(table {} {})", self.limits, ValueType::from(self.element_type))
+  }
+}
+
+pub const PAGE_SIZE: usize = 65536;
+// 4GiB of 64KiB pages
+pub const MAX_PAGES: u32 = 65536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryType {
+  pub limits: Limits,
+}
+
+impl std::fmt::Display for MemoryType {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "(memory {})", self.limits)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalType {
+  pub value_type: ValueType,
+  pub mutable: bool,
+}
+
+// `i32` or `(mut i32)`
+impl std::fmt::Display for GlobalType {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self.mutable {
+      true => write!(f, "(mut {})", self.value_type),
+      false => write!(f, "{}", self.value_type),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+  pub module: String,
+  pub name: String,
+  pub desc: ImportDesc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportDesc {
+  Func(u32), // type index
+  Table(TableType),
+  Memory(MemoryType),
+  Global(GlobalType),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+  pub name: String,
+  pub desc: ExportDesc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportDesc {
+  Func(u32),
+  Table(u32),
+  Memory(u32),
+  Global(u32),
+}
+
+// constant expressions allowed by the MVP, without the trailing `end`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstExpr {
+  I32Const(i32),
+  I64Const(i64),
+  F32Const(u32), // raw bits
+  F64Const(u64), // raw bits
+  GlobalGet(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Global {
+  pub global_type: GlobalType,
+  pub init: ConstExpr,
 }
 
-impl From<u8> for ValueType {
-  fn from(value: u8) -> Self {
-    match value {
-      0x7F => Self::I32,
-      0x7E => Self::I64,
-      _ => panic!("invalid value type: {:X}", value),
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+  pub table_idx: u32,
+  pub offset: ConstExpr,
+  pub init: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Local {
+  pub count: u32,
+  pub value_type: ValueType,
+}
+
+// a code section entry, its locals and instructions are only decoded the first time they are needed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionBody {
+  pub range: Option<Range>, // locals and instructions in the module bytes, without the size prefix
+  decoded: OnceCell<DecodedBody>,
+}
+
+impl FunctionBody {
+  pub fn new(range: Range) -> Self {
+    Self { range: Some(range), decoded: OnceCell::new() }
+  }
+
+  // a body built in memory, it has no bytes to be decoded from
+  pub fn from_decoded(decoded: DecodedBody) -> Self {
+    Self { range: None, decoded: OnceCell::from(decoded) }
+  }
+
+  pub fn decoded(&self) -> Option<&DecodedBody> {
+    self.decoded.get()
+  }
+
+  // `bytes` are the bytes of the module the body was read from
+  pub fn decode(&self, bytes: &[u8]) -> ResultWithDiagnostics<&DecodedBody> {
+    if let Some(decoded) = self.decoded.get() {
+      return Ok(decoded);
     }
+    let range = self.range.as_ref().expect("a function body without bytes is always decoded");
+    let (_, decoded) = decode_body(bytes, range).map_err(|error| decode_failure(bytes, error))?;
+    Ok(self.decoded.get_or_init(|| decoded))
+  }
+
+  // the body no longer matches its original bytes once it can be modified
+  pub fn decode_mut(&mut self, bytes: &[u8]) -> ResultWithDiagnostics<&mut DecodedBody> {
+    self.decode(bytes)?;
+    self.range = None;
+    Ok(self.decoded.get_mut().unwrap())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedBody {
+  pub locals: Vec<Local>,
+  pub body: Vec<DecodedInstruction>, // including the final `end`
+}
+
+// the module bytes, shared by the lazily decoded function bodies
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct RawBytes(Rc<[u8]>);
+
+impl RawBytes {
+  pub fn as_slice(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl From<&[u8]> for RawBytes {
+  fn from(bytes: &[u8]) -> Self {
+    Self(bytes.into())
+  }
+}
+
+impl From<Vec<u8>> for RawBytes {
+  fn from(bytes: Vec<u8>) -> Self {
+    Self(bytes.into())
+  }
+}
+
+impl std::fmt::Debug for RawBytes {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "<{} bytes>", self.0.len())
   }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Data {
+  pub memory_idx: u32,
+  pub offset: ConstExpr,
+  pub init: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomSection {
+  pub name: String,
+  pub data: Vec<u8>,
+  pub after: Option<SectionCode>, // last non-custom section before this one
+}
@@ -1,5 +1,6 @@
 use clap::{Arg, Command};
 
+#[allow(clippy::needless_return)]
 pub fn command_line() -> clap::ArgMatches {
   let matches = Command::new("wasmre")
     .about("WebAssembly Runtime Engine")
@@ -9,17 +10,39 @@ pub fn command_line() -> clap::ArgMatches {
     .subcommand(
       Command::new("check")
         .about("analyze a wasm file.")
-        .arg(Arg::new("file").help("the wasm file to check.").required(true)),
+        .arg(Arg::new("file").help("the wasm file to check, `-` reads a binary module from stdin.").required(true)),
     )
     .subcommand(
       Command::new("compile")
         .about("compile a wasm file.")
-        .arg(Arg::new("file").help("the wasm file to compile.").required(true)),
+        .arg(Arg::new("file").help("the wasm file to compile.").required(true))
+        .arg(
+          Arg::new("output")
+            .short('o')
+            .long("output")
+            .help("where to write the binary module, defaults to the input path with a `.wasm` extension."),
+        )
+        .arg(module_arg()),
     )
     .subcommand(
-      Command::new("run").about("run a wasm file.").arg(Arg::new("file").help("the wasm file to run.").required(true)),
+      Command::new("run")
+        .about("run a wasm file.")
+        .arg(Arg::new("file").help("the wasm file to run.").required(true))
+        .arg(module_arg())
+        .arg(Arg::new("invoke").long("invoke").help("the exported function to call after instantiating the module."))
+        .arg(
+          Arg::new("args")
+            .num_args(0..)
+            .allow_negative_numbers(true)
+            .help("the arguments of the invoked function, parsed with the types of its parameters."),
+        ),
     )
     .get_matches();
 
   return matches;
 }
+
+// text files can hold several modules, `--module $name` picks one instead of the first
+fn module_arg() -> Arg {
+  Arg::new("module").short('m').long("module").help("the `$name` of the module to use, defaults to the first one.")
+}
@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+
+use crate::{
+  bytes::{
+    instructions::{BlockType, DecodedInstruction, Instruction, MemArg},
+    module::Module,
+    names::NameSection,
+    types::{
+      ConstExpr, Data, DecodedBody, Element, ElementType, Export, ExportDesc, FuncType, FunctionBody, Global,
+      GlobalType, Import, ImportDesc, Limits, Local, MemoryType, TableType, ValueType,
+    },
+  },
+  diagnostics::{Diagnostic, NameError, Severity, TypeError},
+  parser::ast,
+  utils::range::Range,
+};
+
+type Result<T> = std::result::Result<T, Diagnostic>;
+
+// the resolver has already rewritten every `$id` into the index it refers to
+fn resolved(kind: &str, index: &ast::Index) -> Result<u32> {
+  match index {
+    ast::Index::Numeric { value, .. } => Ok(*value),
+    ast::Index::Symbolic(id) => {
+      let error =
+        NameError::UnknownIdentifier { kind: kind.to_string(), name: id.name.clone(), range: id.range.clone() };
+      Err(error.into())
+    }
+  }
+}
+
+fn non_empty<T>(items: Vec<T>) -> Option<Vec<T>> {
+  if items.is_empty() {
+    return None;
+  }
+  Some(items)
+}
+
+fn value_type(value_type: ast::ValueType) -> ValueType {
+  match value_type {
+    ast::ValueType::I32 => ValueType::I32,
+    ast::ValueType::I64 => ValueType::I64,
+    ast::ValueType::F32 => ValueType::F32,
+    ast::ValueType::F64 => ValueType::F64,
+    ast::ValueType::FuncRef => ValueType::FuncRef,
+    ast::ValueType::ExternRef => ValueType::ExternRef,
+  }
+}
+
+fn block_type(result: Option<ast::ValueType>) -> BlockType {
+  match result {
+    Some(result) => BlockType::Value(value_type(result)),
+    None => BlockType::Empty,
+  }
+}
+
+fn func_type(signature: &ast::Signature) -> FuncType {
+  let params = signature.params.iter().map(|param| value_type(param.value_type)).collect();
+  let results = signature.results.iter().map(|result| value_type(*result)).collect();
+  FuncType { params, results }
+}
+
+fn limits(limits: &ast::Limits) -> Limits {
+  Limits { min: limits.min, max: limits.max }
+}
+
+fn table_type(table_type: &ast::TableType) -> Result<TableType> {
+  let element_type = match table_type.element_type {
+    ast::ValueType::FuncRef => ElementType::FuncRef,
+    ast::ValueType::ExternRef => ElementType::ExternRef,
+    found => {
+      let range = Some(table_type.range.clone());
+      let error = TypeError::TypeMismatch { expected: "funcref".to_string(), found: found.to_string(), range };
+      return Err(error.into());
+    }
+  };
+  Ok(TableType { element_type, limits: limits(&table_type.limits) })
+}
+
+fn memory_type(memory_type: &ast::MemoryType) -> MemoryType {
+  MemoryType { limits: limits(&memory_type.limits) }
+}
+
+fn global_type(global_type: &ast::GlobalType) -> GlobalType {
+  GlobalType { value_type: value_type(global_type.value_type), mutable: global_type.mutable }
+}
+
+// consecutive locals of the same type share one entry
+fn compress_locals(locals: &[ast::Local]) -> Vec<Local> {
+  let mut compressed: Vec<Local> = vec![];
+  for local in locals {
+    let local_type = value_type(local.value_type);
+    match compressed.last_mut() {
+      Some(last) if last.value_type == local_type => last.count += 1,
+      _ => compressed.push(Local { count: 1, value_type: local_type }),
+    }
+  }
+  compressed
+}
+
+// the `)` or `end` that closes a block or function, where its implicit `end` instruction points
+fn closing(range: &Range) -> Range {
+  Range::new(range.end.saturating_sub(1).max(range.start), range.end)
+}
+
+// the parser has already given every type use an index, inserting implicit types
+fn type_index(type_use: &ast::TypeUse) -> Result<u32> {
+  match &type_use.index {
+    Some(index) => resolved("type", index),
+    None => {
+      let message = "type use without a type index".to_string();
+      let range = Some(type_use.signature.range.clone());
+      Err(Diagnostic { severity: Severity::Error, message, range, hint: None })
+    }
+  }
+}
+
+// lowers a parsed text module into a binary module, ready to be encoded
+pub struct Compiler<'a> {
+  module: &'a ast::Module,
+}
+
+impl<'a> Compiler<'a> {
+  pub fn new(module: &'a ast::Module) -> Self {
+    Self { module }
+  }
+
+  pub fn compile(&mut self) -> Result<Module> {
+    let module = self.module;
+    let mut binary = Module::default();
+
+    let mut imports = vec![];
+    for import in &module.imports {
+      imports.push(self.compile_import(import)?);
+    }
+
+    let mut functions = vec![];
+    let mut bodies = vec![];
+    for function in &module.functions {
+      functions.push(type_index(&function.type_use)?);
+      bodies.push(self.compile_function(function)?);
+    }
+
+    let mut tables = vec![];
+    for table in &module.tables {
+      tables.push(table_type(&table.table_type)?);
+    }
+    let memories = module.memories.iter().map(|memory| memory_type(&memory.memory_type)).collect();
+
+    let mut globals = vec![];
+    for global in &module.globals {
+      let init = self.compile_const_expr(&global.init, &global.range)?;
+      globals.push(Global { global_type: global_type(&global.global_type), init });
+    }
+
+    let mut exports = vec![];
+    for export in &module.exports {
+      let desc = match &export.desc {
+        ast::ExportDesc::Func(index) => ExportDesc::Func(resolved("function", index)?),
+        ast::ExportDesc::Table(index) => ExportDesc::Table(resolved("table", index)?),
+        ast::ExportDesc::Mem(index) => ExportDesc::Memory(resolved("memory", index)?),
+        ast::ExportDesc::Global(index) => ExportDesc::Global(resolved("global", index)?),
+      };
+      exports.push(Export { name: export.name.clone(), desc });
+    }
+
+    if let Some(start) = &module.start {
+      binary.start_section = Some(resolved("function", &start.function)?);
+    }
+
+    let mut elements = vec![];
+    for element in &module.elements {
+      let table_idx = resolved("table", &element.table)?;
+      let offset = self.compile_const_expr(&element.offset, &element.range)?;
+      let init = element.init.iter().map(|index| resolved("function", index)).collect::<Result<_>>()?;
+      elements.push(Element { table_idx, offset, init });
+    }
+
+    let mut data = vec![];
+    for segment in &module.data {
+      let memory_idx = resolved("memory", &segment.memory)?;
+      let offset = self.compile_const_expr(&segment.offset, &segment.range)?;
+      data.push(Data { memory_idx, offset, init: segment.init.clone() });
+    }
+
+    let types = module.types.iter().map(|definition| func_type(&definition.signature)).collect();
+    binary.type_section = non_empty(types);
+    binary.import_section = non_empty(imports);
+    binary.function_section = non_empty(functions);
+    binary.table_section = non_empty(tables);
+    binary.memory_section = non_empty(memories);
+    binary.global_section = non_empty(globals);
+    binary.export_section = non_empty(exports);
+    binary.element_section = non_empty(elements);
+    binary.code_section = non_empty(bodies);
+    binary.data_section = non_empty(data);
+    binary.names = self.compile_names();
+    Ok(binary)
+  }
+
+  // the `$id`s of the module, its functions and their params and locals, as a name section would list them
+  fn compile_names(&self) -> Option<NameSection> {
+    let module = self.module;
+    let mut names = NameSection { module: module.id.as_ref().map(|id| id.name.clone()), ..Default::default() };
+    let imported = module.imports.iter().filter_map(|import| match &import.desc {
+      ast::ImportDesc::Func(type_use) => Some((&import.id, type_use, &[][..])),
+      _ => None,
+    });
+    let defined = module.functions.iter().map(|function| (&function.id, &function.type_use, &function.locals[..]));
+    for (func_idx, (id, type_use, locals)) in imported.chain(defined).enumerate() {
+      if let Some(id) = id {
+        names.functions.insert(func_idx as u32, id.name.clone());
+      }
+      let params = type_use.signature.params.iter().map(|param| &param.id);
+      let local_names: BTreeMap<u32, String> = params
+        .chain(locals.iter().map(|local| &local.id))
+        .enumerate()
+        .filter_map(|(local_idx, id)| Some((local_idx as u32, id.as_ref()?.name.clone())))
+        .collect();
+      if !local_names.is_empty() {
+        names.locals.insert(func_idx as u32, local_names);
+      }
+    }
+    if names == NameSection::default() {
+      return None;
+    }
+    Some(names)
+  }
+
+  fn compile_import(&mut self, import: &ast::Import) -> Result<Import> {
+    let desc = match &import.desc {
+      ast::ImportDesc::Func(type_use) => ImportDesc::Func(type_index(type_use)?),
+      ast::ImportDesc::Table(table) => ImportDesc::Table(table_type(table)?),
+      ast::ImportDesc::Mem(memory) => ImportDesc::Memory(memory_type(memory)),
+      ast::ImportDesc::Global(global) => ImportDesc::Global(global_type(global)),
+    };
+    Ok(Import { module: import.module.clone(), name: import.name.clone(), desc })
+  }
+
+  fn compile_function(&mut self, function: &ast::Function) -> Result<FunctionBody> {
+    let mut body = vec![];
+    self.compile_instrs(&function.body, &mut body)?;
+    // byte offsets are only known once the module is encoded, instructions keep their text range instead
+    body.push(DecodedInstruction { instruction: Instruction::End, range: closing(&function.range) });
+    Ok(FunctionBody::from_decoded(DecodedBody {
+      locals: compress_locals(&function.locals),
+      body,
+    }))
+  }
+
+  fn compile_instrs(&mut self, instrs: &[ast::Instr], out: &mut Vec<DecodedInstruction>) -> Result<()> {
+    for instr in instrs {
+      self.compile_instr(instr, out)?;
+    }
+    Ok(())
+  }
+
+  fn compile_instr(&mut self, instr: &ast::Instr, out: &mut Vec<DecodedInstruction>) -> Result<()> {
+    let range = instr.range().clone();
+    let instruction = match instr {
+      ast::Instr::Unreachable { .. } => Instruction::Unreachable,
+      ast::Instr::Nop { .. } => Instruction::Nop,
+      ast::Instr::Block(block) => {
+        out.push(DecodedInstruction { instruction: Instruction::Block(block_type(block.block_type)), range });
+        self.compile_instrs(&block.instr, out)?;
+        out.push(DecodedInstruction { instruction: Instruction::End, range: closing(&block.range) });
+        return Ok(());
+      }
+      ast::Instr::Loop(block) => {
+        out.push(DecodedInstruction { instruction: Instruction::Loop(block_type(block.loop_type)), range });
+        self.compile_instrs(&block.instr, out)?;
+        out.push(DecodedInstruction { instruction: Instruction::End, range: closing(&block.range) });
+        return Ok(());
+      }
+      ast::Instr::If(block) => {
+        self.compile_instrs(&block.condition, out)?;
+        out.push(DecodedInstruction { instruction: Instruction::If(block_type(block.block_type)), range });
+        self.compile_instrs(&block.instr, out)?;
+        if let Some(else_instr) = &block.else_instr {
+          out.push(DecodedInstruction { instruction: Instruction::Else, range: closing(&block.range) });
+          self.compile_instrs(else_instr, out)?;
+        }
+        out.push(DecodedInstruction { instruction: Instruction::End, range: closing(&block.range) });
+        return Ok(());
+      }
+      ast::Instr::Branch(branch) => Instruction::Br(resolved("label", &branch.label)?),
+      ast::Instr::BranchIf(branch) => Instruction::BrIf(resolved("label", &branch.label)?),
+      ast::Instr::BranchTable(branch) => {
+        let labels = branch.labels.iter().map(|label| resolved("label", label)).collect::<Result<_>>()?;
+        Instruction::BrTable { labels, default: resolved("label", &branch.default)? }
+      }
+      ast::Instr::Return { .. } => Instruction::Return,
+      ast::Instr::Call(call) => Instruction::Call(resolved("function", &call.function)?),
+      ast::Instr::CallIndirect(call) => Instruction::CallIndirect(type_index(&call.type_use)?),
+      ast::Instr::Drop { .. } => Instruction::Drop,
+      ast::Instr::Select { .. } => Instruction::Select,
+      ast::Instr::LocalGet(variable) => Instruction::LocalGet(resolved("local", &variable.index)?),
+      ast::Instr::LocalSet(variable) => Instruction::LocalSet(resolved("local", &variable.index)?),
+      ast::Instr::LocalTee(variable) => Instruction::LocalTee(resolved("local", &variable.index)?),
+      ast::Instr::GlobalGet(variable) => Instruction::GlobalGet(resolved("global", &variable.index)?),
+      ast::Instr::GlobalSet(variable) => Instruction::GlobalSet(resolved("global", &variable.index)?),
+      ast::Instr::Memory(memory) => {
+        // the binary format stores the exponent, the parser only accepts powers of two
+        let memarg = MemArg { align: memory.align.trailing_zeros(), offset: memory.offset };
+        Instruction::Memory(memory.op, memarg)
+      }
+      ast::Instr::MemorySize { .. } => Instruction::MemorySize,
+      ast::Instr::MemoryGrow { .. } => Instruction::MemoryGrow,
+      ast::Instr::I32Const { value, .. } => Instruction::I32Const(*value),
+      ast::Instr::I64Const { value, .. } => Instruction::I64Const(*value),
+      ast::Instr::F32Const { bits, .. } => Instruction::F32Const(*bits),
+      ast::Instr::F64Const { bits, .. } => Instruction::F64Const(*bits),
+      ast::Instr::Numeric(numeric) => Instruction::Numeric(numeric.op),
+    };
+    out.push(DecodedInstruction { instruction, range });
+    Ok(())
+  }
+
+  fn compile_const_expr(&self, instrs: &[ast::Instr], range: &Range) -> Result<ConstExpr> {
+    let expr = match instrs {
+      [ast::Instr::I32Const { value, .. }] => ConstExpr::I32Const(*value),
+      [ast::Instr::I64Const { value, .. }] => ConstExpr::I64Const(*value),
+      [ast::Instr::F32Const { bits, .. }] => ConstExpr::F32Const(*bits),
+      [ast::Instr::F64Const { bits, .. }] => ConstExpr::F64Const(*bits),
+      [ast::Instr::GlobalGet(variable)] => ConstExpr::GlobalGet(resolved("global", &variable.index)?),
+      _ => {
+        let message = "constant expression required".to_string();
+        return Err(Diagnostic { severity: Severity::Error, message, range: Some(range.clone()), hint: None });
+      }
+    };
+    Ok(expr)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    lexer::Lexer,
+    parser::{resolve_program, Parser},
+  };
+
+  fn compile(text: &str) -> Module {
+    let mut parser = Parser::new(Lexer::new(text, "test.wat"));
+    let mut program = parser.parse_program();
+    resolve_program(parser.diagnostics(), &mut program);
+    assert!(!parser.diagnostics().has_errors());
+    Compiler::new(&program.body[0]).compile().unwrap()
+  }
+
+  #[test]
+  fn ids_become_names() {
+    let module = compile(
+      r#"(module $m
+        (import "env" "log" (func $log (param $value i32)))
+        (func (param i32) (local $tmp i64))
+        (func $add (param $a i32) (param $b i32) (result i32) (local $sum i32) local.get $a))"#,
+    );
+    let names = module.names.as_ref().unwrap();
+    assert_eq!(names.module.as_deref(), Some("m"));
+    assert_eq!(
+      names.functions,
+      BTreeMap::from([(0, "log".to_string()), (2, "add".to_string())])
+    );
+    assert_eq!(names.local_name(0, 0), Some("value"));
+    assert_eq!(names.local_name(1, 0), None);
+    assert_eq!(names.local_name(1, 1), Some("tmp"));
+    assert_eq!(names.local_name(2, 1), Some("b"));
+    assert_eq!(names.local_name(2, 2), Some("sum"));
+    assert_eq!(module.display_function(1), "func[1]");
+    assert_eq!(module.display_function(2), "$add");
+  }
+
+  #[test]
+  fn modules_without_ids_have_no_names() {
+    assert!(compile("(module (func (param i32) (local f32)))").names.is_none());
+  }
+}
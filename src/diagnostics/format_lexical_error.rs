@@ -0,0 +1,29 @@
+const PREFIX: &str = "lexical error:";
+
+pub fn format_unknown_character(character: char) -> String {
+  format!("{} unknown character `{}`", PREFIX, character.escape_debug())
+}
+
+pub fn format_unterminated_string() -> String {
+  format!("{} unterminated string", PREFIX)
+}
+
+pub fn format_unterminated_comment() -> String {
+  format!("{} unterminated block comment", PREFIX)
+}
+
+pub fn format_control_character(character: char) -> String {
+  format!("{} control character `{}` in string", PREFIX, character.escape_debug())
+}
+
+pub fn format_unknown_escape(escape: &str) -> String {
+  format!("{} unknown escape `{}`", PREFIX, escape)
+}
+
+pub fn format_invalid_unicode_escape(escape: &str) -> String {
+  format!("{} invalid unicode escape `{}`", PREFIX, escape)
+}
+
+pub fn format_missing_annotation_name() -> String {
+  format!("{} missing annotation name", PREFIX)
+}
@@ -0,0 +1,7 @@
+pub fn format_unknown_identifier(kind: &str, name: &str) -> String {
+  format!("unknown {} `${}`", kind, name)
+}
+
+pub fn format_duplicate_identifier(kind: &str, name: &str) -> String {
+  format!("duplicate {} `${}`", kind, name)
+}
@@ -7,3 +7,19 @@ pub fn format_unexpected_token(expected: &str, actual: &str) -> String {
 pub fn format_unkon_character(character: &str) -> String {
   format!("{} unknown character `{}`", PREFIX, character)
 }
+
+pub fn format_constant_out_of_range(kind: &str, literal: &str) -> String {
+  format!("{} constant `{}` is out of range for `{}`", PREFIX, literal, kind)
+}
+
+pub fn format_malformed_utf8() -> String {
+  format!("{} malformed UTF-8 encoding", PREFIX)
+}
+
+pub fn format_import_after_definition(kind: &str) -> String {
+  format!("{} import after a {} definition", PREFIX, kind)
+}
+
+pub fn format_malformed_alignment(align: u32) -> String {
+  format!("{} alignment `{}` must be a power of two", PREFIX, align)
+}
@@ -7,3 +7,35 @@ pub fn format_type_mismatch(expected: &str, actual: &str) -> String {
 pub fn format_unknown_type(name: &str) -> String {
   format!("unknown type `{}`", name)
 }
+
+pub fn format_unknown_index(kind: &str, index: u32) -> String {
+  format!("unknown {} {}", kind, index)
+}
+
+pub fn format_invalid_alignment(align: u32, natural: u32) -> String {
+  format!(
+    "alignment must not be larger than natural, found 2^{} but the access is {} bytes",
+    align,
+    1u64 << natural
+  )
+}
+
+pub fn format_duplicate_export(name: &str) -> String {
+  format!("duplicate export name `{}`", name)
+}
+
+pub fn format_constant_expression_required() -> String {
+  "constant expression required, `global.get` must refer to an immutable imported global".to_string()
+}
+
+pub fn format_multiple_items(kind: &str) -> String {
+  format!("at most one {} is allowed", kind)
+}
+
+pub fn format_immutable_global(index: u32) -> String {
+  format!("global {} is immutable", index)
+}
+
+pub fn format_too_many_locals(count: u64, limit: u64) -> String {
+  format!("too many locals, found {} but at most {} are allowed", count, limit)
+}
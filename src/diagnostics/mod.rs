@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+mod format_lexical_error;
+mod format_name_error;
 mod format_syntax_error;
 mod format_type_error;
 mod reporter;
@@ -9,11 +11,13 @@ use crate::utils::range::Range;
 
 pub type ResultWithDiagnostics<T> = std::result::Result<T, Diagnostic>;
 
+#[derive(Debug)]
 pub enum Severity {
   Error,
   Warning,
 }
 
+#[derive(Debug)]
 pub struct Diagnostic {
   pub severity: Severity,
   pub message: String,
@@ -36,6 +40,10 @@ impl<'a> DiagnosticManager<'a> {
     self.diagnostics.push(diagnostic);
   }
 
+  pub fn add_lexical_error(&mut self, error: LexicalError) {
+    self.add(error.into());
+  }
+
   pub fn add_sintax_error(&mut self, error: SintaxError) {
     self.add(error.into());
   }
@@ -44,6 +52,18 @@ impl<'a> DiagnosticManager<'a> {
     self.add(error.into());
   }
 
+  pub fn add_name_error(&mut self, error: NameError) {
+    self.add(error.into());
+  }
+
+  pub fn diagnostics(&self) -> &[Diagnostic] {
+    &self.diagnostics
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self.diagnostics.iter().any(|diagnostic| matches!(diagnostic.severity, Severity::Error))
+  }
+
   pub fn report(&self) {
     for diagnostic in &self.diagnostics {
       report_diagnostic(diagnostic, self.raw, self.file_name);
@@ -51,6 +71,56 @@ impl<'a> DiagnosticManager<'a> {
   }
 }
 
+#[derive(Debug, Clone)]
+pub enum LexicalError {
+  UnknownCharacter {
+    character: char,
+    range: Range,
+  },
+  UnterminatedString {
+    range: Range, // from the opening quote to the end of the line
+  },
+  UnterminatedComment {
+    range: Range, // the `(;` left open
+  },
+  ControlCharacter {
+    character: char,
+    range: Range,
+  },
+  UnknownEscape {
+    escape: String,
+    range: Range,
+  },
+  InvalidUnicodeEscape {
+    escape: String,
+    range: Range,
+  },
+  MissingAnnotationName {
+    range: Range,
+  },
+}
+
+impl From<LexicalError> for Diagnostic {
+  fn from(error: LexicalError) -> Self {
+    let (message, range) = match error {
+      LexicalError::UnknownCharacter { character, range } => {
+        (format_lexical_error::format_unknown_character(character), range)
+      }
+      LexicalError::UnterminatedString { range } => (format_lexical_error::format_unterminated_string(), range),
+      LexicalError::UnterminatedComment { range } => (format_lexical_error::format_unterminated_comment(), range),
+      LexicalError::ControlCharacter { character, range } => {
+        (format_lexical_error::format_control_character(character), range)
+      }
+      LexicalError::UnknownEscape { escape, range } => (format_lexical_error::format_unknown_escape(&escape), range),
+      LexicalError::InvalidUnicodeEscape { escape, range } => {
+        (format_lexical_error::format_invalid_unicode_escape(&escape), range)
+      }
+      LexicalError::MissingAnnotationName { range } => (format_lexical_error::format_missing_annotation_name(), range),
+    };
+    Diagnostic { severity: Severity::Error, message, range: Some(range), hint: None }
+  }
+}
+
 #[derive(Debug, Clone)]
 pub enum SintaxError {
   UnxpectedToken {
@@ -62,6 +132,22 @@ pub enum SintaxError {
     name: String,
     range: Range,
   },
+  ConstantOutOfRange {
+    kind: String, // the type the literal was written for
+    literal: String,
+    range: Range,
+  },
+  MalformedUtf8 {
+    range: Range,
+  },
+  ImportAfterDefinition {
+    kind: String, // the kind of the first definition
+    range: Range, // the import
+  },
+  MalformedAlignment {
+    align: u32,
+    range: Range,
+  },
 }
 
 impl From<SintaxError> for Diagnostic {
@@ -75,32 +161,123 @@ impl From<SintaxError> for Diagnostic {
         let message = format_syntax_error::format_unkon_character(&name);
         Diagnostic { severity: Severity::Error, message, range: Some(range), hint: None }
       }
+      SintaxError::ConstantOutOfRange { kind, literal, range } => {
+        let message = format_syntax_error::format_constant_out_of_range(&kind, &literal);
+        Diagnostic { severity: Severity::Error, message, range: Some(range), hint: None }
+      }
+      SintaxError::MalformedUtf8 { range } => {
+        let message = format_syntax_error::format_malformed_utf8();
+        Diagnostic { severity: Severity::Error, message, range: Some(range), hint: None }
+      }
+      SintaxError::ImportAfterDefinition { kind, range } => {
+        let message = format_syntax_error::format_import_after_definition(&kind);
+        Diagnostic { severity: Severity::Error, message, range: Some(range), hint: None }
+      }
+      SintaxError::MalformedAlignment { align, range } => {
+        let message = format_syntax_error::format_malformed_alignment(align);
+        Diagnostic { severity: Severity::Error, message, range: Some(range), hint: None }
+      }
     }
   }
 }
 
+// validation errors, binary modules only know the byte offset of instructions so the range is optional
 #[derive(Debug, Clone)]
 pub enum TypeError {
   TypeMismatch {
     expected: String,
     found: String,
-    range: Range,
+    range: Option<Range>,
   },
   UnknownType {
     name: String,
-    range: Range,
+    range: Option<Range>,
+  },
+  UnknownIndex {
+    kind: String, // the index space, e.g. `function` or `label`
+    index: u32,
+    range: Option<Range>,
+  },
+  InvalidAlignment {
+    align: u32, // log2 of the alignment
+    natural: u32,
+    range: Option<Range>,
+  },
+  InvalidLimits {
+    reason: String,
+    range: Option<Range>,
+  },
+  DuplicateExport {
+    name: String,
+    range: Option<Range>,
+  },
+  ConstantExpressionRequired {
+    range: Option<Range>,
+  },
+  MultipleItems {
+    kind: String,
+    range: Option<Range>,
+  },
+  ImmutableGlobal {
+    index: u32,
+    range: Option<Range>,
+  },
+  TooManyLocals {
+    count: u64, // parameters included
+    limit: u64,
+    range: Option<Range>,
   },
 }
 
 impl From<TypeError> for Diagnostic {
   fn from(error: TypeError) -> Self {
-    match error {
+    let (message, range) = match error {
       TypeError::TypeMismatch { expected, found, range } => {
-        let message = format_type_error::format_type_mismatch(&expected, &found);
+        (format_type_error::format_type_mismatch(&expected, &found), range)
+      }
+      TypeError::UnknownType { name, range } => (format_type_error::format_unknown_type(&name), range),
+      TypeError::UnknownIndex { kind, index, range } => (format_type_error::format_unknown_index(&kind, index), range),
+      TypeError::InvalidAlignment { align, natural, range } => {
+        (format_type_error::format_invalid_alignment(align, natural), range)
+      }
+      TypeError::InvalidLimits { reason, range } => (reason, range),
+      TypeError::DuplicateExport { name, range } => (format_type_error::format_duplicate_export(&name), range),
+      TypeError::ConstantExpressionRequired { range } => {
+        (format_type_error::format_constant_expression_required(), range)
+      }
+      TypeError::MultipleItems { kind, range } => (format_type_error::format_multiple_items(&kind), range),
+      TypeError::ImmutableGlobal { index, range } => (format_type_error::format_immutable_global(index), range),
+      TypeError::TooManyLocals { count, limit, range } => {
+        (format_type_error::format_too_many_locals(count, limit), range)
+      }
+    };
+    Diagnostic { severity: Severity::Error, message, range, hint: None }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum NameError {
+  UnknownIdentifier {
+    kind: String, // the index space, e.g. `function` or `local`
+    name: String,
+    range: Range,
+  },
+  DuplicateIdentifier {
+    kind: String,
+    name: String,
+    range: Range, // the second definition
+  },
+}
+
+impl From<NameError> for Diagnostic {
+  fn from(error: NameError) -> Self {
+    match error {
+      NameError::UnknownIdentifier { kind, name, range } => {
+        let message = format_name_error::format_unknown_identifier(&kind, &name);
         Diagnostic { severity: Severity::Error, message, range: Some(range), hint: None }
       }
-      TypeError::UnknownType { name, range } => {
-        let message = format_type_error::format_unknown_type(&name);
+      NameError::DuplicateIdentifier { kind, name, range } => {
+        let message = format_name_error::format_duplicate_identifier(&kind, &name);
         Diagnostic { severity: Severity::Error, message, range: Some(range), hint: None }
       }
     }
@@ -120,12 +297,28 @@ pub enum RuntimeError {
     name: String,
     range: Option<Range>,
   },
+  UnknownGlobal {
+    name: String,
+    range: Option<Range>,
+  },
+  UnknownImport {
+    module: String,
+    name: String,
+    range: Option<Range>,
+  },
   TableOutOfBounds {
     index: u32,
     range: Option<Range>,
   },
+  UninitializedElement {
+    index: u32,
+    range: Option<Range>,
+  },
+  ImmutableGlobal {
+    range: Option<Range>,
+  },
   MemoryOutOfBounds {
-    offset: u32,
+    address: u64, // the effective address, base plus static offset, which can exceed 32 bits
     range: Option<Range>,
   },
   TypeMismatch {
@@ -136,10 +329,26 @@ pub enum RuntimeError {
   StackOverflow {
     range: Option<Range>,
   },
+  Unreachable {
+    range: Option<Range>,
+  },
+  IntegerDivideByZero {
+    range: Option<Range>,
+  },
+  IntegerOverflow {
+    range: Option<Range>,
+  },
+  InvalidConversion {
+    range: Option<Range>,
+  },
   CallIndirect {
     type_idx: u32,
     range: Option<Range>,
   },
+  OutOfMemory {
+    size: u64, // in bytes
+    range: Option<Range>,
+  },
   FailedToDecodeModule {
     range: Option<Range>,
     cause: String,
@@ -161,12 +370,28 @@ impl From<RuntimeError> for Diagnostic {
         let message = format!("unknown memory `{}`", name);
         Diagnostic { severity: Severity::Error, message, range, hint: None }
       }
+      RuntimeError::UnknownGlobal { name, range } => {
+        let message = format!("unknown global `{}`", name);
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
+      RuntimeError::UnknownImport { module, name, range } => {
+        let message = format!("unknown import `{}.{}`", module, name);
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
       RuntimeError::TableOutOfBounds { index, range } => {
         let message = format!("table out of bounds, index = {}", index);
         Diagnostic { severity: Severity::Error, message, range, hint: None }
       }
-      RuntimeError::MemoryOutOfBounds { offset, range } => {
-        let message = format!("memory out of bounds, offset = {}", offset);
+      RuntimeError::UninitializedElement { index, range } => {
+        let message = format!("uninitialized element, index = {}", index);
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
+      RuntimeError::ImmutableGlobal { range } => {
+        let message = "global is immutable".to_string();
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
+      RuntimeError::MemoryOutOfBounds { address, range } => {
+        let message = format!("memory out of bounds, address = {}", address);
         Diagnostic { severity: Severity::Error, message, range, hint: None }
       }
       RuntimeError::TypeMismatch { expected, found, range } => {
@@ -177,10 +402,30 @@ impl From<RuntimeError> for Diagnostic {
         let message = "stack overflow".to_string();
         Diagnostic { severity: Severity::Error, message, range, hint: None }
       }
+      RuntimeError::Unreachable { range } => {
+        let message = "unreachable executed".to_string();
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
+      RuntimeError::IntegerDivideByZero { range } => {
+        let message = "integer divide by zero".to_string();
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
+      RuntimeError::IntegerOverflow { range } => {
+        let message = "integer overflow".to_string();
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
+      RuntimeError::InvalidConversion { range } => {
+        let message = "invalid conversion to integer".to_string();
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
       RuntimeError::CallIndirect { type_idx, range } => {
         let message = format!("call indirect: type index = {}", type_idx);
         Diagnostic { severity: Severity::Error, message, range, hint: None }
       }
+      RuntimeError::OutOfMemory { size, range } => {
+        let message = format!("out of memory, cannot allocate {} bytes", size);
+        Diagnostic { severity: Severity::Error, message, range, hint: None }
+      }
       RuntimeError::FailedToDecodeModule { range, cause } => {
         let message = format!("failed to decode module: {}", cause);
         Diagnostic { severity: Severity::Error, message, range, hint: None }
@@ -7,28 +7,31 @@ use crate::utils::range::Range;
 use super::Diagnostic;
 use super::Severity;
 
-pub fn report_lexer_diagnostics(message: &str, raw: &str, range: Range, file_name: &str) -> ! {
-  println!("");
-  println!("{}", highlight_red(&format!("ERROR: {}", message)));
-  let text_file_highlighted = highlight_cyan(file_name);
-  println!("{}", text_file_highlighted);
-  println!("{}", code_highlighter::highlight_error(range.start, range.end, raw));
-  std::process::exit(1);
-}
-
 pub fn report_diagnostic(diagnostics: &Diagnostic, raw: &str, file_name: &str) {
   match diagnostics.severity {
     Severity::Error => {
-      report_error(&diagnostics.message, &diagnostics.range, file_name, raw);
+      report_error(
+        &diagnostics.message,
+        &diagnostics.range,
+        &diagnostics.hint,
+        file_name,
+        raw,
+      );
     }
     Severity::Warning => {
-      report_warning(&diagnostics.message, &diagnostics.range, file_name, raw);
+      report_warning(
+        &diagnostics.message,
+        &diagnostics.range,
+        &diagnostics.hint,
+        file_name,
+        raw,
+      );
     }
   }
 }
 
-pub fn report_warning(message: &str, range: &Option<Range>, file_name: &str, raw: &str) {
-  println!("");
+pub fn report_warning(message: &str, range: &Option<Range>, hint: &Option<String>, file_name: &str, raw: &str) {
+  println!();
   println!("{}", highlight_yellow(&format!("WARNING: {}", message)));
   let text_file_highlighted = highlight_cyan(file_name);
   println!("{}", text_file_highlighted);
@@ -36,10 +39,11 @@ pub fn report_warning(message: &str, range: &Option<Range>, file_name: &str, raw
     let highlight = code_highlighter::highlight_error(range.start, range.end, raw);
     println!("{}", highlight);
   }
+  report_hint(hint);
 }
 
-pub fn report_error(message: &str, range: &Option<Range>, file_name: &str, raw: &str) {
-  println!("");
+pub fn report_error(message: &str, range: &Option<Range>, hint: &Option<String>, file_name: &str, raw: &str) {
+  println!();
   println!("{}", highlight_red(&format!("ERROR: {}", message)));
   let text_file_highlighted = highlight_cyan(file_name);
   println!("{}", text_file_highlighted);
@@ -47,4 +51,54 @@ pub fn report_error(message: &str, range: &Option<Range>, file_name: &str, raw:
     let highlight = code_highlighter::highlight_error(range.start, range.end, raw);
     println!("{}", highlight);
   }
+  report_hint(hint);
+}
+
+// binary modules have no source text, show a hex dump of the rows around the range instead
+pub fn report_bytes_diagnostic(diagnostics: &Diagnostic, bytes: &[u8], file_name: &str) {
+  println!();
+  println!("{}", highlight_red(&format!("ERROR: {}", diagnostics.message)));
+  match &diagnostics.range {
+    Some(range) => {
+      println!("{}", highlight_cyan(&format!("{}:0x{:x}", file_name, range.start)));
+      println!("{}", highlight_bytes(range, bytes));
+    }
+    None => println!("{}", highlight_cyan(file_name)),
+  }
+  report_hint(&diagnostics.hint);
+}
+
+fn report_hint(hint: &Option<String>) {
+  if let Some(hint) = hint {
+    println!("{}", hint);
+  }
+}
+
+fn highlight_bytes(range: &Range, bytes: &[u8]) -> String {
+  const ROW_SIZE: usize = 16;
+  const MAX_ROWS: usize = 4;
+  let first_row = range.start / ROW_SIZE;
+  let last_row = (range.end.max(range.start + 1) - 1) / ROW_SIZE;
+  let last_row = last_row.min(first_row + MAX_ROWS - 1);
+  let mut text = String::new();
+  for row in first_row..=last_row {
+    text.push_str(&format!(" {:08x} | ", row * ROW_SIZE));
+    for offset in row * ROW_SIZE..(row + 1) * ROW_SIZE {
+      let Some(byte) = bytes.get(offset) else {
+        if offset == range.start {
+          text.push_str(&highlight_red("<end>"));
+        }
+        break;
+      };
+      let hex = format!("{:02x}", byte);
+      if range.start <= offset && offset < range.end {
+        text.push_str(&highlight_red(&hex));
+      } else {
+        text.push_str(&hex);
+      }
+      text.push(' ');
+    }
+    text.push('\n');
+  }
+  text
 }
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
+use crate::diagnostics::{DiagnosticManager, LexicalError};
+use crate::utils::match_idchar;
 use crate::utils::range::Range;
-use crate::{diagnostics::report_lexer_diagnostics, utils::match_number};
 
 use super::tokens::Token;
 use crate::lexer::tokens::TokenKind;
@@ -11,11 +12,17 @@ pub struct Lexer<'a> {
   pub file_name: &'a str,
   start_cursor: usize,
   cached_token: Option<Token>,
+  diagnostics: DiagnosticManager<'a>, // errors are recorded and lexing goes on with an error token
 }
 
 impl<'a> Lexer<'a> {
   pub fn new(raw: &'a str, file_name: &'a str) -> Self {
-    Self { raw, cursor: 0, file_name, start_cursor: 0, cached_token: None }
+    let diagnostics = DiagnosticManager::new(raw, file_name);
+    Self { raw, cursor: 0, file_name, start_cursor: 0, cached_token: None, diagnostics }
+  }
+
+  pub fn diagnostics(&mut self) -> &mut DiagnosticManager<'a> {
+    &mut self.diagnostics
   }
 
   pub fn peek_token(&mut self) -> Token {
@@ -25,56 +32,29 @@ impl<'a> Lexer<'a> {
   }
   pub fn next_token(&mut self) -> Token {
     self.cached_token = None;
-    self.start_cursor = self.cursor;
     self.skip_whitespace();
+    self.start_cursor = self.cursor;
     if self.is_end() {
       let range = self.create_range();
       return Token::new_eof(range);
     }
     let next_char = self.peek_one();
     match next_char {
-      '0'..='9' => self.read_number(),
-      'a'..='z' | 'A'..='Z' | '_' => self.read_identifier(),
-      '"' => self.read_string(),
+      '(' if self.starts_with("(;") => self.read_block_comment(),
+      '(' if self.starts_with("(@") => self.read_annotation(),
       '(' => self.create_simple_token(TokenKind::LParen),
       ')' => self.create_simple_token(TokenKind::RParen),
-      '+' => self.create_simple_token(TokenKind::Plus),
-      '*' => self.create_simple_token(TokenKind::Star),
-      '/' => self.create_simple_token(TokenKind::Slash),
-      ',' => self.create_simple_token(TokenKind::Comma),
-      '{' => self.create_simple_token(TokenKind::LBrace),
-      '}' => self.create_simple_token(TokenKind::RBrace),
-      '[' => self.create_simple_token(TokenKind::LBracket),
-      ']' => self.create_simple_token(TokenKind::RBracket),
-      '=' => self.create_simple_token(TokenKind::Equal),
-      ':' => self.create_simple_token(TokenKind::Colon),
-      '.' => self.create_simple_token(TokenKind::Dot),
-      '?' => self.create_simple_token(TokenKind::Question),
-      '<' => self.create_complex_token("<=", TokenKind::LessThanEqual, TokenKind::LessThan),
-      '>' => self.create_complex_token(">=", TokenKind::GreaterThanEqual, TokenKind::GreaterThan),
-      '!' => self.create_complex_token("!=", TokenKind::NotEqual, TokenKind::Exclamation),
-      '|' => self.create_complex_token("||", TokenKind::Bar, TokenKind::Or),
-      '&' => self.create_complex_token("&&", TokenKind::Ampersand, TokenKind::And),
-      '-' => self.create_complex_token("->", TokenKind::Arrow, TokenKind::Minus),
-      ';' => self.read_comment_or_semicolon(),
-      '@' => self.create_simple_token(TokenKind::At),
-      '#' => self.create_simple_token(TokenKind::Hash),
-      '\\' => self.create_simple_token(TokenKind::Backslash),
-      '$' => self.create_simple_token(TokenKind::Dollar),
-      _ => {
-        let text = format!("unknown character `{}`", next_char);
-        self.report_diagnostic(text, self.create_range())
+      '"' => self.read_string(),
+      ';' if self.starts_with(";;") => self.read_comment(),
+      character if match_idchar(character) => self.read_word(),
+      character => {
+        self.advance_one();
+        self.add_error(LexicalError::UnknownCharacter { character, range: self.create_range() });
+        self.create_error_token()
       }
     }
   }
 
-  fn read_comment_or_semicolon(&mut self) -> Token {
-    if self.starts_with(";;") {
-      return self.read_comment();
-    }
-    return self.create_simple_token(TokenKind::Semicolon);
-  }
-
   fn read_comment(&mut self) -> Token {
     self.consume_expect(";;");
     let text = self.read_while(|c| c != '\n');
@@ -82,54 +62,168 @@ impl<'a> Lexer<'a> {
     Token::new_comment(range, text)
   }
 
-  fn create_simple_token(&mut self, token_kind: TokenKind) -> Token {
+  // `(; ... ;)`, block comments can be nested
+  fn read_block_comment(&mut self) -> Token {
+    let mut openings = vec![self.cursor];
+    self.consume_expect("(;");
+    while let Some(&opening) = openings.last() {
+      if self.is_end() {
+        self.add_error(LexicalError::UnterminatedComment { range: Range::new(opening, opening + 2) });
+        return self.create_error_token();
+      }
+      if self.starts_with("(;") {
+        openings.push(self.cursor);
+        self.advance_many(2);
+      } else if self.starts_with(";)") {
+        openings.pop();
+        self.advance_many(2);
+      } else {
+        self.advance_one();
+      }
+    }
     let range = self.create_range();
-    self.advance_one();
-    return Token::new(token_kind, range);
+    let text = self.raw[range.start + 2..range.end - 2].to_string();
+    Token::new_comment(range, text)
   }
 
-  fn create_complex_token(&mut self, text: &str, single_kind: TokenKind, double_kind: TokenKind) -> Token {
+  // `(@name`, the rest of the annotation is lexed as ordinary tokens up to the matching `)`
+  fn read_annotation(&mut self) -> Token {
+    self.consume_expect("(@");
+    let name = self.read_while(match_idchar);
     let range = self.create_range();
-    if self.starts_with(text) {
-      self.advance_many(text.len());
-      return Token::new(single_kind, range);
-    } else {
-      self.advance_one();
-      return Token::new(double_kind, range);
+    if name.is_empty() {
+      // still an annotation, so its body is skipped like any other
+      self.add_error(LexicalError::MissingAnnotationName { range: range.clone() });
     }
+    Token::new(TokenKind::Annotation(name), range)
   }
 
-  fn read_number(&mut self) -> Token {
-    let value = self.read_while(|c| match_number(c));
+  #[allow(clippy::needless_return)]
+  fn create_simple_token(&mut self, token_kind: TokenKind) -> Token {
+    self.advance_one();
+    let range = self.create_range();
+    return Token::new(token_kind, range);
+  }
+
+  // keywords, `$id`s, numbers and reserved words are all runs of idchars,
+  // https://webassembly.github.io/spec/core/text/lexical.html#tokens
+  fn read_word(&mut self) -> Token {
+    let text = self.read_while(match_idchar);
     let range = self.create_range();
-    Token::new_number(range, value)
+    let kind = if let Some(name) = text.strip_prefix('$').filter(|name| !name.is_empty()) {
+      TokenKind::Id(name.to_string())
+    } else if is_number(&text) {
+      TokenKind::Number(text)
+    } else if let Some(value) = text.strip_prefix("offset=") {
+      TokenKind::Offset(value.to_string())
+    } else if let Some(value) = text.strip_prefix("align=") {
+      TokenKind::Align(value.to_string())
+    } else if text.starts_with(|character: char| character.is_ascii_lowercase()) {
+      TokenKind::Keyword(text)
+    } else {
+      TokenKind::Reserved(text)
+    };
+    Token::new(kind, range)
   }
 
+  // https://webassembly.github.io/spec/core/text/values.html#strings
   fn read_string(&mut self) -> Token {
     self.consume_expect("\"");
-    let text = self.read_while(|c| c != '"');
-    self.consume_expect("\"");
+    let mut bytes = vec![];
+    let mut valid = true;
+    loop {
+      // a string can't span lines, so an unterminated one stops at the end of its line
+      if self.is_end() || self.peek_one() == '\n' {
+        self.add_error(LexicalError::UnterminatedString { range: self.create_range() });
+        return self.create_error_token();
+      }
+      let character_start = self.cursor;
+      match self.consume_char() {
+        '"' => break,
+        '\\' => valid &= self.read_escape(character_start, &mut bytes),
+        character if character < ' ' || character == '\u{7f}' => {
+          let range = Range::new(character_start, self.cursor);
+          self.add_error(LexicalError::ControlCharacter { character, range });
+          valid = false;
+        }
+        character => bytes.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes()),
+      }
+    }
+    if !valid {
+      return self.create_error_token();
+    }
     let range = self.create_range();
-    Token::new_string(range, text)
+    Token::new_string(range, bytes)
+  }
+
+  // `\t`, `\n`, `\r`, `\"`, `\'`, `\\`, `\u{hexnum}` or two hex digits for a raw byte, `false` if invalid
+  fn read_escape(&mut self, start: usize, bytes: &mut Vec<u8>) -> bool {
+    let character = self.consume_char();
+    let byte = match character {
+      't' => b'\t',
+      'n' => b'\n',
+      'r' => b'\r',
+      '"' => b'"',
+      '\'' => b'\'',
+      '\\' => b'\\',
+      'u' => {
+        let Some(unicode) = self.read_unicode_escape(start) else {
+          return false;
+        };
+        bytes.extend_from_slice(unicode.encode_utf8(&mut [0; 4]).as_bytes());
+        return true;
+      }
+      high if high.is_ascii_hexdigit() && self.peek_one().is_ascii_hexdigit() => {
+        let low = self.consume_char();
+        (high.to_digit(16).unwrap() * 16 + low.to_digit(16).unwrap()) as u8
+      }
+      _ => {
+        let range = Range::new(start, self.cursor);
+        self.add_error(LexicalError::UnknownEscape { escape: self.raw[start..self.cursor].to_string(), range });
+        return false;
+      }
+    };
+    bytes.push(byte);
+    true
+  }
+
+  // `{hexnum}` after `\u`, the value must be a unicode scalar value
+  fn read_unicode_escape(&mut self, start: usize) -> Option<char> {
+    let mut digits = String::new();
+    let mut closed = false;
+    if self.peek_one() == '{' {
+      self.advance_one();
+      digits = self.read_while(|c| c.is_ascii_hexdigit() || c == '_');
+      closed = self.peek_one() == '}';
+      if closed {
+        self.advance_one();
+      }
+    }
+    let valid_digits =
+      !digits.is_empty() && !digits.starts_with('_') && !digits.ends_with('_') && !digits.contains("__");
+    let value = u32::from_str_radix(&digits.replace('_', ""), 16).ok().filter(|_| closed && valid_digits);
+    let character = value.and_then(char::from_u32);
+    if character.is_none() {
+      let range = Range::new(start, self.cursor);
+      let escape = self.raw[start..self.cursor].to_string();
+      self.add_error(LexicalError::InvalidUnicodeEscape { escape, range });
+    }
+    character
   }
 
-  fn read_identifier(&mut self) -> Token {
-    let text = self.read_while(|c| c.is_ascii_alphabetic() || c == '_');
+  // the source text of everything read since the token started
+  fn create_error_token(&self) -> Token {
     let range = self.create_range();
-    Token::new_identifier(range, text)
+    Token::new(TokenKind::Error(self.raw[range.start..range.end].to_string()), range)
   }
 
   fn create_range(&self) -> Range {
     Range { start: self.start_cursor, end: self.cursor }
   }
 
+  // callers have already peeked at `expected`
   fn consume_expect(&mut self, expected: &str) {
-    let atual = self.peek_many(expected.len());
-    if atual != expected {
-      let text = format!("expected `{}` but found `{}`", expected, atual);
-      self.advance_many(expected.len());
-      self.report_diagnostic(text, self.create_range());
-    }
+    debug_assert!(self.starts_with(expected));
     self.advance_many(expected.len());
   }
 
@@ -153,7 +247,7 @@ impl<'a> Lexer<'a> {
   }
 
   fn advance_one(&mut self) {
-    self.cursor += 1;
+    self.cursor += self.peek_one().len_utf8();
   }
 
   fn starts_with(&self, expected: &str) -> bool {
@@ -174,17 +268,244 @@ impl<'a> Lexer<'a> {
   }
 
   fn peek_many(&self, count: usize) -> &str {
-    if self.is_end() || self.cursor + count > self.raw.len() {
-      return &self.raw[self.cursor..].chars().as_str();
-    }
-    &self.raw[self.cursor..self.cursor + count].chars().as_str()
+    let rest = &self.raw[self.cursor.min(self.raw.len())..];
+    rest.get(..count).unwrap_or(rest)
   }
 
   fn advance_many(&mut self, count: usize) {
     self.cursor += count;
   }
 
-  fn report_diagnostic(&self, message: String, range: Range) -> ! {
-    report_lexer_diagnostics(&message, &self.raw, range, &self.file_name)
+  fn add_error(&mut self, error: LexicalError) {
+    self.diagnostics.add_lexical_error(error);
+  }
+}
+
+// `1`, `+0x1p3`, `-inf` and `nan:0x1` are numbers, whether they are valid is decided by the parser
+fn is_number(text: &str) -> bool {
+  let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+  let starts_with_digit = unsigned.starts_with(|character: char| character.is_ascii_digit());
+  starts_with_digit || unsigned == "inf" || unsigned == "nan" || unsigned.starts_with("nan:")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // every token up to the end of file, and the messages of the errors found on the way
+  fn lex(text: &str) -> (Vec<TokenKind>, Vec<String>) {
+    let mut lexer = Lexer::new(text, "test.wat");
+    let mut kinds = vec![];
+    loop {
+      let token = lexer.next_token();
+      if token.kind == TokenKind::EOF {
+        break;
+      }
+      kinds.push(token.kind);
+    }
+    let messages = lexer.diagnostics().diagnostics().iter().map(|diagnostic| diagnostic.message.clone()).collect();
+    (kinds, messages)
+  }
+
+  fn string(bytes: &[u8]) -> TokenKind {
+    TokenKind::String(bytes.to_vec())
+  }
+
+  #[test]
+  fn string_escapes() {
+    let cases: [(&str, &[u8]); 6] = [
+      (r#""plain""#, b"plain"),
+      (r#""\t\n\r\"\'\\""#, b"\t\n\r\"'\\"),
+      (r#""\00\ff\7F""#, &[0x00, 0xff, 0x7f]),
+      (r#""\u{41}\u{e9}\u{20AC}""#, "A\u{e9}\u{20ac}".as_bytes()),
+      (r#""\u{1_F600}""#, "\u{1f600}".as_bytes()),
+      (r#""h\u{e9}llo w\u{f6}rld""#, "h\u{e9}llo w\u{f6}rld".as_bytes()),
+    ];
+    for (text, bytes) in cases {
+      assert_eq!(lex(text), (vec![string(bytes)], vec![]), "{}", text);
+    }
+  }
+
+  #[test]
+  fn invalid_string_escapes() {
+    let cases = [
+      (r#""\q""#, "lexical error: unknown escape `\\q`"),
+      (r#""\f""#, "lexical error: unknown escape `\\f`"),
+      (r#""\u{D800}""#, "lexical error: invalid unicode escape `\\u{D800}`"),
+      (r#""\u{110000}""#, "lexical error: invalid unicode escape `\\u{110000}`"),
+      (r#""\u{_41}""#, "lexical error: invalid unicode escape `\\u{_41}`"),
+      (r#""\u41""#, "lexical error: invalid unicode escape `\\u`"),
+      ("\"tab\u{7f}\"", "lexical error: control character `\\u{7f}` in string"),
+    ];
+    for (text, message) in cases {
+      let (kinds, messages) = lex(text);
+      assert!(matches!(kinds[..], [TokenKind::Error(_)]), "{:?}", kinds);
+      assert_eq!(messages, vec![message.to_string()], "{}", text);
+    }
+  }
+
+  #[test]
+  fn names_must_be_valid_utf8() {
+    let names = |text: &str| {
+      let mut parser = crate::parser::Parser::new(Lexer::new(text, "test.wat"));
+      let program = parser.parse_program();
+      let messages: Vec<String> =
+        parser.diagnostics().diagnostics().iter().map(|diagnostic| diagnostic.message.clone()).collect();
+      let exports = program.body.iter().flat_map(|module| module.exports.iter().map(|export| export.name.clone()));
+      (exports.collect::<Vec<_>>(), messages)
+    };
+    let text = r#"(module (func) (export "\e2\82\ac" (func 0)) (export "\u{20ac}\u{20ac}" (func 0)))"#;
+    assert_eq!(
+      names(text),
+      (vec!["\u{20ac}".to_string(), "\u{20ac}\u{20ac}".to_string()], vec![])
+    );
+    for text in [
+      r#"(module (func) (export "\ff" (func 0)))"#,
+      r#"(module (import "m" "\e2\82" (func)))"#,
+    ] {
+      let (_, messages) = names(text);
+      assert_eq!(messages[0], "syntax error:  malformed UTF-8 encoding", "{}", text);
+    }
+  }
+
+  #[test]
+  fn words_are_classified() {
+    let keyword = |text: &str| TokenKind::Keyword(text.to_string());
+    let id = |text: &str| TokenKind::Id(text.to_string());
+    let number = |text: &str| TokenKind::Number(text.to_string());
+    let reserved = |text: &str| TokenKind::Reserved(text.to_string());
+    let text = "(module $m (func $f.x! i32.add memory.grow 0x1F -1.5 +inf nan nan:0x1 1_000 $ Foo =x 0$ a\"b\"))";
+    let expected = vec![
+      TokenKind::LParen,
+      keyword("module"),
+      id("m"),
+      TokenKind::LParen,
+      keyword("func"),
+      id("f.x!"),
+      keyword("i32.add"),
+      keyword("memory.grow"),
+      number("0x1F"),
+      number("-1.5"),
+      number("+inf"),
+      number("nan"),
+      number("nan:0x1"),
+      number("1_000"),
+      reserved("$"),
+      reserved("Foo"),
+      reserved("=x"),
+      number("0$"),
+      keyword("a"),
+      string(b"b"),
+      TokenKind::RParen,
+      TokenKind::RParen,
+    ];
+    assert_eq!(lex(text), (expected, vec![]));
+  }
+
+  #[test]
+  fn memargs_are_their_own_tokens() {
+    let text = "i64.load offset=0x10 align=8 offset= align=x";
+    let expected = vec![
+      TokenKind::Keyword("i64.load".to_string()),
+      TokenKind::Offset("0x10".to_string()),
+      TokenKind::Align("8".to_string()),
+      TokenKind::Offset(String::new()),
+      TokenKind::Align("x".to_string()),
+    ];
+    assert_eq!(lex(text), (expected, vec![]));
+  }
+
+  #[test]
+  fn block_comments_nest() {
+    let comment = |text: &str| TokenKind::Comment(text.to_string());
+    let text = "(; a (; b ;) c ;) x ;; line\ny (;;) z";
+    let expected = vec![
+      comment(" a (; b ;) c "),
+      TokenKind::Keyword("x".to_string()),
+      comment(" line"),
+      TokenKind::Keyword("y".to_string()),
+      comment(""),
+      TokenKind::Keyword("z".to_string()),
+    ];
+    assert_eq!(lex(text), (expected, vec![]));
+    let (kinds, messages) = lex("x (; (; ;)");
+    assert_eq!(
+      kinds,
+      [
+        TokenKind::Keyword("x".to_string()),
+        TokenKind::Error("(; (; ;)".to_string())
+      ]
+    );
+    assert_eq!(messages, ["lexical error: unterminated block comment"]);
+  }
+
+  #[test]
+  fn annotations_start_with_their_name() {
+    let text = "(@custom \"x\" (a (@b))) y";
+    let expected = vec![
+      TokenKind::Annotation("custom".to_string()),
+      string(b"x"),
+      TokenKind::LParen,
+      TokenKind::Keyword("a".to_string()),
+      TokenKind::Annotation("b".to_string()),
+      TokenKind::RParen,
+      TokenKind::RParen,
+      TokenKind::RParen,
+      TokenKind::Keyword("y".to_string()),
+    ];
+    assert_eq!(lex(text), (expected, vec![]));
+    let (kinds, messages) = lex("(@ x)");
+    assert_eq!(kinds[0], TokenKind::Annotation(String::new()));
+    assert_eq!(messages, ["lexical error: missing annotation name"]);
+  }
+
+  #[test]
+  fn annotations_are_set_aside_by_the_parser() {
+    let text = "(module (@name \"m\") (func (@hint (nested)) nop) (@custom))";
+    let mut parser = crate::parser::Parser::new(Lexer::new(text, "test.wat"));
+    let program = parser.parse_program();
+    assert!(!parser.diagnostics().has_errors());
+    let module = &program.body[0];
+    let names: Vec<&str> = module.annotations.iter().map(|annotation| annotation.name.as_str()).collect();
+    assert_eq!(names, ["name", "hint", "custom"]);
+    assert_eq!(module.annotations[1].tokens.len(), 3);
+    assert_eq!(module.functions[0].body.len(), 1);
+  }
+
+  #[test]
+  fn lexing_goes_on_after_errors() {
+    let keyword = |text: &str| TokenKind::Keyword(text.to_string());
+    let error = |text: &str| TokenKind::Error(text.to_string());
+    let text = "a [ b \"x\\qy\" c \u{e9} \"open\nd";
+    let expected = vec![
+      keyword("a"),
+      error("["),
+      keyword("b"),
+      error("\"x\\qy\""),
+      keyword("c"),
+      error("\u{e9}"),
+      error("\"open"),
+      keyword("d"),
+    ];
+    let messages = vec![
+      "lexical error: unknown character `[`".to_string(),
+      "lexical error: unknown escape `\\q`".to_string(),
+      "lexical error: unknown character `\u{e9}`".to_string(),
+      "lexical error: unterminated string".to_string(),
+    ];
+    assert_eq!(lex(text), (expected, messages));
+    let mut lexer = Lexer::new(text, "test.wat");
+    lexer.next_token();
+    assert_eq!(lexer.next_token().range, Range::new(2, 3));
+  }
+
+  #[test]
+  fn error_tokens_are_not_reported_again() {
+    let mut parser = crate::parser::Parser::new(Lexer::new("(module (func nop [ nop))", "test.wat"));
+    let program = parser.parse_program();
+    let messages: Vec<String> =
+      parser.diagnostics().diagnostics().iter().map(|diagnostic| diagnostic.message.clone()).collect();
+    assert_eq!(messages, ["lexical error: unknown character `[`"]);
+    assert_eq!(program.body[0].functions[0].body.len(), 2);
   }
 }
@@ -4,48 +4,43 @@ use serde::{Deserialize, Serialize};
 use crate::utils::range::Range;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[allow(clippy::upper_case_acronyms)] // `EOF`
 pub enum TokenKind {
   LParen,             // '('
   RParen,             // ')'
-  LBrace,             // '{'
-  RBrace,             // '}'
-  LBracket,           // '['
-  RBracket,           // ']'
-  Comma,              // ','
-  Dot,                // '.'
-  Plus,               // '+'
-  Minus,              // '-'
-  Star,               // '*'
-  Slash,              // '/'
-  Percent,            // '%'
-  Caret,              // '^'
-  Ampersand,          // '&'
-  Bar,                // '|'
-  Tilde,              // '~'
-  Equal,              // '='
-  Exclamation,        // '!'
-  LessThan,           // '<'
-  GreaterThan,        // '>'
-  LessThanEqual,      // '<='
-  GreaterThanEqual,   // '>='
-  NotEqual,           // '!='
-  And,                // '&&'
-  Or,                 // '||'
-  Question,           // '?'
-  Colon,              // ':'
-  Semicolon,          // ';'
-  Arrow,              // '->'
-  At,                 // '@'
-  Hash,               // '#'
-  Backslash,          // '\\'
-  Dollar,             // '$'
-  String(String),     // string
-  Identifier(String), // identifier
-  Number(String),     // number
+  Keyword(String),    // `module`, `i32.add`, `memory.grow`
+  Id(String),         // `$name`, stored without the `$`
+  Number(String),     // integer or float literal, converted by the parser
+  String(Vec<u8>),    // string, with escapes decoded
+  Offset(String),     // `offset=N`
+  Align(String),      // `align=N`
+  Reserved(String),   // any other run of idchars
+  Annotation(String), // `(@name`, stored without the `(@`
   Comment(String),    // comment
+  Error(String),      // malformed source text, already reported to the diagnostics
   EOF,                // end of file
 }
 
+impl std::fmt::Display for TokenKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      TokenKind::LParen => write!(f, "("),
+      TokenKind::RParen => write!(f, ")"),
+      TokenKind::Keyword(keyword) => write!(f, "{}", keyword),
+      TokenKind::Id(name) => write!(f, "${}", name),
+      TokenKind::Number(number) => write!(f, "{}", number),
+      TokenKind::String(bytes) => write!(f, "\"{}\"", String::from_utf8_lossy(bytes)),
+      TokenKind::Offset(value) => write!(f, "offset={}", value),
+      TokenKind::Align(value) => write!(f, "align={}", value),
+      TokenKind::Reserved(text) => write!(f, "{}", text),
+      TokenKind::Annotation(name) => write!(f, "(@{}", name),
+      TokenKind::Comment(_) => write!(f, "comment"),
+      TokenKind::Error(text) => write!(f, "{}", text),
+      TokenKind::EOF => write!(f, "end of file"),
+    }
+  }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Token {
   pub kind: TokenKind,
@@ -57,12 +52,12 @@ impl Token {
     Self { kind, range }
   }
 
-  pub fn new_string(range: Range, string: String) -> Self {
-    Self { kind: TokenKind::String(string), range }
+  pub fn new_string(range: Range, bytes: Vec<u8>) -> Self {
+    Self { kind: TokenKind::String(bytes), range }
   }
 
-  pub fn new_identifier(range: Range, identifier: String) -> Self {
-    Self { kind: TokenKind::Identifier(identifier), range }
+  pub fn new_keyword(range: Range, keyword: String) -> Self {
+    Self { kind: TokenKind::Keyword(keyword), range }
   }
 
   pub fn new_number(range: Range, number: String) -> Self {
@@ -1,11 +1,14 @@
-use lexer::tokens::TokenKind;
+use std::io::Read;
 
 mod bytes;
 mod cli;
+mod compiler;
 mod diagnostics;
 mod lexer;
 mod parser;
+mod runtime;
 mod utils;
+mod validator;
 
 fn main() {
   let matches = cli::command_line();
@@ -16,52 +19,273 @@ fn main() {
     }
     Some(("compile", matches)) => {
       let path_name = matches.get_one::<String>("file").unwrap();
-      compile_wasm(path_name);
+      let output = match matches.get_one::<String>("output") {
+        Some(output) => output.clone(),
+        None => std::path::Path::new(path_name).with_extension("wasm").to_string_lossy().into_owned(),
+      };
+      let module_name = matches.get_one::<String>("module");
+      compile_wasm(path_name, &output, module_name.map(String::as_str));
     }
     Some(("run", matches)) => {
       let path_name = matches.get_one::<String>("file").unwrap();
-      run_wasm(path_name);
+      let module_name = matches.get_one::<String>("module");
+      let invoke = matches.get_one::<String>("invoke");
+      let args: Vec<&str> = matches.get_many::<String>("args").unwrap_or_default().map(String::as_str).collect();
+      run_wasm(
+        path_name,
+        module_name.map(String::as_str),
+        invoke.map(String::as_str),
+        &args,
+      );
     }
     _ => {}
   }
 }
 
-fn run_wasm(file_name: &str) {
-  let contents = std::fs::read_to_string(file_name).unwrap();
-  let mut lexer = lexer::Lexer::new(&contents, file_name);
-  // let mut parser = parser::Parser::new(lexer);
-  // parser.parse_program().unwrap();
-  loop {
-    let token = lexer.next_token();
-    println!("{:#?}", token);
-    if token.kind == TokenKind::EOF {
-      break;
+// instantiates the module and prints the results of the `invoke`d export, one per line
+fn run_wasm(file_name: &str, module_name: Option<&str>, invoke: Option<&str>, args: &[&str]) {
+  let bytes = std::fs::read(file_name).unwrap();
+  // text modules run as compiled, so errors point at the instruction text instead of an offset
+  let contents = (!bytes.starts_with(b"\0asm")).then(|| read_text(&bytes, file_name));
+  let report = |diagnostic: diagnostics::Diagnostic| -> ! {
+    match &contents {
+      Some(contents) => diagnostics::report_diagnostic(&diagnostic, contents, file_name),
+      None => diagnostics::report_bytes_diagnostic(&diagnostic, &bytes, file_name),
     }
+    std::process::exit(1);
+  };
+  let module = match &contents {
+    Some(contents) => compile_text(contents, file_name, module_name),
+    None => bytes::module::Module::new(&bytes).unwrap_or_else(|diagnostic| report(diagnostic)),
+  };
+  let instance =
+    runtime::Instance::new(module, &runtime::Imports::new()).unwrap_or_else(|diagnostic| report(diagnostic));
+  let Some(name) = invoke else {
+    return;
+  };
+  let args = parse_args(&instance, name, args).unwrap_or_else(|diagnostic| report(diagnostic));
+  let results = instance.invoke(name, &args).unwrap_or_else(|diagnostic| report(diagnostic));
+  for result in results {
+    println!("{}", result);
+  }
+}
+
+// command line arguments take the types of the parameters of the function
+fn parse_args(
+  instance: &runtime::Instance,
+  name: &str,
+  args: &[&str],
+) -> diagnostics::ResultWithDiagnostics<Vec<runtime::Value>> {
+  let Some(func_idx) = instance.export_function(name) else {
+    return Err(diagnostics::RuntimeError::UnknownFunction { name: name.to_string(), range: None }.into());
+  };
+  let params = &instance.func_type(func_idx).params;
+  if params.len() != args.len() {
+    let expected = format!("{} arguments", params.len());
+    let error = diagnostics::RuntimeError::TypeMismatch { expected, found: args.len().to_string(), range: None };
+    return Err(error.into());
   }
+  let values = args.iter().zip(params).map(|(arg, value_type)| {
+    runtime::Value::parse(arg, *value_type).ok_or_else(|| {
+      let found = arg.to_string();
+      diagnostics::RuntimeError::TypeMismatch { expected: value_type.to_string(), found, range: None }.into()
+    })
+  });
+  values.collect()
+}
+
+fn compile_wasm(file_name: &str, output: &str, module_name: Option<&str>) {
+  let bytes = std::fs::read(file_name).unwrap();
+  let binary = compile_text(read_text(&bytes, file_name), file_name, module_name);
+  std::fs::write(output, binary.encode()).unwrap();
+}
+
+// the contents of a text module, exiting if the file is neither a binary nor text module
+fn read_text<'b>(bytes: &'b [u8], file_name: &str) -> &'b str {
+  text_module(bytes, file_name).unwrap_or_else(|message| {
+    println!("{}", utils::highlight_red(&message));
+    std::process::exit(1);
+  })
 }
 
-fn compile_wasm(file_name: &str) {
-  let contents = std::fs::read_to_string(file_name).unwrap();
-  let mut lexer = lexer::Lexer::new(&contents, file_name);
-  // let mut parser = parser::Parser::new(lexer);
-  // parser.parse_program().unwrap();
+fn text_module<'b>(bytes: &'b [u8], file_name: &str) -> Result<&'b str, String> {
+  std::str::from_utf8(bytes).map_err(|_| format!("ERROR: `{}` is not a wasm binary or UTF-8 text module", file_name))
+}
+
+// lowers the module called `module_name` of a text file, or its first module, exiting on any error
+fn compile_text(contents: &str, file_name: &str, module_name: Option<&str>) -> bytes::module::Module {
+  let lexer = lexer::Lexer::new(contents, file_name);
+  let mut parser = parser::Parser::new(lexer);
+  let mut program = parser.parse_program();
+  let diagnostics = parser.diagnostics();
+  if !diagnostics.has_errors() {
+    parser::resolve_program(diagnostics, &mut program);
+  }
+  if diagnostics.has_errors() {
+    diagnostics.report();
+    std::process::exit(1);
+  }
+  let module = select_module(&program, module_name, file_name).unwrap_or_else(|message| {
+    println!("{}", utils::highlight_red(&message));
+    std::process::exit(1);
+  });
+  let Some(binary) = lower_module(module, diagnostics) else {
+    diagnostics.report();
+    std::process::exit(1);
+  };
+  binary
+}
+
+// the module called `module_name`, with or without its `$`, or the first module of the file
+fn select_module<'p>(
+  program: &'p parser::ast::Program,
+  module_name: Option<&str>,
+  file_name: &str,
+) -> Result<&'p parser::ast::Module, String> {
+  let module = match module_name {
+    Some(name) => program.find_module(name),
+    None => program.body.first(),
+  };
+  module.ok_or_else(|| match module_name {
+    Some(name) => format!("ERROR: no module named `{}` in `{}`", name, file_name),
+    None => format!("ERROR: no module found in `{}`", file_name),
+  })
+}
+
+// compiles and validates a resolved text module, its errors are added to `diagnostics`
+fn lower_module(
+  module: &parser::ast::Module,
+  diagnostics: &mut diagnostics::DiagnosticManager,
+) -> Option<bytes::module::Module> {
+  let binary = compiler::Compiler::new(module).compile();
+  match binary.and_then(|binary| validator::Validator::new(&binary).validate().map(|_| binary)) {
+    Ok(binary) => Some(binary),
+    Err(diagnostic) => {
+      diagnostics.add(diagnostic);
+      None
+    }
+  }
+}
+
+// reads a binary module from stdin, decoding each chunk as soon as it arrives
+fn check_wasm_stream() {
+  let mut decoder = bytes::stream::StreamingDecoder::new();
+  let mut chunk = vec![0; 64 * 1024];
   loop {
-    let token = lexer.next_token();
-    println!("{:#?}", token);
-    if token.kind == TokenKind::EOF {
+    let size = std::io::stdin().read(&mut chunk).unwrap();
+    if size == 0 {
       break;
     }
+    if let Err(diagnostic) = decoder.push(&chunk[..size]) {
+      diagnostics::report_bytes_diagnostic(&diagnostic, decoder.bytes(), "<stdin>");
+      std::process::exit(1);
+    }
+  }
+  let bytes = decoder.bytes().to_vec();
+  match decoder.finish().and_then(|module| validator::Validator::new(&module).validate().map(|_| module)) {
+    Ok(module) => println!("{:#?}", module),
+    Err(diagnostic) => {
+      diagnostics::report_bytes_diagnostic(&diagnostic, &bytes, "<stdin>");
+      std::process::exit(1);
+    }
   }
 }
 
 fn check_wasm(file_name: &str) {
-  let contents = std::fs::read_to_string(file_name).unwrap();
-  let mut lexer = lexer::Lexer::new(&contents, file_name);
-  loop {
-    let token = lexer.next_token();
-    println!("{:#?}", token);
-    if token.kind == TokenKind::EOF {
-      break;
+  if file_name == "-" {
+    return check_wasm_stream();
+  }
+  let bytes = std::fs::read(file_name).unwrap();
+  if bytes.starts_with(b"\0asm") {
+    let module = bytes::module::Module::new(&bytes);
+    let module = module.and_then(|module| module.decode_function_bodies().map(|_| module));
+    match module.and_then(|module| validator::Validator::new(&module).validate().map(|_| module)) {
+      Ok(module) => println!("{:#?}", module),
+      Err(diagnostic) => {
+        diagnostics::report_bytes_diagnostic(&diagnostic, &bytes, file_name);
+        std::process::exit(1);
+      }
+    }
+    return;
+  }
+  let contents = read_text(&bytes, file_name);
+  let lexer = lexer::Lexer::new(contents, file_name);
+  let mut parser = parser::Parser::new(lexer);
+  let mut program = parser.parse_program();
+  // every lexical and syntax error of the file, not just the first one
+  let diagnostics = parser.diagnostics();
+  if !diagnostics.has_errors() {
+    parser::resolve_program(diagnostics, &mut program);
+  }
+  if !diagnostics.has_errors() {
+    for module in &program.body {
+      lower_module(module, diagnostics);
     }
   }
+  if diagnostics.has_errors() {
+    diagnostics.report();
+    std::process::exit(1);
+  }
+  println!("{:#?}", program);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the diagnostics of parsing and resolving a whole file
+  fn parse(text: &str) -> (parser::ast::Program, Vec<String>) {
+    let mut parser = parser::Parser::new(lexer::Lexer::new(text, "test.wat"));
+    let mut program = parser.parse_program();
+    parser::resolve_program(parser.diagnostics(), &mut program);
+    let messages = parser.diagnostics().diagnostics().iter().map(|diagnostic| diagnostic.message.clone()).collect();
+    (program, messages)
+  }
+
+  fn selected(text: &str, module_name: Option<&str>) -> Result<Vec<u32>, String> {
+    let (program, messages) = parse(text);
+    assert_eq!(messages, Vec::<String>::new());
+    let module = select_module(&program, module_name, "test.wat")?;
+    let mut diagnostics = diagnostics::DiagnosticManager::new(text, "test.wat");
+    let binary = lower_module(module, &mut diagnostics).unwrap();
+    Ok(binary.function_section.unwrap_or_default())
+  }
+
+  const MODULES: &str = r#"
+    (module $first (func))
+    (module (func) (func))
+    (module $third (type (func)) (type (func (param i32))) (func (param i32)) (func) (func (param i32)))"#;
+
+  #[test]
+  fn modules_are_selected_by_name() {
+    assert_eq!(selected(MODULES, None), Ok(vec![0]));
+    assert_eq!(selected(MODULES, Some("third")), Ok(vec![1, 0, 1]));
+    assert_eq!(selected(MODULES, Some("$third")), Ok(vec![1, 0, 1]));
+    assert_eq!(selected(MODULES, Some("$first")), Ok(vec![0]));
+  }
+
+  #[test]
+  fn no_module_matches() {
+    let error = "ERROR: no module named `second` in `test.wat`".to_string();
+    assert_eq!(selected(MODULES, Some("second")), Err(error));
+    let error = "ERROR: no module named `$$third` in `test.wat`".to_string();
+    assert_eq!(selected(MODULES, Some("$$third")), Err(error));
+    let error = "ERROR: no module found in `test.wat`".to_string();
+    assert_eq!(selected(";; nothing here", None), Err(error));
+  }
+
+  #[test]
+  fn module_ids_are_unique_in_a_file() {
+    let (program, messages) = parse("(module $m (func $f)) (module $m (func $f)) (module $n (func $f))");
+    assert_eq!(messages, ["duplicate module `$m`"]);
+    // the first one wins, and function ids are per module
+    assert!(std::ptr::eq(program.find_module("m").unwrap(), &program.body[0]));
+  }
+
+  #[test]
+  fn files_without_the_magic_must_be_utf8() {
+    assert_eq!(text_module(b"(module)", "a.wat"), Ok("(module)"));
+    let message = "ERROR: `a.wasm` is not a wasm binary or UTF-8 text module";
+    assert_eq!(text_module(b"\0wasm\xff", "a.wasm"), Err(message.to_string()));
+  }
 }
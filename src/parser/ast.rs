@@ -1,43 +1,57 @@
 use serde::{Deserialize, Serialize};
 
+use crate::bytes::instructions::{MemoryOp, NumericOp};
+use crate::lexer::tokens::Token;
 use crate::utils::range::Range;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Program {
   pub body: Vec<Module>,
 }
 
-impl Default for Program {
-  fn default() -> Self {
-    Self { body: vec![] }
+impl Program {
+  // `name` with or without its `$`
+  pub fn find_module(&self, name: &str) -> Option<&Module> {
+    let name = name.strip_prefix('$').unwrap_or(name);
+    self.body.iter().find(|module| module.id.as_ref().is_some_and(|id| id.name == name))
   }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Module {
-  pub types: Type,
-  pub imports: Import,
-  pub functions: Function,
-  pub tables: Table,
-  pub memories: Memory,
-  pub globals: Global,
-  pub exports: Export,
+  pub id: Option<Identifier>,
+  pub types: Vec<Type>,
+  pub imports: Vec<Import>,
+  pub functions: Vec<Function>,
+  pub tables: Vec<Table>,
+  pub memories: Vec<Memory>,
+  pub globals: Vec<Global>,
+  pub exports: Vec<Export>,
   pub start: Option<Start>,
-  pub elements: Element,
-  pub codes: Code,
-  pub data: Data,
+  pub elements: Vec<Element>,
+  pub data: Vec<Data>,
+  pub annotations: Vec<Annotation>,
+  pub range: Range,
+}
+
+// `(@name ...)`, kept as raw tokens for tools that understand them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Annotation {
+  pub name: String,
+  pub tokens: Vec<Token>,
+  pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Type {
-  pub form: String,
-  pub params: Vec<ValueType>,
-  pub results: Vec<ValueType>,
+  pub id: Option<Identifier>,
+  pub signature: Signature,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Import {
+  pub id: Option<Identifier>,
   pub module: String,
   pub name: String,
   pub desc: ImportDesc,
@@ -46,54 +60,109 @@ pub struct Import {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ImportDesc {
-  Func(u32),
+  Func(TypeUse),
   Table(TableType),
   Mem(MemoryType),
   Global(GlobalType),
 }
 
+impl ImportDesc {
+  // the keyword of the imported item
+  pub fn kind(&self) -> &'static str {
+    match self {
+      ImportDesc::Func(_) => "func",
+      ImportDesc::Table(_) => "table",
+      ImportDesc::Mem(_) => "memory",
+      ImportDesc::Global(_) => "global",
+    }
+  }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Function {
-  pub name: Identifier,
-  pub signature: Signature,
-  pub body: BlockInstr,
+  pub id: Option<Identifier>,
+  pub type_use: TypeUse,
+  pub locals: Vec<Local>,
+  pub body: Vec<Instr>,
   pub range: Range,
 }
 
+// `(type $t)` and/or inline params and results, at least one of them is present
 #[derive(Debug, Serialize, Deserialize)]
+pub struct TypeUse {
+  pub index: Option<Index>,
+  pub signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Signature {
-  pub params: Vec<ValueType>,
+  pub params: Vec<Param>,
   pub results: Vec<ValueType>,
   pub range: Range,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// `[i32] -> [i32]`, like the signature of a decoded function type
+impl std::fmt::Display for Signature {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let params: Vec<String> = self.params.iter().map(|param| param.value_type.to_string()).collect();
+    let results: Vec<String> = self.results.iter().map(ValueType::to_string).collect();
+    write!(f, "[{}] -> [{}]", params.join(" "), results.join(" "))
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Param {
+  pub id: Option<Identifier>,
+  pub value_type: ValueType,
+  pub range: Range,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Identifier {
   pub name: String,
   pub range: Range,
 }
 
+// a reference to an item, either by position or by its `$id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Index {
+  Numeric { value: u32, range: Range },
+  Symbolic(Identifier),
+}
+
+impl Index {
+  pub fn range(&self) -> &Range {
+    match self {
+      Index::Numeric { range, .. } => range,
+      Index::Symbolic(identifier) => &identifier.range,
+    }
+  }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Local {
-  pub count: u32,
+  pub id: Option<Identifier>,
   pub value_type: ValueType,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Table {
+  pub id: Option<Identifier>,
   pub table_type: TableType,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Memory {
+  pub id: Option<Identifier>,
   pub memory_type: MemoryType,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Global {
+  pub id: Option<Identifier>,
   pub global_type: GlobalType,
   pub init: Vec<Instr>,
   pub range: Range,
@@ -108,47 +177,56 @@ pub struct Export {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ExportDesc {
-  Func(u32),
-  Table(u32),
-  Mem(u32),
-  Global(u32),
+  Func(Index),
+  Table(Index),
+  Mem(Index),
+  Global(Index),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Start {
-  pub func_idx: u32,
+  pub function: Index,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Element {
-  pub table: u32,
+  pub table: Index,
   pub offset: Vec<Instr>,
-  pub init: Vec<u32>,
-  pub range: Range,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Code {
-  pub locals: Vec<Local>,
-  pub body: Vec<Instr>,
+  pub init: Vec<Index>,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Data {
-  pub data: u32,
+  pub memory: Index,
   pub offset: Vec<Instr>,
   pub init: Vec<u8>,
   pub range: Range,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ValueType {
   I32,
   I64,
   F32,
   F64,
+  FuncRef,
+  ExternRef,
+}
+
+impl std::fmt::Display for ValueType {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let name = match self {
+      ValueType::I32 => "i32",
+      ValueType::I64 => "i64",
+      ValueType::F32 => "f32",
+      ValueType::F64 => "f64",
+      ValueType::FuncRef => "funcref",
+      ValueType::ExternRef => "externref",
+    };
+    write!(f, "{}", name)
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -192,6 +270,9 @@ pub enum Instr {
   // Function calls
   Call(CallInstr),
   CallIndirect(CallIndirectInstr),
+  // Parametric instr
+  Drop { range: Range },
+  Select { range: Range },
   // Variable instr
   LocalGet(VariableInstr),
   LocalSet(VariableInstr),
@@ -199,32 +280,62 @@ pub enum Instr {
   GlobalGet(VariableInstr),
   GlobalSet(VariableInstr),
   // Memory instr
-  I32Load(MemInstr),
-  I32Store(MemInstr),
-  I64Load(MemInstr),
-  I64Store(MemInstr),
-  F32Load(MemInstr),
-  F32Store(MemInstr),
-  F64Load(MemInstr),
-  F64Store(MemInstr),
+  Memory(MemInstr),
+  MemorySize { range: Range },
+  MemoryGrow { range: Range },
   // Constants
   I32Const { value: i32, range: Range },
   I64Const { value: i64, range: Range },
-  F32Const { value: f32, range: Range },
-  F64Const { value: f64, range: Range },
+  F32Const { bits: u32, range: Range }, // raw bits, NaN payloads are kept as written
+  F64Const { bits: u64, range: Range },
   // Numeric operations
-  I32Add { range: Range },
-  I32Sub { range: Range },
+  Numeric(NumericInstr),
+}
+
+impl Instr {
+  pub fn range(&self) -> &Range {
+    match self {
+      Instr::Unreachable { range }
+      | Instr::Nop { range }
+      | Instr::Return { range }
+      | Instr::Drop { range }
+      | Instr::Select { range }
+      | Instr::MemorySize { range }
+      | Instr::MemoryGrow { range }
+      | Instr::I32Const { range, .. }
+      | Instr::I64Const { range, .. }
+      | Instr::F32Const { range, .. }
+      | Instr::F64Const { range, .. } => range,
+      Instr::Block(block) => &block.range,
+      Instr::Loop(block) => &block.range,
+      Instr::If(block) => &block.range,
+      Instr::Branch(branch) => &branch.range,
+      Instr::BranchIf(branch) => &branch.range,
+      Instr::BranchTable(branch) => &branch.range,
+      Instr::Call(call) => &call.range,
+      Instr::CallIndirect(call) => &call.range,
+      Instr::LocalGet(variable)
+      | Instr::LocalSet(variable)
+      | Instr::LocalTee(variable)
+      | Instr::GlobalGet(variable)
+      | Instr::GlobalSet(variable) => &variable.range,
+      Instr::Memory(memory) => &memory.range,
+      Instr::Numeric(numeric) => &numeric.range,
+    }
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BlockInstr {
+  pub label: Option<Identifier>,
+  pub block_type: Option<ValueType>,
   pub instr: Vec<Instr>,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoopInstr {
+  pub label: Option<Identifier>,
   pub loop_type: Option<ValueType>,
   pub instr: Vec<Instr>,
   pub range: Range,
@@ -232,7 +343,9 @@ pub struct LoopInstr {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct IfInstr {
-  pub condition: Box<Instr>,
+  pub label: Option<Identifier>,
+  pub block_type: Option<ValueType>,
+  pub condition: Vec<Instr>, // folded `(if (condition) (then ...))` only
   pub instr: Vec<Instr>,
   pub else_instr: Option<Vec<Instr>>,
   pub range: Range,
@@ -240,50 +353,51 @@ pub struct IfInstr {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BranchInstr {
-  pub label_idx: u32,
+  pub label: Index,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BranchIfInstr {
-  pub label_idx: u32,
+  pub label: Index,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BranchTableInstr {
-  pub labels: Vec<u32>,
-  pub default: u32,
+  pub labels: Vec<Index>,
+  pub default: Index,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CallInstr {
-  pub function_idx: u32,
+  pub function: Index,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CallIndirectInstr {
-  pub type_idx: u32,
+  pub type_use: TypeUse,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MemInstr {
+  pub op: MemoryOp,
   pub offset: u32,
-  pub align: u32,
+  pub align: u32, // in bytes, as written in `align=`
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VariableInstr {
-  pub index: u32,
+  pub index: Index,
   pub range: Range,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub enum NumericInstr {
-  I32Add { range: Range },
-  I32Sub { range: Range },
+pub struct NumericInstr {
+  pub op: NumericOp,
+  pub range: Range,
 }
@@ -0,0 +1,389 @@
+// https://webassembly.github.io/spec/core/text/values.html#integers
+// https://webassembly.github.io/spec/core/text/values.html#floating-point
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiteralError {
+  Malformed,
+  OutOfRange,
+}
+
+pub type Result<T> = std::result::Result<T, LiteralError>;
+
+fn split_sign(text: &str) -> (Option<char>, &str) {
+  match text.chars().next() {
+    Some(sign @ ('+' | '-')) => (Some(sign), &text[1..]),
+    _ => (None, text),
+  }
+}
+
+// digits with optional single `_` between them, `None` for an empty or malformed sequence
+fn strip_underscores(text: &str, radix: u32) -> Option<String> {
+  let mut digits = String::new();
+  let mut previous_is_digit = false;
+  for character in text.chars() {
+    if character == '_' {
+      if !previous_is_digit {
+        return None;
+      }
+      previous_is_digit = false;
+      continue;
+    }
+    if !character.is_digit(radix) {
+      return None;
+    }
+    digits.push(character);
+    previous_is_digit = true;
+  }
+  if !previous_is_digit {
+    return None;
+  }
+  Some(digits)
+}
+
+// uN, decimal or `0x` hexadecimal, never signed
+fn parse_unsigned(text: &str) -> Result<u128> {
+  let (digits, radix) = match text.strip_prefix("0x") {
+    Some(hex) => (hex, 16),
+    None => (text, 10),
+  };
+  let digits = strip_underscores(digits, radix).ok_or(LiteralError::Malformed)?;
+  u128::from_str_radix(&digits, radix).map_err(|_| LiteralError::OutOfRange)
+}
+
+pub fn parse_u32(text: &str) -> Result<u32> {
+  let value = parse_unsigned(text)?;
+  u32::try_from(value).map_err(|_| LiteralError::OutOfRange)
+}
+
+// iN accepts unsigned values up to 2^N-1 and signed values from -2^(N-1) to 2^(N-1)-1, as the same bits
+fn parse_integer(text: &str, bits: u32) -> Result<u64> {
+  let (sign, unsigned) = split_sign(text);
+  let magnitude = parse_unsigned(unsigned)?;
+  let mask = u64::MAX >> (64 - bits);
+  match sign {
+    None if magnitude <= mask as u128 => Ok(magnitude as u64),
+    Some('+') if magnitude < 1 << (bits - 1) => Ok(magnitude as u64),
+    Some('-') if magnitude <= 1 << (bits - 1) => Ok((magnitude as u64).wrapping_neg() & mask),
+    _ => Err(LiteralError::OutOfRange),
+  }
+}
+
+pub fn parse_i32(text: &str) -> Result<i32> {
+  Ok(parse_integer(text, 32)? as u32 as i32)
+}
+
+pub fn parse_i64(text: &str) -> Result<i64> {
+  Ok(parse_integer(text, 64)? as i64)
+}
+
+// layout of a binary floating point format
+struct FloatFormat {
+  mantissa_bits: u32,
+  exponent_bits: u32,
+}
+
+const F32: FloatFormat = FloatFormat { mantissa_bits: 23, exponent_bits: 8 };
+const F64: FloatFormat = FloatFormat { mantissa_bits: 52, exponent_bits: 11 };
+
+impl FloatFormat {
+  fn bias(&self) -> i64 {
+    (1 << (self.exponent_bits - 1)) - 1
+  }
+
+  fn sign_bit(&self) -> u64 {
+    1 << (self.mantissa_bits + self.exponent_bits)
+  }
+
+  fn infinity(&self) -> u64 {
+    ((1 << self.exponent_bits) - 1) << self.mantissa_bits
+  }
+}
+
+// shifts right by `shift`, rounding to nearest with ties to even
+fn shift_round(value: u128, shift: u32, sticky: bool) -> u128 {
+  if shift == 0 {
+    return value;
+  }
+  if shift > 128 {
+    return 0;
+  }
+  let (quotient, remainder, half) = match shift {
+    128 => (0, value, 1 << 127),
+    _ => (value >> shift, value & ((1 << shift) - 1), 1 << (shift - 1)),
+  };
+  let round_up = remainder > half || (remainder == half && (sticky || quotient & 1 == 1));
+  quotient + round_up as u128
+}
+
+// the bits of `significand * 2^exponent`, `None` when it rounds to infinity
+fn round_to_format(significand: u128, exponent: i64, sticky: bool, format: &FloatFormat) -> Option<u64> {
+  if significand == 0 {
+    return Some(0);
+  }
+  let mantissa_bits = format.mantissa_bits as i64;
+  let leading = 127 - significand.leading_zeros() as i64 + exponent;
+  let min_exponent = 1 - format.bias();
+  if leading > format.bias() {
+    return None;
+  }
+  // exponent of the last mantissa bit, subnormals keep the minimum exponent
+  let mut unit = leading.max(min_exponent) - mantissa_bits;
+  let mut quotient = match exponent - unit {
+    shift if shift >= 0 => significand << shift,
+    shift => shift_round(significand, (-shift).min(129) as u32, sticky),
+  };
+  if quotient >> (mantissa_bits + 1) != 0 {
+    quotient >>= 1;
+    unit += 1;
+  }
+  let fraction = quotient as u64 & ((1 << mantissa_bits) - 1);
+  if quotient >> mantissa_bits == 0 {
+    return Some(fraction);
+  }
+  let biased = unit + mantissa_bits + format.bias();
+  if biased >= (1 << format.exponent_bits) - 1 {
+    return None;
+  }
+  Some(((biased as u64) << mantissa_bits) | fraction)
+}
+
+// 0x hexnum (. hexnum?)? (p sign? num)?
+fn parse_hex_float(text: &str, format: &FloatFormat) -> Result<u64> {
+  let (mantissa, exponent) = match text.find(['p', 'P']) {
+    Some(position) => (&text[..position], Some(&text[position + 1..])),
+    None => (text, None),
+  };
+  let (integer, fraction) = match mantissa.split_once('.') {
+    Some((integer, fraction)) => (integer, Some(fraction)),
+    None => (mantissa, None),
+  };
+  let integer = strip_underscores(integer, 16).ok_or(LiteralError::Malformed)?;
+  let fraction = match fraction {
+    Some("") | None => String::new(),
+    Some(fraction) => strip_underscores(fraction, 16).ok_or(LiteralError::Malformed)?,
+  };
+  let mut binary_exponent: i64 = match exponent {
+    Some(exponent) => {
+      let (sign, digits) = split_sign(exponent);
+      let digits = strip_underscores(digits, 10).ok_or(LiteralError::Malformed)?;
+      // anything this large is infinite or zero anyway
+      let value = digits.parse::<i64>().unwrap_or(i64::MAX / 4).min(i64::MAX / 4);
+      if sign == Some('-') {
+        -value
+      } else {
+        value
+      }
+    }
+    None => 0,
+  };
+
+  let mut significand: u128 = 0;
+  let mut sticky = false;
+  for (position, digit) in integer.chars().chain(fraction.chars()).enumerate() {
+    let digit = digit.to_digit(16).unwrap() as u128;
+    let is_fraction = position >= integer.len();
+    if significand >> 120 == 0 {
+      significand = significand * 16 + digit;
+      if is_fraction {
+        binary_exponent -= 4;
+      }
+    } else {
+      sticky |= digit != 0;
+      if !is_fraction {
+        binary_exponent += 4;
+      }
+    }
+  }
+  round_to_format(significand, binary_exponent, sticky, format).ok_or(LiteralError::OutOfRange)
+}
+
+// num (. num?)? (e sign? num)?, rounding is left to the standard library, which rounds correctly
+fn parse_decimal_float(text: &str, format: &FloatFormat) -> Result<u64> {
+  let (mantissa, exponent) = match text.find(['e', 'E']) {
+    Some(position) => (&text[..position], Some(&text[position + 1..])),
+    None => (text, None),
+  };
+  let (integer, fraction) = match mantissa.split_once('.') {
+    Some((integer, fraction)) => (integer, Some(fraction)),
+    None => (mantissa, None),
+  };
+  let mut cleaned = strip_underscores(integer, 10).ok_or(LiteralError::Malformed)?;
+  if let Some(fraction) = fraction.filter(|fraction| !fraction.is_empty()) {
+    cleaned.push('.');
+    cleaned.push_str(&strip_underscores(fraction, 10).ok_or(LiteralError::Malformed)?);
+  }
+  if let Some(exponent) = exponent {
+    let (sign, digits) = split_sign(exponent);
+    cleaned.push('e');
+    cleaned.extend(sign);
+    cleaned.push_str(&strip_underscores(digits, 10).ok_or(LiteralError::Malformed)?);
+  }
+  let (finite, bits) = match format.mantissa_bits {
+    23 => cleaned.parse::<f32>().map(|value| (value.is_finite(), value.to_bits() as u64)),
+    _ => cleaned.parse::<f64>().map(|value| (value.is_finite(), value.to_bits())),
+  }
+  .map_err(|_| LiteralError::Malformed)?;
+  if !finite {
+    return Err(LiteralError::OutOfRange);
+  }
+  Ok(bits)
+}
+
+fn parse_float(text: &str, format: &FloatFormat) -> Result<u64> {
+  let (sign, unsigned) = split_sign(text);
+  let sign_bit = if sign == Some('-') { format.sign_bit() } else { 0 };
+  let magnitude = match unsigned {
+    "inf" => format.infinity(),
+    "nan" => format.infinity() | 1 << (format.mantissa_bits - 1),
+    _ => match unsigned.strip_prefix("nan:0x") {
+      Some(payload) => {
+        let digits = strip_underscores(payload, 16).ok_or(LiteralError::Malformed)?;
+        let payload = u64::from_str_radix(&digits, 16).map_err(|_| LiteralError::OutOfRange)?;
+        if payload == 0 || payload >> format.mantissa_bits != 0 {
+          return Err(LiteralError::OutOfRange);
+        }
+        format.infinity() | payload
+      }
+      None => match unsigned.strip_prefix("0x") {
+        Some(hex) => parse_hex_float(hex, format)?,
+        None => parse_decimal_float(unsigned, format)?,
+      },
+    },
+  };
+  Ok(sign_bit | magnitude)
+}
+
+pub fn parse_f32(text: &str) -> Result<u32> {
+  Ok(parse_float(text, &F32)? as u32)
+}
+
+pub fn parse_f64(text: &str) -> Result<u64> {
+  parse_float(text, &F64)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn i32_bounds() {
+    assert_eq!(parse_i32("2147483647"), Ok(i32::MAX));
+    assert_eq!(parse_i32("4294967295"), Ok(-1));
+    assert_eq!(parse_i32("4294967296"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_i32("+2147483647"), Ok(i32::MAX));
+    assert_eq!(parse_i32("+2147483648"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_i32("-2147483648"), Ok(i32::MIN));
+    assert_eq!(parse_i32("-2147483649"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_i32("0xffff_ffff"), Ok(-1));
+    assert_eq!(parse_i32("-0x8000_0000"), Ok(i32::MIN));
+    assert_eq!(parse_i32("0x1_0000_0000"), Err(LiteralError::OutOfRange));
+  }
+
+  #[test]
+  fn i64_bounds() {
+    assert_eq!(parse_i64("18446744073709551615"), Ok(-1));
+    assert_eq!(parse_i64("18446744073709551616"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_i64("+9223372036854775807"), Ok(i64::MAX));
+    assert_eq!(parse_i64("+9223372036854775808"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_i64("-9223372036854775808"), Ok(i64::MIN));
+    assert_eq!(parse_i64("-9223372036854775809"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_i64("-0x8000_0000_0000_0000"), Ok(i64::MIN));
+    assert_eq!(
+      parse_i64("999999999999999999999999999999999999999999"),
+      Err(LiteralError::OutOfRange)
+    );
+  }
+
+  #[test]
+  fn unsigned_and_malformed_integers() {
+    assert_eq!(parse_u32("4294967295"), Ok(u32::MAX));
+    assert_eq!(parse_u32("4294967296"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_u32("1_000"), Ok(1000));
+    for text in ["-1", "+1", "", "0x", "_1", "1_", "1__0", "0x_1", "1a", "0xg"] {
+      assert_eq!(parse_u32(text), Err(LiteralError::Malformed), "{}", text);
+    }
+    assert_eq!(parse_i32("--1"), Err(LiteralError::Malformed));
+  }
+
+  #[test]
+  fn hex_floats_round_half_to_even() {
+    // exactly half an ulp above 1 rounds down to the even mantissa, above an odd one it rounds up
+    assert_eq!(parse_f64("0x1.00000000000008p0"), Ok(0x3ff0000000000000));
+    assert_eq!(parse_f64("0x1.00000000000018p0"), Ok(0x3ff0000000000002));
+    assert_eq!(parse_f64("0x1.000000000000081p0"), Ok(0x3ff0000000000001));
+    assert_eq!(parse_f64("0x1.fffffffffffff8p0"), Ok(0x4000000000000000));
+    assert_eq!(parse_f64("0x1.fffffffffffff7ffp0"), Ok(0x3fffffffffffffff));
+    // a nonzero digit past the precision kept in the significand breaks the tie
+    assert_eq!(
+      parse_f64("0x1.00000000000008000000000000000000001p0"),
+      Ok(0x3ff0000000000001)
+    );
+    assert_eq!(parse_f32("0x1.000001p0"), Ok(0x3f800000));
+    assert_eq!(parse_f32("0x1.000003p0"), Ok(0x3f800002));
+    assert_eq!(parse_f32("0x1.ffffffp0"), Ok(0x40000000));
+    assert_eq!(parse_f32("0x1_0.8p-1"), Ok(0x41040000));
+    assert_eq!(parse_f32("-0x1.p0"), Ok(0xbf800000));
+  }
+
+  #[test]
+  fn hex_float_subnormals() {
+    assert_eq!(parse_f64("0x1p-1022"), Ok(0x0010000000000000));
+    assert_eq!(parse_f64("0x0.fffffffffffffp-1022"), Ok(0x000fffffffffffff));
+    assert_eq!(parse_f64("0x1.fffffffffffffp-1023"), Ok(0x0010000000000000));
+    assert_eq!(parse_f64("0x1p-1074"), Ok(1));
+    assert_eq!(parse_f64("0x1p-1075"), Ok(0));
+    assert_eq!(parse_f64("0x1.8p-1075"), Ok(1));
+    assert_eq!(parse_f64("0x1p-99999999999999999999"), Ok(0));
+    assert_eq!(parse_f32("0x1p-126"), Ok(0x00800000));
+    assert_eq!(parse_f32("0x1p-149"), Ok(1));
+    assert_eq!(parse_f32("0x1p-150"), Ok(0));
+    assert_eq!(parse_f32("0x3p-150"), Ok(2));
+    assert_eq!(parse_f32("-0x1p-149"), Ok(0x80000001));
+  }
+
+  #[test]
+  fn float_overflow_is_out_of_range() {
+    assert_eq!(parse_f64("0x1.fffffffffffffp1023"), Ok(0x7fefffffffffffff));
+    assert_eq!(parse_f64("0x1.fffffffffffff7p1023"), Ok(0x7fefffffffffffff));
+    assert_eq!(parse_f64("0x1.fffffffffffff8p1023"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f64("0x1p1024"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f64("0x1p99999999999999999999"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f32("0x1.fffffep127"), Ok(0x7f7fffff));
+    assert_eq!(parse_f32("0x1.ffffffp127"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f32("3.4028235e38"), Ok(0x7f7fffff));
+    assert_eq!(parse_f32("3.4028236e38"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f64("1e309"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f64("-1e309"), Err(LiteralError::OutOfRange));
+  }
+
+  #[test]
+  fn decimal_floats() {
+    assert_eq!(parse_f64("0.1"), Ok(0x3fb999999999999a));
+    assert_eq!(parse_f64("-0.0"), Ok(0x8000000000000000));
+    assert_eq!(parse_f64("1_000.5e-1_0"), Ok(1000.5e-10f64.to_bits()));
+    assert_eq!(parse_f32("1."), Ok(0x3f800000));
+    assert_eq!(parse_f64("5e-324"), Ok(1));
+    for text in ["1e", ".5", "1.e_1", "1__0.0", "0x1p", "0x.1", "1.5.0"] {
+      assert_eq!(parse_f64(text), Err(LiteralError::Malformed), "{}", text);
+    }
+  }
+
+  #[test]
+  fn nan_payloads_are_bit_exact() {
+    assert_eq!(parse_f32("nan"), Ok(0x7fc00000));
+    assert_eq!(parse_f32("-nan"), Ok(0xffc00000));
+    assert_eq!(parse_f32("+inf"), Ok(0x7f800000));
+    assert_eq!(parse_f32("-inf"), Ok(0xff800000));
+    assert_eq!(parse_f32("nan:0x1"), Ok(0x7f800001));
+    assert_eq!(parse_f32("nan:0x7f_ffff"), Ok(0x7fffffff));
+    assert_eq!(parse_f32("-nan:0x200000"), Ok(0xffa00000));
+    assert_eq!(parse_f32("nan:0x800000"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f32("nan:0x0"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f64("nan"), Ok(0x7ff8000000000000));
+    assert_eq!(parse_f64("nan:0x1"), Ok(0x7ff0000000000001));
+    assert_eq!(parse_f64("-nan:0xf_ffff_ffff_ffff"), Ok(0xffffffffffffffff));
+    assert_eq!(parse_f64("nan:0x10_0000_0000_0000"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f64("nan:0x0"), Err(LiteralError::OutOfRange));
+    assert_eq!(parse_f64("nan:0x"), Err(LiteralError::Malformed));
+  }
+}
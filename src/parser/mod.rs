@@ -1,4 +1,9 @@
 #![allow(dead_code, unused_imports)]
+mod literals;
+#[allow(clippy::module_inception)]
 mod parser;
 pub use parser::Parser;
+mod resolver;
+mod type_uses;
+pub use resolver::{resolve_program, Resolver};
 pub mod ast;
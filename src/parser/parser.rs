@@ -1,33 +1,1193 @@
 #![allow(dead_code)]
 
-use super::ast;
-use crate::{diagnostics::Diagnostic, lexer::Lexer};
+use super::{
+  ast,
+  literals::{self, LiteralError},
+  type_uses,
+};
+use crate::{
+  bytes::instructions::{MemoryOp, NumericOp},
+  diagnostics::{Diagnostic, DiagnosticManager, SintaxError},
+  lexer::{
+    tokens::{Token, TokenKind},
+    Lexer,
+  },
+  utils::range::Range,
+};
 
 type Result<T> = std::result::Result<T, Diagnostic>;
 
+fn unexpected(expected: &str, found: &Token) -> Diagnostic {
+  let error = SintaxError::UnxpectedToken {
+    expected: expected.to_string(),
+    found: found.kind.to_string(),
+    range: found.range.clone(),
+  };
+  error.into()
+}
+
+// keywords that start a module field, where parsing resumes after an error
+const MODULE_FIELDS: [&str; 10] = [
+  "type", "import", "func", "table", "memory", "global", "export", "start", "elem", "data",
+];
+
+// the fields whose keyword can also open an item nested in `field`, `(type` in `(func (type 0))` for instance
+fn nested_fields(field: &str) -> &'static [&'static str] {
+  match field {
+    "type" => &["func"],
+    "import" | "export" => &["type", "func", "table", "memory", "global"],
+    "func" => &["type", "import", "export"],
+    "table" => &["import", "export", "elem"],
+    "memory" => &["import", "export", "data"],
+    "global" => &["import", "export"],
+    "elem" => &["table"],
+    "data" => &["memory"],
+    "start" => &[],
+    // not a field, whatever it holds is skipped
+    _ => &MODULE_FIELDS,
+  }
+}
+
+// the index the next item of `kind` gets, imported items come first in each index space
+fn next_index(module: &ast::Module, kind: &str) -> u32 {
+  let imported = module.imports.iter().filter(|import| import.desc.kind() == kind).count();
+  let defined = match kind {
+    "func" => module.functions.len(),
+    "table" => module.tables.len(),
+    "memory" => module.memories.len(),
+    _ => module.globals.len(),
+  };
+  (imported + defined) as u32
+}
+
+// imports must precede every definition, the indices given so far would shift otherwise
+fn push_import(module: &mut ast::Module, import: ast::Import) -> Result<()> {
+  let definitions = [
+    ("function", module.functions.len()),
+    ("table", module.tables.len()),
+    ("memory", module.memories.len()),
+    ("global", module.globals.len()),
+  ];
+  if let Some((kind, _)) = definitions.iter().find(|(_, count)| *count > 0) {
+    let error = SintaxError::ImportAfterDefinition { kind: kind.to_string(), range: import.range };
+    return Err(error.into());
+  }
+  module.imports.push(import);
+  Ok(())
+}
+
+// the text of a literal token converted to `kind`, out of range values are reported with the literal
+fn convert_literal<T>(kind: &str, text: &str, token: &Token, parse: fn(&str) -> literals::Result<T>) -> Result<T> {
+  parse(text).map_err(|error| match error {
+    LiteralError::Malformed => unexpected(kind, token),
+    LiteralError::OutOfRange => {
+      let range = token.range.clone();
+      SintaxError::ConstantOutOfRange { kind: kind.to_string(), literal: text.to_string(), range }.into()
+    }
+  })
+}
+
 pub struct Parser<'a> {
   lexer: Lexer<'a>,
+  lookahead: Vec<Token>, // tokens peeked but not consumed yet, comments are never stored
+  previous_end: usize,   // end of the last consumed token
+  annotations: Vec<ast::Annotation>, // read since the current module started
+  depth: usize,          // parentheses opened by the consumed tokens and not closed yet
 }
 
 impl<'a> Parser<'a> {
   pub fn new(lexer: Lexer<'a>) -> Self {
-    Self { lexer }
+    Self { lexer, lookahead: vec![], previous_end: 0, annotations: vec![], depth: 0 }
+  }
+
+  // lexical and syntax errors found while reading the source
+  pub fn diagnostics(&mut self) -> &mut DiagnosticManager<'a> {
+    self.lexer.diagnostics()
   }
 
-  pub fn parse(&mut self) -> Result<ast::Program> {
+  pub fn parse(&mut self) -> ast::Program {
     self.parse_program()
   }
 
-  pub fn parse_program(&mut self) -> Result<ast::Program> {
+  // every error is recorded in the diagnostics, the program keeps whatever could be parsed
+  pub fn parse_program(&mut self) -> ast::Program {
     let mut program = ast::Program::default();
-    while !self.lexer.is_end() {
-      let module = self.parse_module()?;
-      program.body.push(module);
+    while self.peek().kind != TokenKind::EOF {
+      match self.parse_module() {
+        Ok(module) => program.body.push(module),
+        Err(diagnostic) => {
+          self.diagnostics().add(diagnostic);
+          // anything before the next module is skipped
+          while self.peek().kind != TokenKind::EOF && !self.peek_field("module") {
+            self.next();
+          }
+          self.depth = 0;
+        }
+      }
     }
-    Ok(program)
+    program
   }
 
+  // (module $id? field*), a broken field is skipped and parsing resumes at the next one
   pub fn parse_module(&mut self) -> Result<ast::Module> {
-    todo!()
+    let start = self.expect(TokenKind::LParen)?.range.start;
+    self.expect_keyword("module")?;
+    let mut module = ast::Module { id: self.parse_optional_id()?, ..Default::default() };
+    let depth = self.depth;
+    loop {
+      let token = self.peek();
+      match token.kind {
+        TokenKind::RParen | TokenKind::EOF => break,
+        // a `)` is missing, the next module starts here
+        TokenKind::LParen if self.peek_field("module") => break,
+        TokenKind::LParen => {
+          let token = self.peek_nth(1);
+          let field = self.keyword(&token).unwrap_or_default().to_string();
+          if let Err(diagnostic) = self.parse_module_field(&mut module) {
+            self.diagnostics().add(diagnostic);
+            let nested = nested_fields(&field);
+            let fields: Vec<&str> = MODULE_FIELDS.into_iter().filter(|keyword| !nested.contains(keyword)).collect();
+            self.synchronize(depth, &fields);
+          }
+        }
+        _ => {
+          self.diagnostics().add(unexpected("module field", &token));
+          self.next();
+        }
+      }
+    }
+    if let Err(diagnostic) = self.expect(TokenKind::RParen) {
+      self.diagnostics().add(diagnostic);
+    }
+    module.range = self.range_from(start);
+    module.annotations = std::mem::take(&mut self.annotations);
+    type_uses::insert_implicit_types(&mut module);
+    Ok(module)
+  }
+
+  // skips tokens until the parentheses opened past `depth` are closed, or until `(keyword` starts
+  // the next item at that depth, a `)` is missing then. `keywords` can't open anything nested in the broken item
+  fn synchronize(&mut self, depth: usize, keywords: &[&str]) {
+    while self.depth > depth {
+      if self.peek().kind == TokenKind::EOF {
+        return;
+      }
+      if keywords.iter().any(|keyword| self.peek_field(keyword)) {
+        self.depth = depth;
+        return;
+      }
+      self.next();
+    }
+  }
+
+  fn parse_module_field(&mut self, module: &mut ast::Module) -> Result<()> {
+    let token = self.peek_nth(1);
+    if !MODULE_FIELDS.iter().any(|keyword| self.peek_field(keyword)) {
+      self.skip_stray_paren();
+      return Err(unexpected("module field", &token));
+    }
+    let start = self.next().range.start;
+    let token = self.next();
+    match self.keyword(&token).unwrap_or_default() {
+      "type" => {
+        let definition = self.parse_type(start)?;
+        module.types.push(definition);
+      }
+      "import" => {
+        let import = self.parse_import(start)?;
+        push_import(module, import)?;
+      }
+      "func" => self.parse_function(start, module)?,
+      "table" => self.parse_table(start, module)?,
+      "memory" => self.parse_memory(start, module)?,
+      "global" => self.parse_global(start, module)?,
+      "export" => {
+        let export = self.parse_export(start)?;
+        module.exports.push(export);
+      }
+      "start" => {
+        let function = self.parse_index()?;
+        self.expect(TokenKind::RParen)?;
+        module.start = Some(ast::Start { function, range: self.range_from(start) });
+      }
+      "elem" => {
+        let element = self.parse_element(start)?;
+        module.elements.push(element);
+      }
+      "data" => {
+        let data = self.parse_data(start)?;
+        module.data.push(data);
+      }
+      _ => unreachable!("peeked a module field"),
+    }
+    Ok(())
+  }
+
+  // a `(` followed by another `(` opens nothing, it is dropped so that the item after it still parses,
+  // and so is an empty `()`. any other `(` opens an item that is left for `synchronize` to skip
+  fn skip_stray_paren(&mut self) {
+    let token = self.peek_nth(1);
+    self.next();
+    match token.kind {
+      TokenKind::LParen => self.depth -= 1,
+      TokenKind::RParen => {
+        self.next();
+      }
+      _ => {}
+    }
+  }
+
+  // `(keyword` of a module field, instructions never start with one
+  fn peek_module_field(&mut self) -> bool {
+    MODULE_FIELDS.iter().any(|keyword| self.peek_field(keyword))
+  }
+
+  // (type $id? (func param* result*))
+  fn parse_type(&mut self, start: usize) -> Result<ast::Type> {
+    let id = self.parse_optional_id()?;
+    self.expect(TokenKind::LParen)?;
+    self.expect_keyword("func")?;
+    let signature = self.parse_signature()?;
+    self.expect(TokenKind::RParen)?;
+    self.expect(TokenKind::RParen)?;
+    Ok(ast::Type { id, signature, range: self.range_from(start) })
+  }
+
+  // (import "module" "name" (func|table|memory|global $id? ...))
+  fn parse_import(&mut self, start: usize) -> Result<ast::Import> {
+    let module = self.parse_name()?;
+    let name = self.parse_name()?;
+    self.expect(TokenKind::LParen)?;
+    let token = self.next();
+    let id = self.parse_optional_id()?;
+    let desc = match self.keyword(&token).unwrap_or_default() {
+      "func" => ast::ImportDesc::Func(self.parse_type_use()?),
+      "table" => ast::ImportDesc::Table(self.parse_table_type()?),
+      "memory" => ast::ImportDesc::Mem(self.parse_memory_type()?),
+      "global" => ast::ImportDesc::Global(self.parse_global_type()?),
+      _ => return Err(unexpected("import kind", &token)),
+    };
+    self.expect(TokenKind::RParen)?;
+    self.expect(TokenKind::RParen)?;
+    Ok(ast::Import { id, module, name, desc, range: self.range_from(start) })
+  }
+
+  // (func $id? (export "name")* (import "module" "name")? typeuse local* instr*)
+  fn parse_function(&mut self, start: usize, module: &mut ast::Module) -> Result<()> {
+    let id = self.parse_optional_id()?;
+    self.parse_inline_exports(module, "func")?;
+    if let Some(names) = self.parse_inline_import()? {
+      let desc = ast::ImportDesc::Func(self.parse_type_use()?);
+      return self.push_inline_import(start, module, id, names, desc);
+    }
+    let type_use = self.parse_type_use()?;
+    let locals = self.parse_locals()?;
+    let body = self.parse_instrs()?;
+    self.expect(TokenKind::RParen)?;
+    module.functions.push(ast::Function { id, type_use, locals, body, range: self.range_from(start) });
+    Ok(())
+  }
+
+  // (table $id? (export "name")* (import "module" "name")? tabletype)
+  // or (table $id? (export "name")* reftype (elem index*)), sized to fit its elements
+  fn parse_table(&mut self, start: usize, module: &mut ast::Module) -> Result<()> {
+    let id = self.parse_optional_id()?;
+    self.parse_inline_exports(module, "table")?;
+    if let Some(names) = self.parse_inline_import()? {
+      let desc = ast::ImportDesc::Table(self.parse_table_type()?);
+      return self.push_inline_import(start, module, id, names, desc);
+    }
+    let table_type = match self.peek().kind {
+      TokenKind::Keyword(_) => self.parse_inline_element(module)?,
+      _ => self.parse_table_type()?,
+    };
+    self.expect(TokenKind::RParen)?;
+    module.tables.push(ast::Table { id, table_type, range: self.range_from(start) });
+    Ok(())
+  }
+
+  // reftype (elem index*), the segment fills the table from offset 0
+  fn parse_inline_element(&mut self, module: &mut ast::Module) -> Result<ast::TableType> {
+    let start = self.peek().range.start;
+    let element_type = self.parse_value_type()?;
+    let element_start = self.expect(TokenKind::LParen)?.range.start;
+    self.expect_keyword("elem")?;
+    let mut init = vec![];
+    while self.peek_index() {
+      init.push(self.parse_index()?);
+    }
+    self.expect(TokenKind::RParen)?;
+    let range = self.range_from(element_start);
+    let size = init.len() as u32;
+    let table = ast::Index::Numeric { value: next_index(module, "table"), range: range.clone() };
+    let offset = vec![ast::Instr::I32Const { value: 0, range: range.clone() }];
+    module.elements.push(ast::Element { table, offset, init, range: range.clone() });
+    let limits = ast::Limits { min: size, max: Some(size), range };
+    Ok(ast::TableType { element_type, limits, range: self.range_from(start) })
+  }
+
+  // (memory $id? (export "name")* (import "module" "name")? limits)
+  // or (memory $id? (export "name")* (data string*)), sized to fit its data
+  fn parse_memory(&mut self, start: usize, module: &mut ast::Module) -> Result<()> {
+    let id = self.parse_optional_id()?;
+    self.parse_inline_exports(module, "memory")?;
+    if let Some(names) = self.parse_inline_import()? {
+      let desc = ast::ImportDesc::Mem(self.parse_memory_type()?);
+      return self.push_inline_import(start, module, id, names, desc);
+    }
+    let memory_type = if self.peek_field("data") {
+      self.parse_inline_data(module)?
+    } else {
+      self.parse_memory_type()?
+    };
+    self.expect(TokenKind::RParen)?;
+    module.memories.push(ast::Memory { id, memory_type, range: self.range_from(start) });
+    Ok(())
+  }
+
+  // (data string*), the segment fills the memory from offset 0
+  fn parse_inline_data(&mut self, module: &mut ast::Module) -> Result<ast::MemoryType> {
+    const PAGE_SIZE: usize = 64 * 1024;
+    let start = self.expect(TokenKind::LParen)?.range.start;
+    self.expect_keyword("data")?;
+    let mut init = vec![];
+    while matches!(self.peek().kind, TokenKind::String(_)) {
+      init.extend(self.parse_string()?);
+    }
+    self.expect(TokenKind::RParen)?;
+    let range = self.range_from(start);
+    let pages = init.len().div_ceil(PAGE_SIZE) as u32;
+    let memory = ast::Index::Numeric { value: next_index(module, "memory"), range: range.clone() };
+    let offset = vec![ast::Instr::I32Const { value: 0, range: range.clone() }];
+    module.data.push(ast::Data { memory, offset, init, range: range.clone() });
+    let limits = ast::Limits { min: pages, max: Some(pages), range: range.clone() };
+    Ok(ast::MemoryType { limits, range })
+  }
+
+  // (global $id? (export "name")* (import "module" "name")? globaltype expr?)
+  fn parse_global(&mut self, start: usize, module: &mut ast::Module) -> Result<()> {
+    let id = self.parse_optional_id()?;
+    self.parse_inline_exports(module, "global")?;
+    if let Some(names) = self.parse_inline_import()? {
+      let desc = ast::ImportDesc::Global(self.parse_global_type()?);
+      return self.push_inline_import(start, module, id, names, desc);
+    }
+    let global_type = self.parse_global_type()?;
+    let init = self.parse_instrs()?;
+    self.expect(TokenKind::RParen)?;
+    module.globals.push(ast::Global { id, global_type, init, range: self.range_from(start) });
+    Ok(())
+  }
+
+  // (export "name")*, each one exports the item of `kind` being defined
+  fn parse_inline_exports(&mut self, module: &mut ast::Module, kind: &str) -> Result<()> {
+    let index = next_index(module, kind);
+    while self.peek_field("export") {
+      let start = self.next().range.start;
+      self.next();
+      let name = self.parse_name()?;
+      self.expect(TokenKind::RParen)?;
+      let range = self.range_from(start);
+      let index = ast::Index::Numeric { value: index, range: range.clone() };
+      let desc = match kind {
+        "func" => ast::ExportDesc::Func(index),
+        "table" => ast::ExportDesc::Table(index),
+        "memory" => ast::ExportDesc::Mem(index),
+        _ => ast::ExportDesc::Global(index),
+      };
+      module.exports.push(ast::Export { name, desc, range });
+    }
+    Ok(())
+  }
+
+  // (import "module" "name") inside an item, which is then imported instead of defined
+  fn parse_inline_import(&mut self) -> Result<Option<(String, String)>> {
+    if !self.peek_field("import") {
+      return Ok(None);
+    }
+    self.next();
+    self.next();
+    let module = self.parse_name()?;
+    let name = self.parse_name()?;
+    self.expect(TokenKind::RParen)?;
+    Ok(Some((module, name)))
+  }
+
+  fn push_inline_import(
+    &mut self,
+    start: usize,
+    module: &mut ast::Module,
+    id: Option<ast::Identifier>,
+    (module_name, name): (String, String),
+    desc: ast::ImportDesc,
+  ) -> Result<()> {
+    self.expect(TokenKind::RParen)?;
+    let import = ast::Import { id, module: module_name, name, desc, range: self.range_from(start) };
+    push_import(module, import)
+  }
+
+  // (export "name" (func|table|memory|global index))
+  fn parse_export(&mut self, start: usize) -> Result<ast::Export> {
+    let name = self.parse_name()?;
+    self.expect(TokenKind::LParen)?;
+    let token = self.next();
+    let desc = match self.keyword(&token).unwrap_or_default() {
+      "func" => ast::ExportDesc::Func(self.parse_index()?),
+      "table" => ast::ExportDesc::Table(self.parse_index()?),
+      "memory" => ast::ExportDesc::Mem(self.parse_index()?),
+      "global" => ast::ExportDesc::Global(self.parse_index()?),
+      _ => return Err(unexpected("export kind", &token)),
+    };
+    self.expect(TokenKind::RParen)?;
+    self.expect(TokenKind::RParen)?;
+    Ok(ast::Export { name, desc, range: self.range_from(start) })
+  }
+
+  // (elem (table index)? offset func? index*)
+  fn parse_element(&mut self, start: usize) -> Result<ast::Element> {
+    let table = self.parse_segment_target("table", start)?;
+    let offset = self.parse_offset()?;
+    if self.peek_keyword("func") {
+      self.next();
+    }
+    let mut init = vec![];
+    while self.peek_index() {
+      init.push(self.parse_index()?);
+    }
+    self.expect(TokenKind::RParen)?;
+    Ok(ast::Element { table, offset, init, range: self.range_from(start) })
+  }
+
+  // (data (memory index)? offset string*)
+  fn parse_data(&mut self, start: usize) -> Result<ast::Data> {
+    let memory = self.parse_segment_target("memory", start)?;
+    let offset = self.parse_offset()?;
+    let mut init = vec![];
+    while matches!(self.peek().kind, TokenKind::String(_)) {
+      init.extend(self.parse_string()?);
+    }
+    self.expect(TokenKind::RParen)?;
+    Ok(ast::Data { memory, offset, init, range: self.range_from(start) })
+  }
+
+  // the table or memory of a segment, `(memory $m)`, `$m` or nothing for the first one
+  fn parse_segment_target(&mut self, kind: &str, start: usize) -> Result<ast::Index> {
+    if self.peek_field(kind) {
+      self.next();
+      self.next();
+      let index = self.parse_index()?;
+      self.expect(TokenKind::RParen)?;
+      return Ok(index);
+    }
+    if self.peek_index() {
+      return self.parse_index();
+    }
+    Ok(ast::Index::Numeric { value: 0, range: Range::new(start, start) })
+  }
+
+  // (offset instr*) or a single folded instruction
+  fn parse_offset(&mut self) -> Result<Vec<ast::Instr>> {
+    if self.peek_field("offset") {
+      self.next();
+      self.next();
+      let instrs = self.parse_instrs()?;
+      self.expect(TokenKind::RParen)?;
+      return Ok(instrs);
+    }
+    let mut instrs = vec![];
+    self.parse_folded_instr(&mut instrs)?;
+    Ok(instrs)
+  }
+
+  // (type index)? param* result*
+  fn parse_type_use(&mut self) -> Result<ast::TypeUse> {
+    let mut index = None;
+    if self.peek_field("type") {
+      self.next();
+      self.next();
+      index = Some(self.parse_index()?);
+      self.expect(TokenKind::RParen)?;
+    }
+    let signature = self.parse_signature()?;
+    Ok(ast::TypeUse { index, signature })
+  }
+
+  // (param $id valtype) | (param valtype*), then (result valtype*)
+  fn parse_signature(&mut self) -> Result<ast::Signature> {
+    let start = self.peek().range.start;
+    let mut params = vec![];
+    while self.peek_field("param") {
+      self.next();
+      self.next();
+      for (id, value_type, range) in self.parse_declarations()? {
+        params.push(ast::Param { id, value_type, range });
+      }
+    }
+    let mut results = vec![];
+    while self.peek_field("result") {
+      self.next();
+      self.next();
+      while self.peek().kind != TokenKind::RParen {
+        results.push(self.parse_value_type()?);
+      }
+      self.expect(TokenKind::RParen)?;
+    }
+    Ok(ast::Signature { params, results, range: self.range_from(start) })
+  }
+
+  // (local $id valtype) | (local valtype*)
+  fn parse_locals(&mut self) -> Result<Vec<ast::Local>> {
+    let mut locals = vec![];
+    while self.peek_field("local") {
+      self.next();
+      self.next();
+      for (id, value_type, range) in self.parse_declarations()? {
+        locals.push(ast::Local { id, value_type, range });
+      }
+    }
+    Ok(locals)
+  }
+
+  // the inside of a param or local declaration, up to and including the closing paren
+  fn parse_declarations(&mut self) -> Result<Vec<(Option<ast::Identifier>, ast::ValueType, Range)>> {
+    let mut declarations = vec![];
+    if let Some(id) = self.parse_optional_id()? {
+      let start = id.range.start;
+      let value_type = self.parse_value_type()?;
+      declarations.push((Some(id), value_type, self.range_from(start)));
+    } else {
+      while self.peek().kind != TokenKind::RParen {
+        let range = self.peek().range;
+        declarations.push((None, self.parse_value_type()?, range));
+      }
+    }
+    self.expect(TokenKind::RParen)?;
+    Ok(declarations)
+  }
+
+  fn parse_value_type(&mut self) -> Result<ast::ValueType> {
+    let token = self.next();
+    let value_type = match self.keyword(&token).unwrap_or_default() {
+      "i32" => ast::ValueType::I32,
+      "i64" => ast::ValueType::I64,
+      "f32" => ast::ValueType::F32,
+      "f64" => ast::ValueType::F64,
+      "funcref" => ast::ValueType::FuncRef,
+      "externref" => ast::ValueType::ExternRef,
+      _ => return Err(unexpected("value type", &token)),
+    };
+    Ok(value_type)
+  }
+
+  fn parse_limits(&mut self) -> Result<ast::Limits> {
+    let start = self.peek().range.start;
+    let min = self.parse_u32()?;
+    let max = if matches!(self.peek().kind, TokenKind::Number(_)) {
+      Some(self.parse_u32()?)
+    } else {
+      None
+    };
+    Ok(ast::Limits { min, max, range: self.range_from(start) })
+  }
+
+  fn parse_table_type(&mut self) -> Result<ast::TableType> {
+    let start = self.peek().range.start;
+    let limits = self.parse_limits()?;
+    let element_type = self.parse_value_type()?;
+    Ok(ast::TableType { element_type, limits, range: self.range_from(start) })
+  }
+
+  fn parse_memory_type(&mut self) -> Result<ast::MemoryType> {
+    let start = self.peek().range.start;
+    let limits = self.parse_limits()?;
+    Ok(ast::MemoryType { limits, range: self.range_from(start) })
+  }
+
+  // valtype | (mut valtype)
+  fn parse_global_type(&mut self) -> Result<ast::GlobalType> {
+    let start = self.peek().range.start;
+    if self.peek_field("mut") {
+      self.next();
+      self.next();
+      let value_type = self.parse_value_type()?;
+      self.expect(TokenKind::RParen)?;
+      return Ok(ast::GlobalType { value_type, mutable: true, range: self.range_from(start) });
+    }
+    let value_type = self.parse_value_type()?;
+    Ok(ast::GlobalType { value_type, mutable: false, range: self.range_from(start) })
+  }
+
+  // instructions up to the end of the enclosing block, `end`, `else` and `)` are left for the caller
+  fn parse_instrs(&mut self) -> Result<Vec<ast::Instr>> {
+    let mut instrs = vec![];
+    loop {
+      let token = self.peek();
+      match self.keyword(&token) {
+        Some("end") | Some("else") => break,
+        Some(_) => self.parse_plain_instr(&mut instrs)?,
+        // the next module field, the `)` of the function is missing
+        None if self.peek_module_field() => break,
+        None if token.kind == TokenKind::LParen => self.parse_folded_instr(&mut instrs)?,
+        None => break,
+      }
+    }
+    Ok(instrs)
+  }
+
+  fn parse_plain_instr(&mut self, instrs: &mut Vec<ast::Instr>) -> Result<()> {
+    let token = self.next();
+    let start = token.range.start;
+    let instr = match self.keyword(&token).unwrap_or_default() {
+      "block" => {
+        let label = self.parse_optional_id()?;
+        let block_type = self.parse_block_type()?;
+        let instr = self.parse_instrs()?;
+        self.parse_block_end()?;
+        ast::Instr::Block(ast::BlockInstr { label, block_type, instr, range: self.range_from(start) })
+      }
+      "loop" => {
+        let label = self.parse_optional_id()?;
+        let loop_type = self.parse_block_type()?;
+        let instr = self.parse_instrs()?;
+        self.parse_block_end()?;
+        ast::Instr::Loop(ast::LoopInstr { label, loop_type, instr, range: self.range_from(start) })
+      }
+      "if" => {
+        let label = self.parse_optional_id()?;
+        let block_type = self.parse_block_type()?;
+        let instr = self.parse_instrs()?;
+        let mut else_instr = None;
+        if self.peek_keyword("else") {
+          self.next();
+          self.parse_optional_id()?;
+          else_instr = Some(self.parse_instrs()?);
+        }
+        self.parse_block_end()?;
+        let range = self.range_from(start);
+        ast::Instr::If(ast::IfInstr { label, block_type, condition: vec![], instr, else_instr, range })
+      }
+      _ => self.parse_operation(&token)?,
+    };
+    instrs.push(instr);
+    Ok(())
+  }
+
+  // `end` with an optional repeated label
+  fn parse_block_end(&mut self) -> Result<()> {
+    self.expect_keyword("end")?;
+    self.parse_optional_id()?;
+    Ok(())
+  }
+
+  // folded operands are pushed before the instruction that consumes them
+  fn parse_folded_instr(&mut self, instrs: &mut Vec<ast::Instr>) -> Result<()> {
+    let token = self.peek_nth(1);
+    if self.keyword(&token).is_none() {
+      self.skip_stray_paren();
+      return Err(unexpected("instruction", &token));
+    }
+    let start = self.next().range.start;
+    let token = self.next();
+    let instr = match self.keyword(&token).unwrap_or_default() {
+      "block" => {
+        let label = self.parse_optional_id()?;
+        let block_type = self.parse_block_type()?;
+        let instr = self.parse_instrs()?;
+        self.expect(TokenKind::RParen)?;
+        ast::Instr::Block(ast::BlockInstr { label, block_type, instr, range: self.range_from(start) })
+      }
+      "loop" => {
+        let label = self.parse_optional_id()?;
+        let loop_type = self.parse_block_type()?;
+        let instr = self.parse_instrs()?;
+        self.expect(TokenKind::RParen)?;
+        ast::Instr::Loop(ast::LoopInstr { label, loop_type, instr, range: self.range_from(start) })
+      }
+      "if" => {
+        let label = self.parse_optional_id()?;
+        let block_type = self.parse_block_type()?;
+        let mut condition = vec![];
+        while self.peek().kind == TokenKind::LParen && !self.peek_field("then") && !self.peek_module_field() {
+          self.parse_folded_instr(&mut condition)?;
+        }
+        self.expect(TokenKind::LParen)?;
+        self.expect_keyword("then")?;
+        let instr = self.parse_instrs()?;
+        self.expect(TokenKind::RParen)?;
+        let mut else_instr = None;
+        if self.peek_field("else") {
+          self.next();
+          self.next();
+          else_instr = Some(self.parse_instrs()?);
+          self.expect(TokenKind::RParen)?;
+        }
+        self.expect(TokenKind::RParen)?;
+        let range = self.range_from(start);
+        ast::Instr::If(ast::IfInstr { label, block_type, condition, instr, else_instr, range })
+      }
+      _ => {
+        let instr = self.parse_operation(&token)?;
+        // the next module field ends the operands, the `)` of the instruction is missing
+        while self.peek().kind == TokenKind::LParen && !self.peek_module_field() {
+          self.parse_folded_instr(instrs)?;
+        }
+        self.expect(TokenKind::RParen)?;
+        instr
+      }
+    };
+    instrs.push(instr);
+    Ok(())
+  }
+
+  // (result valtype)?
+  fn parse_block_type(&mut self) -> Result<Option<ast::ValueType>> {
+    if !self.peek_field("result") {
+      return Ok(None);
+    }
+    self.next();
+    self.next();
+    let value_type = self.parse_value_type()?;
+    self.expect(TokenKind::RParen)?;
+    Ok(Some(value_type))
+  }
+
+  // any instruction that is not a block, with its immediates
+  fn parse_operation(&mut self, token: &Token) -> Result<ast::Instr> {
+    let start = token.range.start;
+    let range = token.range.clone();
+    let keyword = self.keyword(token).unwrap_or_default();
+    let instr = match keyword {
+      "unreachable" => ast::Instr::Unreachable { range },
+      "nop" => ast::Instr::Nop { range },
+      "return" => ast::Instr::Return { range },
+      "drop" => ast::Instr::Drop { range },
+      "select" => ast::Instr::Select { range },
+      "memory.size" => ast::Instr::MemorySize { range },
+      "memory.grow" => ast::Instr::MemoryGrow { range },
+      "br" => {
+        let label = self.parse_index()?;
+        ast::Instr::Branch(ast::BranchInstr { label, range: self.range_from(start) })
+      }
+      "br_if" => {
+        let label = self.parse_index()?;
+        ast::Instr::BranchIf(ast::BranchIfInstr { label, range: self.range_from(start) })
+      }
+      "br_table" => {
+        let mut labels = vec![self.parse_index()?];
+        while self.peek_index() {
+          labels.push(self.parse_index()?);
+        }
+        let default = labels.pop().unwrap();
+        ast::Instr::BranchTable(ast::BranchTableInstr { labels, default, range: self.range_from(start) })
+      }
+      "call" => {
+        let function = self.parse_index()?;
+        ast::Instr::Call(ast::CallInstr { function, range: self.range_from(start) })
+      }
+      "call_indirect" => {
+        let type_use = self.parse_type_use()?;
+        ast::Instr::CallIndirect(ast::CallIndirectInstr { type_use, range: self.range_from(start) })
+      }
+      "local.get" | "local.set" | "local.tee" | "global.get" | "global.set" => {
+        let index = self.parse_index()?;
+        let variable = ast::VariableInstr { index, range: self.range_from(start) };
+        match keyword {
+          "local.get" => ast::Instr::LocalGet(variable),
+          "local.set" => ast::Instr::LocalSet(variable),
+          "local.tee" => ast::Instr::LocalTee(variable),
+          "global.get" => ast::Instr::GlobalGet(variable),
+          _ => ast::Instr::GlobalSet(variable),
+        }
+      }
+      "i32.const" => {
+        let value = self.parse_literal("i32", literals::parse_i32)?;
+        ast::Instr::I32Const { value, range: self.range_from(start) }
+      }
+      "i64.const" => {
+        let value = self.parse_literal("i64", literals::parse_i64)?;
+        ast::Instr::I64Const { value, range: self.range_from(start) }
+      }
+      "f32.const" => {
+        let bits = self.parse_literal("f32", literals::parse_f32)?;
+        ast::Instr::F32Const { bits, range: self.range_from(start) }
+      }
+      "f64.const" => {
+        let bits = self.parse_literal("f64", literals::parse_f64)?;
+        ast::Instr::F64Const { bits, range: self.range_from(start) }
+      }
+      _ => {
+        if let Some(op) = NumericOp::from_name(keyword) {
+          return Ok(ast::Instr::Numeric(ast::NumericInstr { op, range }));
+        }
+        let Some(op) = MemoryOp::from_name(keyword) else {
+          return Err(unexpected("instruction", token));
+        };
+        let offset = self.parse_memarg_field("offset")?.unwrap_or(0);
+        let align = self.parse_memarg_field("align")?.unwrap_or_else(|| op.natural_alignment());
+        ast::Instr::Memory(ast::MemInstr { op, offset, align, range: self.range_from(start) })
+      }
+    };
+    Ok(instr)
+  }
+
+  // `offset=N` or `align=N`
+  fn parse_memarg_field(&mut self, name: &str) -> Result<Option<u32>> {
+    let token = self.peek();
+    let value = match (name, &token.kind) {
+      ("offset", TokenKind::Offset(value)) | ("align", TokenKind::Align(value)) => value,
+      _ => return Ok(None),
+    };
+    self.next();
+    let value = convert_literal("u32", value, &token, literals::parse_u32)?;
+    // the binary format can only encode an exponent, over-alignment is left to validation
+    if name == "align" && !value.is_power_of_two() {
+      return Err(SintaxError::MalformedAlignment { align: value, range: token.range }.into());
+    }
+    Ok(Some(value))
+  }
+
+  // a number or a `$id`
+  fn parse_index(&mut self) -> Result<ast::Index> {
+    if let Some(id) = self.parse_optional_id()? {
+      return Ok(ast::Index::Symbolic(id));
+    }
+    let token = self.peek();
+    if !matches!(token.kind, TokenKind::Number(_)) {
+      return Err(unexpected("index", &token));
+    }
+    let value = self.parse_u32()?;
+    Ok(ast::Index::Numeric { value, range: token.range })
+  }
+
+  fn peek_index(&mut self) -> bool {
+    matches!(self.peek().kind, TokenKind::Number(_) | TokenKind::Id(_))
+  }
+
+  fn parse_optional_id(&mut self) -> Result<Option<ast::Identifier>> {
+    let token = self.peek();
+    let TokenKind::Id(name) = token.kind else {
+      return Ok(None);
+    };
+    self.next();
+    Ok(Some(ast::Identifier { name, range: token.range }))
+  }
+
+  // the decoded bytes of a string, which can be any binary data
+  fn parse_string(&mut self) -> Result<Vec<u8>> {
+    let token = self.peek();
+    let TokenKind::String(bytes) = token.kind else {
+      return Err(unexpected("string", &token));
+    };
+    self.next();
+    Ok(bytes)
+  }
+
+  // import and export names are strings that must also be valid UTF-8
+  fn parse_name(&mut self) -> Result<String> {
+    let range = self.peek().range;
+    let bytes = self.parse_string()?;
+    String::from_utf8(bytes).map_err(|_| SintaxError::MalformedUtf8 { range }.into())
+  }
+
+  fn parse_u32(&mut self) -> Result<u32> {
+    self.parse_literal("u32", literals::parse_u32)
+  }
+
+  // a number token converted to `kind`
+  fn parse_literal<T>(&mut self, kind: &str, parse: fn(&str) -> literals::Result<T>) -> Result<T> {
+    let token = self.peek();
+    let TokenKind::Number(number) = &token.kind else {
+      return Err(unexpected(kind, &token));
+    };
+    self.next();
+    convert_literal(kind, number, &token, parse)
+  }
+
+  fn keyword<'t>(&self, token: &'t Token) -> Option<&'t str> {
+    match &token.kind {
+      TokenKind::Keyword(keyword) => Some(keyword),
+      _ => None,
+    }
+  }
+
+  // unexpected tokens are left in place, they may start the item where parsing resumes
+  fn expect(&mut self, kind: TokenKind) -> Result<Token> {
+    let token = self.peek();
+    if token.kind != kind {
+      return Err(unexpected(&kind.to_string(), &token));
+    }
+    Ok(self.next())
+  }
+
+  fn expect_keyword(&mut self, keyword: &str) -> Result<Token> {
+    let token = self.peek();
+    if self.keyword(&token) != Some(keyword) {
+      return Err(unexpected(keyword, &token));
+    }
+    Ok(self.next())
+  }
+
+  fn peek_keyword(&mut self, keyword: &str) -> bool {
+    let token = self.peek();
+    self.keyword(&token) == Some(keyword)
+  }
+
+  // `(keyword`
+  fn peek_field(&mut self, keyword: &str) -> bool {
+    let token = self.peek_nth(1);
+    self.peek().kind == TokenKind::LParen && self.keyword(&token) == Some(keyword)
+  }
+
+  fn range_from(&self, start: usize) -> Range {
+    Range::new(start, self.previous_end.max(start))
+  }
+
+  fn peek(&mut self) -> Token {
+    self.peek_nth(0)
+  }
+
+  fn peek_nth(&mut self, n: usize) -> Token {
+    while self.lookahead.len() <= n {
+      let token = self.lexer.next_token();
+      match token.kind {
+        // error tokens were already reported by the lexer
+        TokenKind::Comment(_) | TokenKind::Error(_) => {}
+        TokenKind::Annotation(name) => self.read_annotation(name, token.range.start),
+        _ => self.lookahead.push(token),
+      }
+    }
+    self.lookahead[n].clone()
+  }
+
+  // annotations can appear anywhere, they are set aside instead of reaching the grammar
+  fn read_annotation(&mut self, name: String, start: usize) {
+    let mut tokens = vec![];
+    let mut depth = 1;
+    let mut end = start;
+    loop {
+      let token = self.lexer.next_token();
+      end = token.range.end.max(end);
+      match token.kind {
+        TokenKind::LParen | TokenKind::Annotation(_) => depth += 1,
+        TokenKind::RParen if depth == 1 => break,
+        TokenKind::RParen => depth -= 1,
+        TokenKind::Comment(_) | TokenKind::Error(_) => continue,
+        // left for the grammar to report as an unexpected end of file
+        TokenKind::EOF => {
+          self.lookahead.push(token);
+          break;
+        }
+        _ => {}
+      }
+      tokens.push(token);
+    }
+    self.annotations.push(ast::Annotation { name, tokens, range: Range::new(start, end) });
+  }
+
+  fn next(&mut self) -> Token {
+    self.peek();
+    let token = self.lookahead.remove(0);
+    self.previous_end = token.range.end;
+    match token.kind {
+      TokenKind::LParen => self.depth += 1,
+      TokenKind::RParen => self.depth = self.depth.saturating_sub(1),
+      _ => {}
+    }
+    token
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    bytes::{
+      instructions::{BlockType, Instruction},
+      types::ValueType,
+    },
+    compiler::Compiler,
+  };
+
+  fn messages(text: &str) -> Vec<String> {
+    let mut parser = Parser::new(Lexer::new(text, "test.wat"));
+    parser.parse_program();
+    parser.diagnostics().diagnostics().iter().map(|diagnostic| diagnostic.message.clone()).collect()
+  }
+
+  fn parse(text: &str) -> ast::Module {
+    let mut parser = Parser::new(Lexer::new(text, "test.wat"));
+    let module = parser.parse_module().unwrap();
+    assert!(!parser.diagnostics().has_errors(), "{}", text);
+    module
+  }
+
+  // the instructions of the only function once lowered, ranges left out
+  fn instructions(body: &str) -> Vec<Instruction> {
+    let module = parse(&format!("(module (func (local i32) {}))", body));
+    let binary = Compiler::new(&module).compile().unwrap();
+    let body = binary.function_body(0).unwrap();
+    body.body.iter().map(|decoded| decoded.instruction.clone()).collect()
+  }
+
+  #[test]
+  fn folded_operands_come_before_their_instruction() {
+    let cases = [
+      (
+        "(i32.add (local.get 0) (i32.const 1))",
+        "local.get 0 i32.const 1 i32.add",
+      ),
+      (
+        "(i32.mul (i32.add (local.get 0) (i32.const 1)) (local.get 0))",
+        "local.get 0 i32.const 1 i32.add local.get 0 i32.mul",
+      ),
+      (
+        "(local.set 0 (i32.const 2)) (drop (local.get 0))",
+        "i32.const 2 local.set 0 local.get 0 drop",
+      ),
+      ("(nop) (unreachable)", "nop unreachable"),
+      ("(br_if 0 (i32.const 1)) (return)", "i32.const 1 br_if 0 return"),
+    ];
+    for (folded, flat) in cases {
+      assert_eq!(instructions(folded), instructions(flat), "{}", folded);
+    }
+  }
+
+  #[test]
+  fn folded_if_evaluates_its_condition_first() {
+    let cases = [
+      (
+        "(if (result i32) (local.get 0) (then (i32.const 1)) (else (i32.const 2))) drop",
+        "local.get 0 if (result i32) i32.const 1 else i32.const 2 end drop",
+      ),
+      ("(if (local.get 0) (then (nop)))", "local.get 0 if nop end"),
+      (
+        "(if (i32.eqz (local.get 0)) (then) (else (unreachable)))",
+        "local.get 0 i32.eqz if else unreachable end",
+      ),
+    ];
+    for (folded, flat) in cases {
+      assert_eq!(instructions(folded), instructions(flat), "{}", folded);
+    }
+    let flat = instructions("local.get 0 if (result i32) i32.const 1 else i32.const 2 end drop");
+    assert_eq!(flat[1], Instruction::If(BlockType::Value(ValueType::I32)));
+    assert_eq!(flat[3], Instruction::Else);
+  }
+
+  #[test]
+  fn folded_blocks_and_loops_match_the_flat_form() {
+    let cases = [
+      (
+        "(block (result i32) (loop (br 1 (i32.const 1)))) drop",
+        "block (result i32) loop i32.const 1 br 1 end end drop",
+      ),
+      (
+        "(loop (block (br_if 1 (local.get 0))))",
+        "loop block local.get 0 br_if 1 end end",
+      ),
+    ];
+    for (folded, flat) in cases {
+      assert_eq!(instructions(folded), instructions(flat), "{}", folded);
+    }
+  }
+
+  #[test]
+  fn blocks_keep_their_labels() {
+    for body in [
+      "(block $outer (loop $inner (br $outer)))",
+      "block $outer loop $inner br $outer end $inner end $outer",
+    ] {
+      let module = parse(&format!("(module (func {}))", body));
+      let [ast::Instr::Block(block)] = &module.functions[0].body[..] else {
+        panic!("{}", body)
+      };
+      assert_eq!(block.label.as_ref().unwrap().name, "outer");
+      let [ast::Instr::Loop(inner)] = &block.instr[..] else {
+        panic!("{}", body)
+      };
+      assert_eq!(inner.label.as_ref().unwrap().name, "inner");
+      let [ast::Instr::Branch(branch)] = &inner.instr[..] else {
+        panic!("{}", body)
+      };
+      assert!(matches!(&branch.label, ast::Index::Symbolic(id) if id.name == "outer"));
+    }
+  }
+
+  #[test]
+  fn alignment_must_be_a_power_of_two() {
+    for align in ["1", "2", "4", "8", "0x10"] {
+      let text = format!("(module (memory 1) (func i32.const 0 i32.load align={} drop))", align);
+      assert_eq!(messages(&text), Vec::<String>::new(), "{}", align);
+    }
+    for align in ["0", "3", "6", "0xffffffff"] {
+      let text = format!("(module (memory 1) (func i32.const 0 i32.load align={} drop))", align);
+      let found = messages(&text);
+      assert_eq!(found.len(), 1, "{}", align);
+      assert!(found[0].ends_with("must be a power of two"), "{}", found[0]);
+    }
+  }
+
+  const OUT_OF_RANGE: &str = "syntax error:  constant `99999999999` is out of range for `i32`";
+
+  // every module and function parsed, with the diagnostics
+  fn recover(text: &str) -> (Vec<usize>, Vec<String>) {
+    let mut parser = Parser::new(Lexer::new(text, "test.wat"));
+    let program = parser.parse_program();
+    let messages = parser.diagnostics().diagnostics().iter().map(|diagnostic| diagnostic.message.clone()).collect();
+    (
+      program.body.iter().map(|module| module.functions.len()).collect(),
+      messages,
+    )
+  }
+
+  #[test]
+  fn missing_paren_ends_at_the_next_field() {
+    let text = r#"(module
+      (func $f (result i32)
+        (i32.add (i32.const 1) (i32.const 2)
+      (func $h (result i32) i32.const 99999999999)
+      (func $ok)
+      (export "h" (func $h)))
+    (module $b (func (result i32) i32.const 99999999999) (func))"#;
+    let messages = ["syntax error:  expected `)`, but found `(`", OUT_OF_RANGE, OUT_OF_RANGE];
+    assert_eq!(recover(text), (vec![1, 1], messages.map(String::from).to_vec()));
+  }
+
+  #[test]
+  fn stray_paren_is_dropped() {
+    let text = r#"(module
+      (func $f (result i32) ( (i32.const 1))
+      (
+      (func $h (result i32) i32.const 99999999999)
+      (func $ok)
+      ()
+      (export "h" (func $h)))
+    (module $b (func (result i32) i32.const 99999999999) (func))"#;
+    let messages = [
+      "syntax error:  expected `instruction`, but found `(`",
+      "syntax error:  expected `module field`, but found `(`",
+      OUT_OF_RANGE,
+      "syntax error:  expected `module field`, but found `)`",
+      OUT_OF_RANGE,
+    ];
+    assert_eq!(recover(text), (vec![1, 1], messages.map(String::from).to_vec()));
+  }
+
+  #[test]
+  fn bad_token_skips_only_its_field() {
+    let text = r#"(module
+      (func $f (result i32) i32.const 1 bogus i32.const 99999999999)
+      (func $h (result i32) i32.const 99999999999)
+      (func $ok)
+      (oops (func $skipped))
+      (func $g $extra (export "g") (type 0) i32.const 99999999999)
+      (global i32 (i32.const 99999999999)))"#;
+    let messages = [
+      "syntax error:  expected `instruction`, but found `bogus`",
+      OUT_OF_RANGE,
+      "syntax error:  expected `module field`, but found `oops`",
+      "syntax error:  expected `)`, but found `$extra`",
+      OUT_OF_RANGE,
+    ];
+    assert_eq!(recover(text), (vec![1], messages.map(String::from).to_vec()));
   }
 }
@@ -0,0 +1,438 @@
+use super::{ast, type_uses::same_signature};
+use crate::diagnostics::{Diagnostic, DiagnosticManager, NameError, TypeError};
+
+type Result<T> = std::result::Result<T, Diagnostic>;
+
+// the `$id` of every item of one index space, in index order
+struct IndexSpace {
+  kind: &'static str,
+  ids: Vec<Option<ast::Identifier>>,
+}
+
+impl IndexSpace {
+  fn new(kind: &'static str) -> Self {
+    Self { kind, ids: vec![] }
+  }
+
+  fn define(&mut self, id: &Option<ast::Identifier>) -> Result<()> {
+    if let Some(id) = id {
+      if self.position(&id.name).is_some() {
+        let error = NameError::DuplicateIdentifier {
+          kind: self.kind.to_string(),
+          name: id.name.clone(),
+          range: id.range.clone(),
+        };
+        self.ids.push(None);
+        return Err(error.into());
+      }
+    }
+    self.ids.push(id.clone());
+    Ok(())
+  }
+
+  fn position(&self, name: &str) -> Option<usize> {
+    self.ids.iter().position(|id| id.as_ref().is_some_and(|id| id.name == name))
+  }
+
+  fn resolve(&self, index: &mut ast::Index) -> Result<()> {
+    let ast::Index::Symbolic(id) = index else {
+      return Ok(());
+    };
+    let Some(position) = self.position(&id.name) else {
+      return Err(unknown_identifier(self.kind, id));
+    };
+    *index = ast::Index::Numeric { value: position as u32, range: id.range.clone() };
+    Ok(())
+  }
+
+  fn resolve_type_use(&self, type_use: &mut ast::TypeUse) -> Result<()> {
+    match &mut type_use.index {
+      Some(index) => self.resolve(index),
+      None => Ok(()),
+    }
+  }
+}
+
+fn unknown_identifier(kind: &str, id: &ast::Identifier) -> Diagnostic {
+  let error = NameError::UnknownIdentifier { kind: kind.to_string(), name: id.name.clone(), range: id.range.clone() };
+  error.into()
+}
+
+// locals and enclosing block labels of the function being resolved
+struct FunctionScope {
+  locals: IndexSpace,
+  labels: Vec<Option<String>>, // innermost last, inner labels shadow outer ones
+}
+
+impl FunctionScope {
+  fn resolve_label(&self, index: &mut ast::Index) -> Result<()> {
+    let ast::Index::Symbolic(id) = index else {
+      return Ok(());
+    };
+    let Some(position) = self.labels.iter().rposition(|label| label.as_deref() == Some(id.name.as_str())) else {
+      return Err(unknown_identifier("label", id));
+    };
+    let depth = self.labels.len() - 1 - position;
+    *index = ast::Index::Numeric { value: depth as u32, range: id.range.clone() };
+    Ok(())
+  }
+}
+
+// module names must be unique within a file, each module is resolved on its own
+pub fn resolve_program(diagnostics: &mut DiagnosticManager, program: &mut ast::Program) {
+  let mut modules = IndexSpace::new("module");
+  for module in &mut program.body {
+    if let Err(diagnostic) = modules.define(&module.id) {
+      diagnostics.add(diagnostic);
+    }
+    Resolver::new(diagnostics).resolve(module);
+  }
+}
+
+// rewrites every `$id` of a module into the numeric index it refers to
+pub struct Resolver<'d, 'a> {
+  diagnostics: &'d mut DiagnosticManager<'a>,
+  types: IndexSpace,
+  functions: IndexSpace,
+  tables: IndexSpace,
+  memories: IndexSpace,
+  globals: IndexSpace,
+  signatures: Vec<ast::Signature>, // of each explicit type, in type index order
+}
+
+impl<'d, 'a> Resolver<'d, 'a> {
+  pub fn new(diagnostics: &'d mut DiagnosticManager<'a>) -> Self {
+    Self {
+      diagnostics,
+      types: IndexSpace::new("type"),
+      functions: IndexSpace::new("function"),
+      tables: IndexSpace::new("table"),
+      memories: IndexSpace::new("memory"),
+      globals: IndexSpace::new("global"),
+      signatures: vec![],
+    }
+  }
+
+  pub fn resolve(&mut self, module: &mut ast::Module) {
+    self.define_ids(module);
+
+    for import in &mut module.imports {
+      if let ast::ImportDesc::Func(type_use) = &mut import.desc {
+        let result = self.resolve_type_use(type_use);
+        self.report(result);
+      }
+    }
+    for function in &mut module.functions {
+      self.resolve_function(function);
+    }
+    for global in &mut module.globals {
+      self.resolve_const_expr(&mut global.init);
+    }
+    for export in &mut module.exports {
+      let result = match &mut export.desc {
+        ast::ExportDesc::Func(index) => self.functions.resolve(index),
+        ast::ExportDesc::Table(index) => self.tables.resolve(index),
+        ast::ExportDesc::Mem(index) => self.memories.resolve(index),
+        ast::ExportDesc::Global(index) => self.globals.resolve(index),
+      };
+      self.report(result);
+    }
+    if let Some(start) = &mut module.start {
+      let result = self.functions.resolve(&mut start.function);
+      self.report(result);
+    }
+    for element in &mut module.elements {
+      let result = self.tables.resolve(&mut element.table);
+      self.report(result);
+      self.resolve_const_expr(&mut element.offset);
+      for index in &mut element.init {
+        let result = self.functions.resolve(index);
+        self.report(result);
+      }
+    }
+    for data in &mut module.data {
+      let result = self.memories.resolve(&mut data.memory);
+      self.report(result);
+      self.resolve_const_expr(&mut data.offset);
+    }
+  }
+
+  // inline `(param ...)` and `(result ...)` next to `(type $t)` must repeat the signature of `$t`
+  fn resolve_type_use(&self, type_use: &mut ast::TypeUse) -> Result<()> {
+    self.types.resolve_type_use(type_use)?;
+    let signature = &type_use.signature;
+    let Some(ast::Index::Numeric { value, .. }) = &type_use.index else {
+      return Ok(());
+    };
+    // an unknown numeric index is reported when the module is compiled
+    let Some(definition) = self.signatures.get(*value as usize) else {
+      return Ok(());
+    };
+    if (signature.params.is_empty() && signature.results.is_empty()) || same_signature(definition, signature) {
+      return Ok(());
+    }
+    let error = TypeError::TypeMismatch {
+      expected: definition.to_string(),
+      found: signature.to_string(),
+      range: Some(signature.range.clone()),
+    };
+    Err(error.into())
+  }
+
+  fn report(&mut self, result: Result<()>) {
+    if let Err(diagnostic) = result {
+      self.diagnostics.add(diagnostic);
+    }
+  }
+
+  // imported items come first in each index space
+  fn define_ids(&mut self, module: &ast::Module) {
+    for definition in &module.types {
+      let result = self.types.define(&definition.id);
+      self.report(result);
+      self.signatures.push(definition.signature.clone());
+    }
+    for import in &module.imports {
+      let space = match import.desc {
+        ast::ImportDesc::Func(_) => &mut self.functions,
+        ast::ImportDesc::Table(_) => &mut self.tables,
+        ast::ImportDesc::Mem(_) => &mut self.memories,
+        ast::ImportDesc::Global(_) => &mut self.globals,
+      };
+      let result = space.define(&import.id);
+      self.report(result);
+    }
+    let mut results = vec![];
+    results.extend(module.functions.iter().map(|function| self.functions.define(&function.id)));
+    results.extend(module.tables.iter().map(|table| self.tables.define(&table.id)));
+    results.extend(module.memories.iter().map(|memory| self.memories.define(&memory.id)));
+    results.extend(module.globals.iter().map(|global| self.globals.define(&global.id)));
+    results.into_iter().for_each(|result| self.report(result));
+  }
+
+  fn resolve_function(&mut self, function: &mut ast::Function) {
+    let result = self.resolve_type_use(&mut function.type_use);
+    self.report(result);
+
+    let mut scope = FunctionScope { locals: IndexSpace::new("local"), labels: vec![] };
+    let params = &function.type_use.signature.params;
+    if params.is_empty() {
+      // `(func (type $t) ...)` declares the parameters of `$t`, all of them unnamed
+      let count = match &function.type_use.index {
+        Some(ast::Index::Numeric { value, .. }) => {
+          self.signatures.get(*value as usize).map_or(0, |signature| signature.params.len())
+        }
+        _ => 0,
+      };
+      (0..count).for_each(|_| scope.locals.ids.push(None));
+    }
+    for param in params {
+      let result = scope.locals.define(&param.id);
+      self.report(result);
+    }
+    for local in &function.locals {
+      let result = scope.locals.define(&local.id);
+      self.report(result);
+    }
+    self.resolve_instrs(&mut function.body, &mut scope);
+  }
+
+  // constant expressions can only refer to globals
+  fn resolve_const_expr(&mut self, instrs: &mut [ast::Instr]) {
+    let mut scope = FunctionScope { locals: IndexSpace::new("local"), labels: vec![] };
+    self.resolve_instrs(instrs, &mut scope);
+  }
+
+  fn resolve_instrs(&mut self, instrs: &mut [ast::Instr], scope: &mut FunctionScope) {
+    for instr in instrs {
+      self.resolve_instr(instr, scope);
+    }
+  }
+
+  fn resolve_block(&mut self, label: &Option<ast::Identifier>, instrs: &mut [ast::Instr], scope: &mut FunctionScope) {
+    scope.labels.push(label.as_ref().map(|label| label.name.clone()));
+    self.resolve_instrs(instrs, scope);
+    scope.labels.pop();
+  }
+
+  fn resolve_instr(&mut self, instr: &mut ast::Instr, scope: &mut FunctionScope) {
+    let result = match instr {
+      ast::Instr::Block(block) => {
+        self.resolve_block(&block.label, &mut block.instr, scope);
+        Ok(())
+      }
+      ast::Instr::Loop(block) => {
+        self.resolve_block(&block.label, &mut block.instr, scope);
+        Ok(())
+      }
+      ast::Instr::If(block) => {
+        self.resolve_instrs(&mut block.condition, scope);
+        self.resolve_block(&block.label, &mut block.instr, scope);
+        if let Some(else_instr) = &mut block.else_instr {
+          self.resolve_block(&block.label, else_instr, scope);
+        }
+        Ok(())
+      }
+      ast::Instr::Branch(branch) => scope.resolve_label(&mut branch.label),
+      ast::Instr::BranchIf(branch) => scope.resolve_label(&mut branch.label),
+      ast::Instr::BranchTable(branch) => {
+        for label in &mut branch.labels {
+          let result = scope.resolve_label(label);
+          self.report(result);
+        }
+        scope.resolve_label(&mut branch.default)
+      }
+      ast::Instr::Call(call) => self.functions.resolve(&mut call.function),
+      ast::Instr::CallIndirect(call) => self.resolve_type_use(&mut call.type_use),
+      ast::Instr::LocalGet(variable) | ast::Instr::LocalSet(variable) | ast::Instr::LocalTee(variable) => {
+        scope.locals.resolve(&mut variable.index)
+      }
+      ast::Instr::GlobalGet(variable) | ast::Instr::GlobalSet(variable) => self.globals.resolve(&mut variable.index),
+      _ => Ok(()),
+    };
+    self.report(result);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{bytes::instructions::Instruction, compiler::Compiler, lexer::Lexer, parser::Parser};
+
+  fn messages(text: &str) -> Vec<String> {
+    let mut parser = Parser::new(Lexer::new(text, "test.wat"));
+    let mut program = parser.parse_program();
+    resolve_program(parser.diagnostics(), &mut program);
+    parser.diagnostics().diagnostics().iter().map(|diagnostic| diagnostic.message.clone()).collect()
+  }
+
+  // the branch instructions of the first function, with their resolved depths
+  fn branches(text: &str) -> Vec<Instruction> {
+    let mut parser = Parser::new(Lexer::new(text, "test.wat"));
+    let mut program = parser.parse_program();
+    resolve_program(parser.diagnostics(), &mut program);
+    assert!(!parser.diagnostics().has_errors(), "{}", text);
+    let binary = Compiler::new(&program.body[0]).compile().unwrap();
+    let body = binary.function_body(0).unwrap();
+    let is_branch = |instruction: &Instruction| {
+      matches!(
+        instruction,
+        Instruction::Br(_) | Instruction::BrIf(_) | Instruction::BrTable { .. }
+      )
+    };
+    body.body.iter().map(|decoded| decoded.instruction.clone()).filter(is_branch).collect()
+  }
+
+  #[test]
+  fn unknown_identifiers() {
+    let cases = [
+      ("(module (func call $missing))", "unknown function `$missing`"),
+      ("(module (func local.get $x drop))", "unknown local `$x`"),
+      ("(module (func global.get $g drop))", "unknown global `$g`"),
+      ("(module (func (block br $done)))", "unknown label `$done`"),
+      (
+        "(module (func $f (local $x i32)) (func local.get $x drop))",
+        "unknown local `$x`",
+      ),
+      ("(module (export \"m\" (memory $mem)))", "unknown memory `$mem`"),
+      ("(module (func (type $t)))", "unknown type `$t`"),
+      (
+        "(module (table 1 funcref) (func $f) (elem (table $t) (i32.const 0) $f))",
+        "unknown table `$t`",
+      ),
+    ];
+    for (text, message) in cases {
+      assert_eq!(messages(text), vec![message.to_string()], "{}", text);
+    }
+  }
+
+  #[test]
+  fn duplicate_identifiers_per_index_space() {
+    let cases = [
+      ("(module (func $f) (func $f))", "duplicate function `$f`"),
+      (
+        "(module (import \"m\" \"f\" (func $f)) (func $f))",
+        "duplicate function `$f`",
+      ),
+      ("(module (func (param $x i32) (local $x i64)))", "duplicate local `$x`"),
+      (
+        "(module (type $t (func)) (type $t (func (param i32))))",
+        "duplicate type `$t`",
+      ),
+      (
+        "(module (global $g i32 (i32.const 0)) (global $g i32 (i32.const 1)))",
+        "duplicate global `$g`",
+      ),
+      ("(module $m) (module $m)", "duplicate module `$m`"),
+    ];
+    for (text, message) in cases {
+      assert_eq!(messages(text), vec![message.to_string()], "{}", text);
+    }
+    // each kind of item has its own index space
+    let text = "(module (type $x (func)) (func $x (local $x i32)) (memory $x 1) (global $x i32 (i32.const 0)))";
+    assert_eq!(messages(text), Vec::<String>::new());
+  }
+
+  #[test]
+  fn labels_resolve_to_their_depth() {
+    let text = r#"(module (func (local i32)
+      (block $outer
+        (loop $inner
+          (br_if $outer (local.get 0))
+          (block (br $inner))
+          (br_table $inner $outer $outer (local.get 0))))))"#;
+    let expected = [
+      Instruction::BrIf(1),
+      Instruction::Br(1),
+      Instruction::BrTable { labels: vec![0, 1], default: 1 },
+    ];
+    assert_eq!(branches(text), expected);
+  }
+
+  #[test]
+  fn inner_labels_shadow_outer_ones() {
+    let text =
+      "(module (func (block $l (block $l (br $l)) (br $l)) (if $l (i32.const 1) (then (br $l)) (else (br $l)))))";
+    assert_eq!(
+      branches(text),
+      [
+        Instruction::Br(0),
+        Instruction::Br(0),
+        Instruction::Br(0),
+        Instruction::Br(0)
+      ]
+    );
+    // a label is out of scope once its block ends
+    let text = "(module (func (block $l) br $l))";
+    assert_eq!(messages(text), vec!["unknown label `$l`".to_string()]);
+  }
+
+  #[test]
+  fn inline_signature_must_match_the_type() {
+    let valid = [
+      "(module (type $t (func (param i32) (result i32))) (func (type $t) (param $x i32) (result i32) local.get $x))",
+      "(module (type $t (func (param i32) (result i32))) (func (type $t) local.get 0))",
+      "(module (type (func (param i64))) (import \"m\" \"f\" (func (type 0) (param i64))))",
+    ];
+    for text in valid {
+      assert_eq!(messages(text), Vec::<String>::new(), "{}", text);
+    }
+    let invalid = [
+      (
+        "(module (type $t (func (param i32) (result i32))) (func (type $t) (param i64) (result i32) i32.const 0))",
+        "expected `[i32] -> [i32]`, but found `[i64] -> [i32]`",
+      ),
+      (
+        "(module (type $t (func)) (import \"m\" \"f\" (func (type $t) (result i32))))",
+        "expected `[] -> []`, but found `[] -> [i32]`",
+      ),
+      (
+        "(module (type $t (func (param i32))) (table 1 funcref) (func i32.const 0 call_indirect (type $t) (param f32)))",
+        "expected `[i32] -> []`, but found `[f32] -> []`",
+      ),
+    ];
+    for (text, message) in invalid {
+      assert_eq!(messages(text), vec![message.to_string()], "{}", text);
+    }
+  }
+}
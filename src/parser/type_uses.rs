@@ -0,0 +1,170 @@
+use super::ast;
+
+// a type use without `(type ...)` refers to the first type with the same signature,
+// types missing from the module are appended in the order they are used
+pub fn insert_implicit_types(module: &mut ast::Module) {
+  let types = &mut module.types;
+  for import in &mut module.imports {
+    if let ast::ImportDesc::Func(type_use) = &mut import.desc {
+      insert_type(types, type_use);
+    }
+  }
+  for function in &mut module.functions {
+    insert_type(types, &mut function.type_use);
+    insert_in_instrs(types, &mut function.body);
+  }
+}
+
+fn insert_in_instrs(types: &mut Vec<ast::Type>, instrs: &mut [ast::Instr]) {
+  for instr in instrs {
+    match instr {
+      ast::Instr::Block(block) => insert_in_instrs(types, &mut block.instr),
+      ast::Instr::Loop(block) => insert_in_instrs(types, &mut block.instr),
+      ast::Instr::If(block) => {
+        insert_in_instrs(types, &mut block.condition);
+        insert_in_instrs(types, &mut block.instr);
+        if let Some(else_instr) = &mut block.else_instr {
+          insert_in_instrs(types, else_instr);
+        }
+      }
+      ast::Instr::CallIndirect(call) => insert_type(types, &mut call.type_use),
+      _ => {}
+    }
+  }
+}
+
+fn insert_type(types: &mut Vec<ast::Type>, type_use: &mut ast::TypeUse) {
+  if type_use.index.is_some() {
+    return;
+  }
+  let signature = &type_use.signature;
+  let position = types.iter().position(|definition| same_signature(&definition.signature, signature));
+  let position = position.unwrap_or_else(|| {
+    types.push(ast::Type { id: None, signature: signature.clone(), range: signature.range.clone() });
+    types.len() - 1
+  });
+  type_use.index = Some(ast::Index::Numeric { value: position as u32, range: signature.range.clone() });
+}
+
+// parameter names are not part of the type
+pub(super) fn same_signature(first: &ast::Signature, second: &ast::Signature) -> bool {
+  let first_params = first.params.iter().map(|param| param.value_type);
+  let second_params = second.params.iter().map(|param| param.value_type);
+  first_params.eq(second_params) && first.results == second.results
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    bytes::{
+      instructions::Instruction,
+      module::Module,
+      types::{
+        ConstExpr, Data, Element, ElementType, Export, ExportDesc, FuncType, GlobalType, Import, ImportDesc, Limits,
+        MemoryType, TableType, ValueType,
+      },
+    },
+    compiler::Compiler,
+    lexer::Lexer,
+    parser::{resolve_program, Parser},
+  };
+
+  fn compile(text: &str) -> Module {
+    let mut parser = Parser::new(Lexer::new(text, "test.wat"));
+    let mut program = parser.parse_program();
+    resolve_program(parser.diagnostics(), &mut program);
+    assert!(!parser.diagnostics().has_errors(), "{}", text);
+    Compiler::new(&program.body[0]).compile().unwrap()
+  }
+
+  fn func_type(params: &[ValueType], results: &[ValueType]) -> FuncType {
+    FuncType { params: params.to_vec(), results: results.to_vec() }
+  }
+
+  #[test]
+  fn implicit_types_are_appended_in_use_order_once() {
+    let module = compile(
+      r#"(module
+        (type $a (func (param i32)))
+        (import "m" "f" (func (param f32)))
+        (func (param i32))
+        (func (result i64) (call_indirect (param f64) (i32.const 0)) (i64.const 0))
+        (func (param $x f32))
+        (func (type $a) (param i32))
+        (table 1 funcref))"#,
+    );
+    let types = [
+      func_type(&[ValueType::I32], &[]),
+      func_type(&[ValueType::F32], &[]),
+      func_type(&[], &[ValueType::I64]),
+      func_type(&[ValueType::F64], &[]),
+    ];
+    assert_eq!(module.type_section.as_deref(), Some(&types[..]));
+    assert_eq!(module.import_section.as_ref().unwrap()[0].desc, ImportDesc::Func(1));
+    assert_eq!(module.function_section.as_deref(), Some(&[0, 2, 1, 0][..]));
+    let body = module.function_body(1).unwrap();
+    assert!(body.body.iter().any(|decoded| decoded.instruction == Instruction::CallIndirect(3)));
+  }
+
+  #[test]
+  fn inline_exports_and_imports() {
+    let module = compile(
+      r#"(module
+        (func (import "m" "f") (param i32))
+        (memory (import "m" "mem") 1)
+        (global (export "imported") (import "m" "g") i32)
+        (func $g (export "g") (export "g2") nop)
+        (table (export "t") 1 funcref)
+        (global (export "defined") (mut i32) (i32.const 0)))"#,
+    );
+    let import = |name: &str, desc| Import { module: "m".to_string(), name: name.to_string(), desc };
+    let imports = [
+      import("f", ImportDesc::Func(0)),
+      import(
+        "mem",
+        ImportDesc::Memory(MemoryType { limits: Limits { min: 1, max: None } }),
+      ),
+      import(
+        "g",
+        ImportDesc::Global(GlobalType { value_type: ValueType::I32, mutable: false }),
+      ),
+    ];
+    assert_eq!(module.import_section.as_deref(), Some(&imports[..]));
+    let export = |name: &str, desc| Export { name: name.to_string(), desc };
+    let exports = [
+      export("imported", ExportDesc::Global(0)),
+      export("g", ExportDesc::Func(1)),
+      export("g2", ExportDesc::Func(1)),
+      export("t", ExportDesc::Table(0)),
+      export("defined", ExportDesc::Global(1)),
+    ];
+    assert_eq!(module.export_section.as_deref(), Some(&exports[..]));
+    assert_eq!(module.function_section.as_ref().map(Vec::len), Some(1));
+    assert_eq!(module.memory_section, None);
+  }
+
+  #[test]
+  fn inline_segments_size_their_table_and_memory() {
+    let module = compile(
+      r#"(module
+        (import "m" "t" (table 1 funcref))
+        (func $a) (func $b)
+        (table funcref (elem $a $b $a))
+        (memory (data "hi" "\21")))"#,
+    );
+    let table = TableType { element_type: ElementType::FuncRef, limits: Limits { min: 3, max: Some(3) } };
+    assert_eq!(module.table_section.as_deref(), Some(&[table][..]));
+    let element = Element { table_idx: 1, offset: ConstExpr::I32Const(0), init: vec![0, 1, 0] };
+    assert_eq!(module.element_section.as_deref(), Some(&[element][..]));
+    let memory = MemoryType { limits: Limits { min: 1, max: Some(1) } };
+    assert_eq!(module.memory_section.as_deref(), Some(&[memory][..]));
+    let data = Data { memory_idx: 0, offset: ConstExpr::I32Const(0), init: b"hi!".to_vec() };
+    assert_eq!(module.data_section.as_deref(), Some(&[data][..]));
+    // an empty segment gives an empty memory
+    let module = compile("(module (memory (data)))");
+    assert_eq!(
+      module.memory_section.unwrap()[0].limits,
+      Limits { min: 0, max: Some(0) }
+    );
+  }
+}
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::bytes::{
+  instructions::{DecodedInstruction, Instruction},
+  types::{DecodedBody, ValueType},
+};
+
+// a function body ready to run, with the `end` and `else` matching each block resolved up front
+#[derive(Debug)]
+pub(super) struct Code {
+  pub locals: Vec<ValueType>, // declared locals, without the parameters
+  pub instructions: Vec<DecodedInstruction>,
+  ends: Vec<usize>,             // `block`, `loop`, `if` and `else` -> position of their `end`
+  elses: HashMap<usize, usize>, // `if` -> position of its `else`
+}
+
+impl Code {
+  pub fn new(body: &DecodedBody) -> Self {
+    let locals =
+      body.locals.iter().flat_map(|local| std::iter::repeat_n(local.value_type, local.count as usize)).collect();
+    let mut ends = vec![0; body.body.len()];
+    let mut elses = HashMap::new();
+    let mut open = vec![];
+    for (position, decoded) in body.body.iter().enumerate() {
+      match decoded.instruction {
+        Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) => open.push(position),
+        Instruction::Else => {
+          if let Some(start) = open.last() {
+            elses.insert(*start, position);
+          }
+        }
+        Instruction::End => {
+          // the final `end` closes the function, not a block
+          let Some(start) = open.pop() else { continue };
+          ends[start] = position;
+          if let Some(else_position) = elses.get(&start) {
+            ends[*else_position] = position;
+          }
+        }
+        _ => {}
+      }
+    }
+    Self { locals, instructions: body.body.clone(), ends, elses }
+  }
+
+  pub fn end(&self, position: usize) -> usize {
+    self.ends[position]
+  }
+
+  pub fn else_position(&self, position: usize) -> Option<usize> {
+    self.elses.get(&position).copied()
+  }
+}
@@ -0,0 +1,95 @@
+use crate::{
+  bytes::types::GlobalType,
+  diagnostics::{ResultWithDiagnostics, RuntimeError},
+};
+
+use super::value::{check_values, Value};
+
+// https://webassembly.github.io/spec/core/exec/runtime.html#global-instances, shared through `Rc` by every instance
+// importing it and by the host
+#[derive(Debug)]
+pub struct Global {
+  pub global_type: GlobalType,
+  value: Value,
+}
+
+impl Global {
+  pub fn new(global_type: GlobalType, value: Value) -> ResultWithDiagnostics<Self> {
+    check_values(&[value], &[global_type.value_type])?;
+    Ok(Self { global_type, value })
+  }
+
+  pub fn get(&self) -> Value {
+    self.value
+  }
+
+  // validation rejects `global.set` of an immutable global, this guards the host
+  pub fn set(&mut self, value: Value) -> ResultWithDiagnostics<()> {
+    if !self.global_type.mutable {
+      return Err(RuntimeError::ImmutableGlobal { range: None }.into());
+    }
+    check_values(&[value], &[self.global_type.value_type])?;
+    self.value = value;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    bytes::{module::Module, types::ValueType},
+    runtime::{
+      imports::{Extern, Imports},
+      instance::Instance,
+    },
+  };
+
+  fn instantiate(text: &str, imports: &Imports) -> Instance {
+    Instance::new(Module::new(&wat::parse_str(text).unwrap()).unwrap(), imports).unwrap()
+  }
+
+  fn get(instance: &Instance) -> Value {
+    instance.invoke("get", &[]).unwrap()[0]
+  }
+
+  #[test]
+  fn mutable_globals_are_shared_by_instances_and_the_host() {
+    let accessors = r#"
+      (func (export "get") (result i32) (global.get $g))
+      (func (export "set") (param i32) (global.set $g (local.get 0)))"#;
+    let exporter = instantiate(
+      &format!(
+        r#"(module (global $g (export "g") (mut i32) (i32.const 1)) {})"#,
+        accessors
+      ),
+      &Imports::new(),
+    );
+    let global = exporter.export_global("g").unwrap();
+    let mut imports = Imports::new();
+    imports.define("env", "g", Extern::Global(global.clone()));
+    let importer = instantiate(
+      &format!(r#"(module (global $g (import "env" "g") (mut i32)) {})"#, accessors),
+      &imports,
+    );
+    assert_eq!(get(&importer), Value::I32(1));
+    importer.invoke("set", &[Value::I32(2)]).unwrap();
+    assert_eq!((get(&exporter), global.borrow().get()), (Value::I32(2), Value::I32(2)));
+    exporter.invoke("set", &[Value::I32(3)]).unwrap();
+    assert_eq!(get(&importer), Value::I32(3));
+    global.borrow_mut().set(Value::I32(4)).unwrap();
+    assert_eq!((get(&exporter), get(&importer)), (Value::I32(4), Value::I32(4)));
+  }
+
+  #[test]
+  fn only_mutable_globals_can_be_set() {
+    let instance = instantiate(r#"(module (global (export "g") i32 (i32.const 1)))"#, &Imports::new());
+    let global = instance.export_global("g").unwrap();
+    let error = global.borrow_mut().set(Value::I32(2)).unwrap_err();
+    assert_eq!(error.message, "global is immutable");
+    assert_eq!(global.borrow().get(), Value::I32(1));
+    let mut global = Global::new(GlobalType { value_type: ValueType::I32, mutable: true }, Value::I32(1)).unwrap();
+    let error = global.set(Value::I64(2)).unwrap_err();
+    assert_eq!(error.message, "type mismatch: expected `[i32]` but found `[i64]`");
+  }
+}
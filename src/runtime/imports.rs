@@ -0,0 +1,60 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{bytes::types::FuncType, diagnostics::ResultWithDiagnostics};
+
+use super::{global::Global, memory::Memory, table::Table, value::Value};
+
+pub type HostCallback = dyn Fn(&[Value]) -> ResultWithDiagnostics<Vec<Value>>;
+
+// a function implemented by the embedder, called with arguments matching `func_type`
+#[derive(Clone)]
+pub struct HostFunction {
+  pub func_type: FuncType,
+  callback: Rc<HostCallback>,
+}
+
+impl HostFunction {
+  pub fn new(func_type: FuncType, callback: impl Fn(&[Value]) -> ResultWithDiagnostics<Vec<Value>> + 'static) -> Self {
+    Self { func_type, callback: Rc::new(callback) }
+  }
+
+  pub fn call(&self, args: &[Value]) -> ResultWithDiagnostics<Vec<Value>> {
+    (self.callback)(args)
+  }
+}
+
+impl std::fmt::Debug for HostFunction {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "<host function {}>", self.func_type)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum Extern {
+  Func(HostFunction),
+  // the same cell can be imported by several instances, e.g. one exported by `Instance::export_global`
+  Global(Rc<RefCell<Global>>),
+  // its elements are function indexes of the importing instance, e.g. `env.__indirect_function_table`
+  Table(Rc<RefCell<Table>>),
+  Memory(Rc<RefCell<Memory>>),
+}
+
+// what the host provides to a module, by `module` and `name` of the import
+#[derive(Debug, Default)]
+pub struct Imports {
+  externs: HashMap<(String, String), Extern>,
+}
+
+impl Imports {
+  pub fn new() -> Self {
+    Self { externs: HashMap::new() }
+  }
+
+  pub fn define(&mut self, module: &str, name: &str, value: Extern) {
+    self.externs.insert((module.to_string(), name.to_string()), value);
+  }
+
+  pub fn get(&self, module: &str, name: &str) -> Option<&Extern> {
+    self.externs.get(&(module.to_string(), name.to_string()))
+  }
+}
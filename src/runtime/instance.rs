@@ -0,0 +1,368 @@
+use std::{
+  cell::{OnceCell, RefCell},
+  rc::Rc,
+};
+
+use crate::{
+  bytes::{
+    module::Module,
+    types::{ConstExpr, ExportDesc, FuncType, ImportDesc, Limits, MemoryType, TableType},
+  },
+  diagnostics::{ResultWithDiagnostics, RuntimeError},
+  validator::{validate_function, Context, Validator},
+};
+
+use super::{
+  code::Code,
+  global::Global,
+  imports::{Extern, HostFunction, Imports},
+  interpreter::Interpreter,
+  memory::Memory,
+  table::Table,
+  value::{check_values, Value},
+};
+
+// imported functions come first in the index space, like in the module
+#[derive(Debug)]
+pub(super) enum Function {
+  Host {
+    type_idx: u32,
+    host: HostFunction,
+  },
+  // `index` is the position in the code section, the code is built on the first call
+  Wasm {
+    type_idx: u32,
+    index: usize,
+    code: OnceCell<Code>,
+  },
+}
+
+impl Function {
+  pub fn type_idx(&self) -> u32 {
+    match self {
+      Function::Host { type_idx, .. } | Function::Wasm { type_idx, .. } => *type_idx,
+    }
+  }
+}
+
+// https://webassembly.github.io/spec/core/exec/modules.html#instantiation
+#[derive(Debug)]
+pub struct Instance {
+  pub module: Module,
+  pub(super) functions: Vec<Function>,
+  pub(super) tables: Vec<Rc<RefCell<Table>>>,
+  pub(super) memories: Vec<Rc<RefCell<Memory>>>,
+  pub(super) globals: Vec<Rc<RefCell<Global>>>,
+  context: Context, // to validate function bodies
+}
+
+impl Instance {
+  // validates the module but not its function bodies, resolves its imports, initializes tables and memories
+  // and runs the start function
+  pub fn new(module: Module, imports: &Imports) -> ResultWithDiagnostics<Self> {
+    let validator = Validator::new(&module);
+    validator.validate_module()?;
+    let context = validator.into_context();
+    let (mut functions, mut tables, mut memories, mut globals) = (vec![], vec![], vec![], vec![]);
+    for import in module.import_section.iter().flatten() {
+      match (&import.desc, imports.get(&import.module, &import.name)) {
+        (ImportDesc::Func(type_idx), Some(Extern::Func(host))) => {
+          let func_type = &module.type_section.as_ref().unwrap()[*type_idx as usize];
+          if host.func_type != *func_type {
+            let found = host.func_type.to_string();
+            return Err(RuntimeError::TypeMismatch { expected: func_type.to_string(), found, range: None }.into());
+          }
+          functions.push(Function::Host { type_idx: *type_idx, host: host.clone() });
+        }
+        (ImportDesc::Global(global_type), Some(Extern::Global(global))) => {
+          let found = &global.borrow().global_type;
+          if found != global_type {
+            let error =
+              RuntimeError::TypeMismatch { expected: global_type.to_string(), found: found.to_string(), range: None };
+            return Err(error.into());
+          }
+          globals.push(global.clone());
+        }
+        // the current size counts as the minimum, the host can have grown it
+        (ImportDesc::Table(table_type), Some(Extern::Table(table))) => {
+          let found = &table.borrow().table_type;
+          let limits = Limits { min: table.borrow().size(), max: found.limits.max };
+          if found.element_type != table_type.element_type || !limits.matches(&table_type.limits) {
+            let found = TableType { limits, ..found.clone() }.to_string();
+            return Err(RuntimeError::TypeMismatch { expected: table_type.to_string(), found, range: None }.into());
+          }
+          tables.push(table.clone());
+        }
+        (ImportDesc::Memory(memory_type), Some(Extern::Memory(memory))) => {
+          let limits = Limits { min: memory.borrow().size(), max: memory.borrow().memory_type.limits.max };
+          if !limits.matches(&memory_type.limits) {
+            let found = MemoryType { limits }.to_string();
+            return Err(RuntimeError::TypeMismatch { expected: memory_type.to_string(), found, range: None }.into());
+          }
+          memories.push(memory.clone());
+        }
+        _ => {
+          let (module, name) = (import.module.clone(), import.name.clone());
+          return Err(RuntimeError::UnknownImport { module, name, range: None }.into());
+        }
+      }
+    }
+    for (index, type_idx) in module.function_section.iter().flatten().enumerate() {
+      functions.push(Function::Wasm { type_idx: *type_idx, index, code: OnceCell::new() });
+    }
+    for table_type in module.table_section.iter().flatten() {
+      tables.push(Rc::new(RefCell::new(Table::new(table_type.clone())?)));
+    }
+    for memory_type in module.memory_section.iter().flatten() {
+      memories.push(Rc::new(RefCell::new(Memory::new(memory_type.clone())?)));
+    }
+    let mut instance = Self { functions, tables, memories, globals, context, module };
+    instance.initialize_globals()?;
+    instance.initialize_elements()?;
+    instance.initialize_data()?;
+    if let Some(func_idx) = instance.module.start_section {
+      instance.call(func_idx, &[])?;
+    }
+    Ok(instance)
+  }
+
+  // initializers only read imported globals, which are already in place
+  fn initialize_globals(&mut self) -> ResultWithDiagnostics<()> {
+    for global in self.module.global_section.iter().flatten() {
+      let value = self.evaluate(&global.init)?;
+      self.globals.push(Rc::new(RefCell::new(Global::new(global.global_type.clone(), value)?)));
+    }
+    Ok(())
+  }
+
+  fn initialize_elements(&self) -> ResultWithDiagnostics<()> {
+    for element in self.module.element_section.iter().flatten() {
+      let Value::I32(offset) = self.evaluate(&element.offset)? else {
+        unreachable!("validated element offsets are i32");
+      };
+      let mut table = self.tables[element.table_idx as usize].borrow_mut();
+      table.init(offset as u32, &element.init)?;
+    }
+    Ok(())
+  }
+
+  // segments are copied in order, an out of bounds one fails instantiation after the previous ones were written
+  fn initialize_data(&self) -> ResultWithDiagnostics<()> {
+    for data in self.module.data_section.iter().flatten() {
+      let Value::I32(offset) = self.evaluate(&data.offset)? else {
+        unreachable!("validated data offsets are i32");
+      };
+      let mut memory = self.memories[data.memory_idx as usize].borrow_mut();
+      memory.write(offset as u32 as u64, &data.init)?;
+    }
+    Ok(())
+  }
+
+  pub fn evaluate(&self, expr: &ConstExpr) -> ResultWithDiagnostics<Value> {
+    let value = match expr {
+      ConstExpr::I32Const(value) => Value::I32(*value),
+      ConstExpr::I64Const(value) => Value::I64(*value),
+      ConstExpr::F32Const(bits) => Value::F32(f32::from_bits(*bits)),
+      ConstExpr::F64Const(bits) => Value::F64(f64::from_bits(*bits)),
+      ConstExpr::GlobalGet(global_idx) => match self.globals.get(*global_idx as usize) {
+        Some(global) => global.borrow().get(),
+        None => return Err(RuntimeError::UnknownGlobal { name: global_idx.to_string(), range: None }.into()),
+      },
+    };
+    Ok(value)
+  }
+
+  pub fn table(&self, table_idx: u32) -> Option<&RefCell<Table>> {
+    self.tables.get(table_idx as usize).map(Rc::as_ref)
+  }
+
+  pub fn memory(&self, memory_idx: u32) -> Option<&RefCell<Memory>> {
+    self.memories.get(memory_idx as usize).map(Rc::as_ref)
+  }
+
+  // the body of a wasm function is decoded and validated on its first call, an invalid one fails every call
+  pub(super) fn code(&self, func_idx: u32) -> ResultWithDiagnostics<&Code> {
+    let Function::Wasm { index, code, .. } = &self.functions[func_idx as usize] else {
+      unreachable!("host functions have no code");
+    };
+    if let Some(code) = code.get() {
+      return Ok(code);
+    }
+    validate_function(&self.module, &self.context, *index)?;
+    let body = self.module.function_body(*index)?;
+    Ok(code.get_or_init(|| Code::new(body)))
+  }
+
+  pub fn func_type(&self, func_idx: u32) -> &FuncType {
+    let type_idx = self.functions[func_idx as usize].type_idx();
+    &self.module.type_section.as_ref().unwrap()[type_idx as usize]
+  }
+
+  pub fn export_function(&self, name: &str) -> Option<u32> {
+    self.module.export_section.iter().flatten().find_map(|export| match export.desc {
+      ExportDesc::Func(func_idx) if export.name == name => Some(func_idx),
+      _ => None,
+    })
+  }
+
+  // the exported cell itself, so the host or another instance importing it sees every `global.set`
+  pub fn export_global(&self, name: &str) -> Option<Rc<RefCell<Global>>> {
+    self.module.export_section.iter().flatten().find_map(|export| match export.desc {
+      ExportDesc::Global(global_idx) if export.name == name => Some(self.globals[global_idx as usize].clone()),
+      _ => None,
+    })
+  }
+
+  // calls an exported function, the arguments must match its parameters
+  pub fn invoke(&self, name: &str, args: &[Value]) -> ResultWithDiagnostics<Vec<Value>> {
+    let Some(func_idx) = self.export_function(name) else {
+      return Err(RuntimeError::UnknownFunction { name: name.to_string(), range: None }.into());
+    };
+    check_values(args, &self.func_type(func_idx).params)?;
+    self.call(func_idx, args)
+  }
+
+  pub fn call(&self, func_idx: u32, args: &[Value]) -> ResultWithDiagnostics<Vec<Value>> {
+    Interpreter::new(self).call(func_idx, args)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    bytes::types::{ElementType, GlobalType, ValueType},
+    diagnostics::Diagnostic,
+  };
+
+  fn instantiate(text: &str) -> Instance {
+    instantiate_with(text, &Imports::new()).unwrap()
+  }
+
+  fn instantiate_with(text: &str, imports: &Imports) -> Result<Instance, String> {
+    Instance::new(Module::new(&wat::parse_str(text).unwrap()).unwrap(), imports).map_err(|error| error.message)
+  }
+
+  fn table(min: u32, max: Option<u32>) -> Rc<RefCell<Table>> {
+    let table_type = TableType { element_type: ElementType::FuncRef, limits: Limits { min, max } };
+    Rc::new(RefCell::new(Table::new(table_type).map_err(Diagnostic::from).unwrap()))
+  }
+
+  fn memory(min: u32, max: Option<u32>) -> Rc<RefCell<Memory>> {
+    Rc::new(RefCell::new(
+      Memory::new(MemoryType { limits: Limits { min, max } }).map_err(Diagnostic::from).unwrap(),
+    ))
+  }
+
+  #[test]
+  fn function_bodies_are_validated_on_their_first_call() {
+    let instance = instantiate(
+      r#"(module
+        (func (export "good") (result i32) i32.const 7)
+        (func (export "bad") (result i32) i64.const 1))"#,
+    );
+    assert_eq!(instance.invoke("good", &[]).unwrap(), [Value::I32(7)]);
+    let Function::Wasm { code, .. } = &instance.functions[1] else {
+      unreachable!()
+    };
+    assert!(code.get().is_none());
+    for _ in 0..2 {
+      let diagnostic = instance.invoke("bad", &[]).unwrap_err();
+      assert!(diagnostic.message.contains("in func[1]"), "{}", diagnostic.message);
+    }
+    assert!(code.get().is_none());
+  }
+
+  #[test]
+  fn tables_and_memories_are_imported() {
+    let (table, memory) = (table(1, None), memory(1, Some(2)));
+    let mut imports = Imports::new();
+    imports.define("env", "__indirect_function_table", Extern::Table(table.clone()));
+    imports.define("env", "memory", Extern::Memory(memory.clone()));
+    let instance = instantiate_with(
+      r#"(module
+        (import "env" "__indirect_function_table" (table 1 funcref))
+        (import "env" "memory" (memory 1 2))
+        (elem (i32.const 0) $seven)
+        (func $seven (result i32) (i32.const 7))
+        (func (export "run") (i32.store (i32.const 8) (call_indirect (result i32) (i32.const 0)))))"#,
+      &imports,
+    )
+    .unwrap();
+    assert_eq!(table.borrow().get(0).ok(), Some(Some(0)));
+    instance.invoke("run", &[]).unwrap();
+    assert_eq!(memory.borrow().read(8, 4).ok(), Some(&[7, 0, 0, 0][..]));
+  }
+
+  #[test]
+  fn imported_tables_and_memories_must_match_their_limits() {
+    let shared = memory(1, Some(2));
+    let mut imports = Imports::new();
+    imports.define("env", "table", Extern::Table(table(1, None)));
+    imports.define("env", "memory", Extern::Memory(shared.clone()));
+    let import = |desc: &str| instantiate_with(&format!(r#"(module (import "env" {}))"#, desc), &imports).map(|_| ());
+    assert_eq!(import(r#""table" (table 0 funcref)"#), Ok(()));
+    assert_eq!(import(r#""memory" (memory 0 3)"#), Ok(()));
+    let mismatch =
+      |expected: &str, found: &str| Err(format!("type mismatch: expected `{}` but found `{}`", expected, found));
+    assert_eq!(
+      import(r#""table" (table 2 funcref)"#),
+      mismatch("(table 2 funcref)", "(table 1 funcref)")
+    );
+    assert_eq!(
+      import(r#""table" (table 1 5 funcref)"#),
+      mismatch("(table 1 5 funcref)", "(table 1 funcref)")
+    );
+    assert_eq!(
+      import(r#""table" (table 1 externref)"#),
+      mismatch("(table 1 externref)", "(table 1 funcref)")
+    );
+    assert_eq!(
+      import(r#""memory" (memory 1 1)"#),
+      mismatch("(memory 1 1)", "(memory 1 2)")
+    );
+    // a grown memory is as large as its current size
+    shared.borrow_mut().grow(1).unwrap();
+    assert_eq!(import(r#""memory" (memory 2)"#), Ok(()));
+    assert_eq!(
+      import(r#""table" (memory 1)"#),
+      Err("unknown import `env.table`".to_string())
+    );
+  }
+
+  #[test]
+  fn imports_must_exist_and_match_their_type() {
+    let mut imports = Imports::new();
+    let add = FuncType { params: vec![ValueType::I32, ValueType::I32], results: vec![ValueType::I32] };
+    imports.define(
+      "env",
+      "add",
+      Extern::Func(HostFunction::new(add, |args| Ok(args[..1].to_vec()))),
+    );
+    let counter = Global::new(GlobalType { value_type: ValueType::I32, mutable: true }, Value::I32(0));
+    imports.define(
+      "env",
+      "counter",
+      Extern::Global(Rc::new(RefCell::new(counter.unwrap()))),
+    );
+    let import = |desc: &str| instantiate_with(&format!(r#"(module (import "env" {}))"#, desc), &imports).map(|_| ());
+    assert_eq!(import(r#""add" (func (param i32 i32) (result i32))"#), Ok(()));
+    assert_eq!(import(r#""counter" (global (mut i32))"#), Ok(()));
+    let mismatch =
+      |expected: &str, found: &str| Err(format!("type mismatch: expected `{}` but found `{}`", expected, found));
+    assert_eq!(
+      import(r#""add" (func (param i32) (result i32))"#),
+      mismatch("[i32] -> [i32]", "[i32 i32] -> [i32]")
+    );
+    assert_eq!(import(r#""counter" (global i32)"#), mismatch("i32", "(mut i32)"));
+    assert_eq!(
+      import(r#""counter" (global (mut i64))"#),
+      mismatch("(mut i64)", "(mut i32)")
+    );
+    assert_eq!(
+      import(r#""counter" (func)"#),
+      Err("unknown import `env.counter`".to_string())
+    );
+    assert_eq!(import(r#""sub" (func)"#), Err("unknown import `env.sub`".to_string()));
+  }
+}
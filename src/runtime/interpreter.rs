@@ -0,0 +1,460 @@
+use crate::{
+  bytes::instructions::{BlockType, Instruction},
+  diagnostics::{Diagnostic, ResultWithDiagnostics, RuntimeError},
+  utils::range::Range,
+};
+
+use super::{
+  code::Code,
+  instance::{Function, Instance},
+  memory::execute_memory,
+  numeric::execute_numeric,
+  stack::ValueStack,
+  value::{check_values, Value},
+};
+
+type Result<T> = ResultWithDiagnostics<T>;
+
+// nested calls before `StackOverflow`, frames live on the heap so this only bounds runaway recursion
+const MAX_CALL_DEPTH: usize = 10_000;
+// a runaway recursion would otherwise print every one of its frames
+const MAX_BACKTRACE_FRAMES: usize = 32;
+
+// where a branch to a block continues, and how many values it carries there
+#[derive(Debug, Clone, Copy)]
+struct Label {
+  arity: usize,
+  height: usize,       // operands below this height belong to the enclosing blocks
+  continuation: usize, // the `loop` itself or the instruction after the `end`
+}
+
+struct Frame<'a> {
+  func_idx: u32,
+  code: &'a Code,
+  pc: usize,
+  locals: Vec<Value>,
+  labels: Vec<Label>, // the function body is the outermost label, branching to it returns
+}
+
+impl<'a> Frame<'a> {
+  // the instruction being executed, for callers the `call` they are waiting on
+  fn position(&self) -> &'a Range {
+    &self.code.instructions[self.pc - 1].range
+  }
+}
+
+// https://webassembly.github.io/spec/core/exec/instructions.html, calls push frames instead of recursing
+pub(super) struct Interpreter<'a> {
+  instance: &'a Instance,
+  stack: ValueStack,
+  frames: Vec<Frame<'a>>,
+}
+
+impl<'a> Interpreter<'a> {
+  pub fn new(instance: &'a Instance) -> Self {
+    Self { instance, stack: ValueStack::default(), frames: vec![] }
+  }
+
+  pub fn call(mut self, func_idx: u32, args: &[Value]) -> Result<Vec<Value>> {
+    self.stack.extend(args.to_vec());
+    self.enter(func_idx)?;
+    self.run()?;
+    let arity = self.instance.func_type(func_idx).results.len();
+    Ok(self.stack.pop_n(arity))
+  }
+
+  // host functions run to completion, wasm functions get a new frame
+  fn enter(&mut self, func_idx: u32) -> Result<()> {
+    let func_type = self.instance.func_type(func_idx);
+    let mut args = self.stack.pop_n(func_type.params.len());
+    match &self.instance.functions[func_idx as usize] {
+      Function::Host { host, .. } => {
+        let results = host.call(&args)?;
+        check_values(&results, &func_type.results)?;
+        self.stack.extend(results);
+      }
+      Function::Wasm { .. } => {
+        let code = self.instance.code(func_idx)?;
+        if self.frames.len() == MAX_CALL_DEPTH {
+          return Err(RuntimeError::StackOverflow { range: None }.into());
+        }
+        args.extend(code.locals.iter().map(|value_type| Value::default_for(*value_type)));
+        let label =
+          Label { arity: func_type.results.len(), height: self.stack.len(), continuation: code.instructions.len() };
+        self.frames.push(Frame { func_idx, code, pc: 0, locals: args, labels: vec![label] });
+      }
+    }
+    Ok(())
+  }
+
+  // signatures are compared structurally, two type indexes with the same params and results match
+  fn resolve_indirect(&mut self, type_idx: u32) -> Result<u32> {
+    let index = self.stack.pop_i32()? as u32;
+    // validation guarantees table 0 exists
+    let Some(func_idx) = self.instance.tables[0].borrow().get(index)? else {
+      return Err(RuntimeError::UninitializedElement { index, range: None }.into());
+    };
+    let expected = &self.instance.module.type_section.as_ref().unwrap()[type_idx as usize];
+    if self.instance.func_type(func_idx) != expected {
+      return Err(RuntimeError::CallIndirect { type_idx, range: None }.into());
+    }
+    Ok(func_idx)
+  }
+
+  fn run(&mut self) -> Result<()> {
+    while let Some(frame) = self.frames.last_mut() {
+      let code = frame.code;
+      let Some(decoded) = code.instructions.get(frame.pc) else {
+        // the results are already on top of the stack
+        self.frames.pop();
+        continue;
+      };
+      frame.pc += 1;
+      self.execute(&decoded.instruction).map_err(|diagnostic| self.trace(diagnostic))?;
+    }
+    Ok(())
+  }
+
+  // points the error at the failing instruction and lists the active calls, innermost first
+  fn trace(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+    if let Some(frame) = self.frames.last() {
+      diagnostic.range = diagnostic.range.or_else(|| Some(frame.position().clone()));
+    }
+    let module = &self.instance.module;
+    // compiled text modules have no bytes, their ranges are text offsets that mean nothing in a backtrace
+    let binary = !module.bytes.as_slice().is_empty();
+    let mut backtrace = "wasm backtrace:".to_string();
+    for (depth, frame) in self.frames.iter().rev().enumerate().take(MAX_BACKTRACE_FRAMES) {
+      let name = module.display_function(frame.func_idx);
+      match binary {
+        true => backtrace.push_str(&format!("\n  {}: 0x{:x} - {}", depth, frame.position().start, name)),
+        false => backtrace.push_str(&format!("\n  {}: {}", depth, name)),
+      }
+    }
+    if self.frames.len() > MAX_BACKTRACE_FRAMES {
+      backtrace.push_str(&format!(
+        "\n  ... {} more frames",
+        self.frames.len() - MAX_BACKTRACE_FRAMES
+      ));
+    }
+    diagnostic.hint = Some(backtrace);
+    diagnostic
+  }
+
+  fn frame(&mut self) -> &mut Frame<'a> {
+    self.frames.last_mut().unwrap()
+  }
+
+  fn push_label(&mut self, block_type: &BlockType, continuation: usize) {
+    let arity = match block_type {
+      BlockType::Empty => 0,
+      BlockType::Value(_) => 1,
+    };
+    let height = self.stack.len();
+    self.frame().labels.push(Label { arity, height, continuation });
+  }
+
+  // keeps the values the label carries and drops everything else pushed since it was entered
+  fn branch(&mut self, depth: u32) {
+    let frame = self.frames.last_mut().unwrap();
+    let index = frame.labels.len() - 1 - depth as usize;
+    let label = frame.labels[index];
+    let values = self.stack.pop_n(label.arity);
+    self.stack.truncate(label.height);
+    self.stack.extend(values);
+    frame.labels.truncate(index);
+    frame.pc = label.continuation;
+  }
+
+  fn execute(&mut self, instruction: &Instruction) -> Result<()> {
+    match instruction {
+      Instruction::Unreachable => return Err(RuntimeError::Unreachable { range: None }.into()),
+      Instruction::Nop => {}
+      Instruction::Block(block_type) => {
+        let frame = self.frame();
+        let continuation = frame.code.end(frame.pc - 1) + 1;
+        self.push_label(block_type, continuation);
+      }
+      Instruction::Loop(_) => {
+        let continuation = self.frame().pc - 1;
+        self.push_label(&BlockType::Empty, continuation);
+      }
+      Instruction::If(block_type) => {
+        let condition = self.stack.pop_i32()?;
+        let frame = self.frame();
+        let (code, position) = (frame.code, frame.pc - 1);
+        if condition != 0 {
+          self.push_label(block_type, code.end(position) + 1);
+        } else if let Some(else_position) = code.else_position(position) {
+          self.push_label(block_type, code.end(position) + 1);
+          self.frame().pc = else_position + 1;
+        } else {
+          self.frame().pc = code.end(position) + 1;
+        }
+      }
+      // the `then` branch is over, skip the `else` one
+      Instruction::Else => self.branch(0),
+      Instruction::End => {
+        self.frame().labels.pop();
+      }
+      Instruction::Br(depth) => self.branch(*depth),
+      Instruction::BrIf(depth) => {
+        if self.stack.pop_i32()? != 0 {
+          self.branch(*depth);
+        }
+      }
+      Instruction::BrTable { labels, default } => {
+        let index = self.stack.pop_i32()? as u32 as usize;
+        self.branch(*labels.get(index).unwrap_or(default));
+      }
+      Instruction::Return => {
+        let depth = self.frame().labels.len() - 1;
+        self.branch(depth as u32);
+      }
+      Instruction::Call(func_idx) => self.enter(*func_idx)?,
+      Instruction::CallIndirect(type_idx) => {
+        let func_idx = self.resolve_indirect(*type_idx)?;
+        self.enter(func_idx)?;
+      }
+      Instruction::Drop => {
+        self.stack.pop()?;
+      }
+      Instruction::Select => {
+        let condition = self.stack.pop_i32()?;
+        let second = self.stack.pop()?;
+        let first = self.stack.pop()?;
+        self.stack.push(if condition != 0 { first } else { second });
+      }
+      Instruction::LocalGet(local_idx) => {
+        let value = self.frame().locals[*local_idx as usize];
+        self.stack.push(value);
+      }
+      Instruction::LocalSet(local_idx) => {
+        let value = self.stack.pop()?;
+        self.frame().locals[*local_idx as usize] = value;
+      }
+      Instruction::LocalTee(local_idx) => {
+        let value = self.stack.pop()?;
+        self.stack.push(value);
+        self.frame().locals[*local_idx as usize] = value;
+      }
+      Instruction::GlobalGet(global_idx) => {
+        let value = self.instance.globals[*global_idx as usize].borrow().get();
+        self.stack.push(value);
+      }
+      Instruction::GlobalSet(global_idx) => {
+        let value = self.stack.pop()?;
+        self.instance.globals[*global_idx as usize].borrow_mut().set(value)?;
+      }
+      // validation guarantees memory 0 exists
+      Instruction::Memory(op, memarg) => {
+        let mut memory = self.instance.memories[0].borrow_mut();
+        execute_memory(*op, memarg, &mut memory, &mut self.stack)?;
+      }
+      Instruction::MemorySize => {
+        let size = self.instance.memories[0].borrow().size();
+        self.stack.push(size as i32);
+      }
+      Instruction::MemoryGrow => {
+        let delta = self.stack.pop_i32()? as u32;
+        let previous = self.instance.memories[0].borrow_mut().grow(delta);
+        self.stack.push(previous.map_or(-1, |size| size as i32));
+      }
+      Instruction::I32Const(value) => self.stack.push(*value),
+      Instruction::I64Const(value) => self.stack.push(*value),
+      Instruction::F32Const(bits) => self.stack.push(f32::from_bits(*bits)),
+      Instruction::F64Const(bits) => self.stack.push(f64::from_bits(*bits)),
+      Instruction::Numeric(op) => execute_numeric(*op, &mut self.stack)?,
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{MAX_BACKTRACE_FRAMES, MAX_CALL_DEPTH};
+  use crate::{
+    bytes::module::Module,
+    diagnostics::{Diagnostic, RuntimeError},
+    runtime::{imports::Imports, instance::Instance, value::Value},
+  };
+
+  fn instantiate(wasm: &[u8]) -> Instance {
+    Instance::new(Module::new(wasm).unwrap(), &Imports::new()).unwrap()
+  }
+
+  fn invoke(text: &str, name: &str, args: &[Value]) -> Vec<Value> {
+    instantiate(&wat::parse_str(text).unwrap()).invoke(name, args).unwrap()
+  }
+
+  fn trap(text: &str, name: &str, args: &[Value]) -> Diagnostic {
+    instantiate(&wat::parse_str(text).unwrap()).invoke(name, args).unwrap_err()
+  }
+
+  fn message(error: RuntimeError) -> String {
+    Diagnostic::from(error).message
+  }
+
+  #[test]
+  fn factorial() {
+    let instance = instantiate(&wat::parse_file("tests/playground/factorial.wat").unwrap());
+    assert_eq!(
+      instance.invoke("factorial", &[Value::I32(5)]).unwrap(),
+      [Value::I32(120)]
+    );
+    assert_eq!(instance.invoke("factorial", &[Value::I32(0)]).unwrap(), [Value::I32(1)]);
+  }
+
+  #[test]
+  fn br_table_picks_the_label_or_the_default() {
+    let text = r#"(module
+      (func (export "select") (param i32) (result i32)
+        (block (block (block
+          (br_table 0 1 2 (local.get 0)))
+          (return (i32.const 10)))
+          (return (i32.const 11)))
+        i32.const 12))"#;
+    for (arg, result) in [(0, 10), (1, 11), (2, 12), (3, 12), (-1, 12)] {
+      assert_eq!(
+        invoke(text, "select", &[Value::I32(arg)]),
+        [Value::I32(result)],
+        "{}",
+        arg
+      );
+    }
+  }
+
+  #[test]
+  fn loop_with_br_if() {
+    let text = r#"(module
+      (func (export "sum") (param $n i32) (result i32) (local $sum i32)
+        (loop $continue
+          (local.set $sum (i32.add (local.get $sum) (local.get $n)))
+          (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+          (br_if $continue (i32.gt_s (local.get $n) (i32.const 0))))
+        local.get $sum))"#;
+    assert_eq!(invoke(text, "sum", &[Value::I32(10)]), [Value::I32(55)]);
+    assert_eq!(invoke(text, "sum", &[Value::I32(0)]), [Value::I32(0)]);
+  }
+
+  #[test]
+  fn blocks_leave_their_results() {
+    let text = r#"(module
+      (func (export "block") (result i32)
+        (block (result i32) i32.const 1 i32.const 2 drop))
+      (func (export "branch") (result i64)
+        (block (result i64) i64.const 3 i64.const 4 br 0))
+      (func (export "if") (param i32) (result f32)
+        (if (result f32) (local.get 0) (then (f32.const 1.5)) (else (f32.const -1.5))))
+      (func (export "nested") (result i32)
+        (i32.add
+          (block (result i32) (block (result i32) (i32.const 5)) (i32.const 6) i32.mul)
+          (i32.const 1))))"#;
+    assert_eq!(invoke(text, "block", &[]), [Value::I32(1)]);
+    assert_eq!(invoke(text, "branch", &[]), [Value::I64(4)]);
+    assert_eq!(invoke(text, "if", &[Value::I32(1)]), [Value::F32(1.5)]);
+    assert_eq!(invoke(text, "if", &[Value::I32(0)]), [Value::F32(-1.5)]);
+    assert_eq!(invoke(text, "nested", &[]), [Value::I32(31)]);
+  }
+
+  #[test]
+  fn unreachable_code_is_polymorphic() {
+    let text = r#"(module
+      (func (export "return") (result i32)
+        (block (result i32) i32.const 1 return i32.add))
+      (func (export "branch") (result i32)
+        (block (result i32) i32.const 2 br 0 f64.mul drop))
+      (func (export "select") (param i32) (result i64)
+        (block (result i64)
+          (br_if 0 (i64.const 3) (local.get 0))
+          drop unreachable select)))"#;
+    assert_eq!(invoke(text, "return", &[]), [Value::I32(1)]);
+    assert_eq!(invoke(text, "branch", &[]), [Value::I32(2)]);
+    assert_eq!(invoke(text, "select", &[Value::I32(1)]), [Value::I64(3)]);
+  }
+
+  #[test]
+  fn integer_division_traps() {
+    let text = r#"(module
+      (func (export "div_s") (param i32 i32) (result i32) (i32.div_s (local.get 0) (local.get 1)))
+      (func (export "rem_u") (param i64 i64) (result i64) (i64.rem_u (local.get 0) (local.get 1)))
+      (func (export "rem_s") (param i32 i32) (result i32) (i32.rem_s (local.get 0) (local.get 1))))"#;
+    let divide_by_zero = message(RuntimeError::IntegerDivideByZero { range: None });
+    assert_eq!(
+      trap(text, "div_s", &[Value::I32(1), Value::I32(0)]).message,
+      divide_by_zero
+    );
+    assert_eq!(
+      trap(text, "rem_u", &[Value::I64(1), Value::I64(0)]).message,
+      divide_by_zero
+    );
+    let overflow = message(RuntimeError::IntegerOverflow { range: None });
+    assert_eq!(
+      trap(text, "div_s", &[Value::I32(i32::MIN), Value::I32(-1)]).message,
+      overflow
+    );
+    // the remainder of the same division is defined
+    assert_eq!(
+      invoke(text, "rem_s", &[Value::I32(i32::MIN), Value::I32(-1)]),
+      [Value::I32(0)]
+    );
+  }
+
+  #[test]
+  fn truncation_traps() {
+    let text = r#"(module
+      (func (export "f32") (param f32) (result i32) (i32.trunc_f32_s (local.get 0)))
+      (func (export "f64") (param f64) (result i64) (i64.trunc_f64_u (local.get 0))))"#;
+    let invalid = message(RuntimeError::InvalidConversion { range: None });
+    let overflow = message(RuntimeError::IntegerOverflow { range: None });
+    assert_eq!(trap(text, "f32", &[Value::F32(f32::NAN)]).message, invalid);
+    assert_eq!(trap(text, "f64", &[Value::F64(f64::NAN)]).message, invalid);
+    assert_eq!(trap(text, "f32", &[Value::F32(2147483648.0)]).message, overflow);
+    assert_eq!(trap(text, "f32", &[Value::F32(f32::NEG_INFINITY)]).message, overflow);
+    assert_eq!(trap(text, "f64", &[Value::F64(-1.0)]).message, overflow);
+    assert_eq!(
+      invoke(text, "f32", &[Value::F32(-2147483648.0)]),
+      [Value::I32(i32::MIN)]
+    );
+    assert_eq!(invoke(text, "f64", &[Value::F64(-0.9)]), [Value::I64(0)]);
+  }
+
+  #[test]
+  fn backtrace_lists_the_active_calls() {
+    let text = r#"(module
+      (func $fail unreachable)
+      (func $middle (call $fail))
+      (func (export "outer") (call $middle)))"#;
+    let diagnostic = trap(text, "outer", &[]);
+    assert_eq!(diagnostic.message, message(RuntimeError::Unreachable { range: None }));
+    let start = diagnostic.range.unwrap().start;
+    let hint = diagnostic.hint.unwrap();
+    let lines: Vec<&str> = hint.lines().collect();
+    assert_eq!(lines[0], "wasm backtrace:");
+    assert_eq!(lines[1], format!("  0: 0x{:x} - $fail", start));
+    assert!(
+      lines[2].starts_with("  1: 0x") && lines[2].ends_with(" - $middle"),
+      "{}",
+      lines[2]
+    );
+    assert!(
+      lines[3].starts_with("  2: 0x") && lines[3].ends_with(" - func[2]"),
+      "{}",
+      lines[3]
+    );
+    assert_eq!(lines.len(), 4);
+  }
+
+  #[test]
+  fn unbounded_recursion_overflows_the_stack() {
+    let text = r#"(module (func $loop (export "loop") (call $loop)))"#;
+    let diagnostic = trap(text, "loop", &[]);
+    assert_eq!(diagnostic.message, message(RuntimeError::StackOverflow { range: None }));
+    let hint = diagnostic.hint.unwrap();
+    let lines: Vec<&str> = hint.lines().collect();
+    assert_eq!(lines.len(), 1 + MAX_BACKTRACE_FRAMES + 1);
+    assert!(lines[1..=MAX_BACKTRACE_FRAMES].iter().all(|line| line.ends_with(" - $loop")));
+    let more = format!("  ... {} more frames", MAX_CALL_DEPTH - MAX_BACKTRACE_FRAMES);
+    assert_eq!(lines[MAX_BACKTRACE_FRAMES + 1], more);
+  }
+}
@@ -0,0 +1,218 @@
+use crate::{
+  bytes::{
+    instructions::{MemArg, MemoryOp},
+    types::{MemoryType, MAX_PAGES, PAGE_SIZE},
+  },
+  diagnostics::{ResultWithDiagnostics, RuntimeError},
+};
+
+use super::stack::ValueStack;
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+// https://webassembly.github.io/spec/core/exec/runtime.html#memory-instances, little endian bytes in 64KiB pages
+#[derive(Debug)]
+pub struct Memory {
+  pub memory_type: MemoryType,
+  data: Vec<u8>,
+}
+
+// zeroes `data` up to `pages`, failing instead of aborting when the host can't allocate them
+fn resize(data: &mut Vec<u8>, pages: u32) -> Result<()> {
+  let size = pages as usize * PAGE_SIZE;
+  if data.try_reserve_exact(size - data.len()).is_err() {
+    return Err(RuntimeError::OutOfMemory { size: size as u64, range: None });
+  }
+  data.resize(size, 0);
+  Ok(())
+}
+
+impl Memory {
+  pub fn new(memory_type: MemoryType) -> Result<Self> {
+    let mut data = vec![];
+    resize(&mut data, memory_type.limits.min)?;
+    Ok(Self { memory_type, data })
+  }
+
+  // in pages
+  pub fn size(&self) -> u32 {
+    (self.data.len() / PAGE_SIZE) as u32
+  }
+
+  // the previous size in pages, or `None` when the maximum would be exceeded or the pages can't be allocated
+  pub fn grow(&mut self, delta: u32) -> Option<u32> {
+    let size = self.size();
+    let max = self.memory_type.limits.max.unwrap_or(MAX_PAGES);
+    if size as u64 + delta as u64 > max as u64 {
+      return None;
+    }
+    resize(&mut self.data, size + delta).ok()?;
+    Some(size)
+  }
+
+  fn check(&self, address: u64, length: usize) -> Result<usize> {
+    if address + length as u64 > self.data.len() as u64 {
+      return Err(RuntimeError::MemoryOutOfBounds { address, range: None });
+    }
+    Ok(address as usize)
+  }
+
+  pub fn read(&self, address: u64, length: usize) -> Result<&[u8]> {
+    let start = self.check(address, length)?;
+    Ok(&self.data[start..start + length])
+  }
+
+  pub fn write(&mut self, address: u64, bytes: &[u8]) -> Result<()> {
+    let start = self.check(address, bytes.len())?;
+    self.data[start..start + bytes.len()].copy_from_slice(bytes);
+    Ok(())
+  }
+
+  fn load<const N: usize>(&self, address: u64) -> Result<[u8; N]> {
+    Ok(self.read(address, N)?.try_into().unwrap())
+  }
+}
+
+// https://webassembly.github.io/spec/core/exec/instructions.html#memory-instructions
+pub(super) fn execute_memory(
+  op: MemoryOp,
+  memarg: &MemArg,
+  memory: &mut Memory,
+  stack: &mut ValueStack,
+) -> ResultWithDiagnostics<()> {
+  if op.is_store() {
+    return execute_store(op, memarg, memory, stack);
+  }
+  let address = stack.pop_i32()? as u32 as u64 + memarg.offset as u64;
+  match op {
+    MemoryOp::I32Load => stack.push(i32::from_le_bytes(memory.load(address)?)),
+    MemoryOp::I64Load => stack.push(i64::from_le_bytes(memory.load(address)?)),
+    MemoryOp::F32Load => stack.push(f32::from_le_bytes(memory.load(address)?)),
+    MemoryOp::F64Load => stack.push(f64::from_le_bytes(memory.load(address)?)),
+    MemoryOp::I32Load8S => stack.push(i8::from_le_bytes(memory.load(address)?) as i32),
+    MemoryOp::I32Load8U => stack.push(u8::from_le_bytes(memory.load(address)?) as i32),
+    MemoryOp::I32Load16S => stack.push(i16::from_le_bytes(memory.load(address)?) as i32),
+    MemoryOp::I32Load16U => stack.push(u16::from_le_bytes(memory.load(address)?) as i32),
+    MemoryOp::I64Load8S => stack.push(i8::from_le_bytes(memory.load(address)?) as i64),
+    MemoryOp::I64Load8U => stack.push(u8::from_le_bytes(memory.load(address)?) as i64),
+    MemoryOp::I64Load16S => stack.push(i16::from_le_bytes(memory.load(address)?) as i64),
+    MemoryOp::I64Load16U => stack.push(u16::from_le_bytes(memory.load(address)?) as i64),
+    MemoryOp::I64Load32S => stack.push(i32::from_le_bytes(memory.load(address)?) as i64),
+    MemoryOp::I64Load32U => stack.push(u32::from_le_bytes(memory.load(address)?) as i64),
+    MemoryOp::I32Store
+    | MemoryOp::I64Store
+    | MemoryOp::F32Store
+    | MemoryOp::F64Store
+    | MemoryOp::I32Store8
+    | MemoryOp::I32Store16
+    | MemoryOp::I64Store8
+    | MemoryOp::I64Store16
+    | MemoryOp::I64Store32 => unreachable!("{:?} is a store", op),
+  }
+  Ok(())
+}
+
+// the value is on top of the address, narrow stores keep the low bytes
+fn execute_store(
+  op: MemoryOp,
+  memarg: &MemArg,
+  memory: &mut Memory,
+  stack: &mut ValueStack,
+) -> ResultWithDiagnostics<()> {
+  let bytes = match op {
+    MemoryOp::I32Store => stack.pop_i32()?.to_le_bytes().to_vec(),
+    MemoryOp::I64Store => stack.pop_i64()?.to_le_bytes().to_vec(),
+    MemoryOp::F32Store => stack.pop_f32()?.to_le_bytes().to_vec(),
+    MemoryOp::F64Store => stack.pop_f64()?.to_le_bytes().to_vec(),
+    MemoryOp::I32Store8 => (stack.pop_i32()? as u8).to_le_bytes().to_vec(),
+    MemoryOp::I32Store16 => (stack.pop_i32()? as u16).to_le_bytes().to_vec(),
+    MemoryOp::I64Store8 => (stack.pop_i64()? as u8).to_le_bytes().to_vec(),
+    MemoryOp::I64Store16 => (stack.pop_i64()? as u16).to_le_bytes().to_vec(),
+    MemoryOp::I64Store32 => (stack.pop_i64()? as u32).to_le_bytes().to_vec(),
+    MemoryOp::I32Load
+    | MemoryOp::I64Load
+    | MemoryOp::F32Load
+    | MemoryOp::F64Load
+    | MemoryOp::I32Load8S
+    | MemoryOp::I32Load8U
+    | MemoryOp::I32Load16S
+    | MemoryOp::I32Load16U
+    | MemoryOp::I64Load8S
+    | MemoryOp::I64Load8U
+    | MemoryOp::I64Load16S
+    | MemoryOp::I64Load16U
+    | MemoryOp::I64Load32S
+    | MemoryOp::I64Load32U => unreachable!("{:?} is a load", op),
+  };
+  let address = stack.pop_i32()? as u32 as u64 + memarg.offset as u64;
+  memory.write(address, &bytes)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    bytes::module::Module,
+    diagnostics::Diagnostic,
+    runtime::{imports::Imports, instance::Instance, value::Value},
+  };
+
+  fn instantiate(text: &str) -> Result<Instance, Diagnostic> {
+    Instance::new(Module::new(&wat::parse_str(text).unwrap()).unwrap(), &Imports::new())
+  }
+
+  fn invoke(instance: &Instance, name: &str, arg: i32) -> Result<Vec<Value>, String> {
+    instance.invoke(name, &[Value::I32(arg)]).map_err(|diagnostic| diagnostic.message)
+  }
+
+  #[test]
+  fn accesses_past_the_end_trap() {
+    let instance = instantiate(
+      r#"(module (memory 1)
+        (func (export "load") (param i32) (result i32) (i32.load (local.get 0)))
+        (func (export "load_offset") (param i32) (result i64) (i64.load32_u offset=4294967295 (local.get 0)))
+        (func (export "store") (param i32) (i64.store16 offset=2 (local.get 0) (i64.const -1))))"#,
+    )
+    .unwrap();
+    assert_eq!(invoke(&instance, "load", 65532), Ok(vec![Value::I32(0)]));
+    let trap = |address: u64| Err(format!("memory out of bounds, address = {}", address));
+    assert_eq!(invoke(&instance, "load", 65533), trap(65533));
+    assert_eq!(invoke(&instance, "load", -1), trap(4294967295));
+    // the effective address doesn't wrap around
+    assert_eq!(invoke(&instance, "load_offset", 1), trap(4294967296));
+    assert_eq!(invoke(&instance, "load_offset", -1), trap(8589934590));
+    assert_eq!(invoke(&instance, "store", 65532), Ok(vec![]));
+    assert_eq!(invoke(&instance, "load", 65532), Ok(vec![Value::I32(-65536)]));
+    assert_eq!(invoke(&instance, "store", 65533), trap(65535));
+  }
+
+  #[test]
+  fn memory_grows_up_to_its_maximum() {
+    let instance = instantiate(
+      r#"(module (memory 1 3)
+        (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+        (func (export "size") (param i32) (result i32) (memory.size))
+        (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0))))"#,
+    )
+    .unwrap();
+    assert_eq!(invoke(&instance, "grow", 1), Ok(vec![Value::I32(1)]));
+    assert_eq!(invoke(&instance, "size", 0), Ok(vec![Value::I32(2)]));
+    assert_eq!(invoke(&instance, "load", 131071), Ok(vec![Value::I32(0)]));
+    assert_eq!(invoke(&instance, "grow", 2), Ok(vec![Value::I32(-1)]));
+    assert_eq!(invoke(&instance, "grow", -1), Ok(vec![Value::I32(-1)]));
+    assert_eq!(invoke(&instance, "size", 0), Ok(vec![Value::I32(2)]));
+    assert_eq!(invoke(&instance, "grow", 0), Ok(vec![Value::I32(2)]));
+    assert_eq!(invoke(&instance, "grow", 1), Ok(vec![Value::I32(2)]));
+    assert_eq!(invoke(&instance, "size", 0), Ok(vec![Value::I32(3)]));
+  }
+
+  #[test]
+  fn data_segments_must_fit() {
+    let instance = instantiate(r#"(module (memory 1) (data (i32.const 65534) "ab"))"#);
+    assert!(instance.is_ok());
+    let diagnostic = instantiate(r#"(module (memory 1) (data (i32.const 65535) "ab"))"#).unwrap_err();
+    assert_eq!(diagnostic.message, "memory out of bounds, address = 65535");
+    let diagnostic = instantiate(r#"(module (memory 0) (data (i32.const -1) ""))"#).unwrap_err();
+    assert_eq!(diagnostic.message, "memory out of bounds, address = 4294967295");
+  }
+}
@@ -0,0 +1,15 @@
+#![allow(dead_code)]
+mod code;
+mod global;
+mod imports;
+mod instance;
+mod interpreter;
+mod memory;
+mod numeric;
+mod stack;
+mod table;
+mod value;
+// the command line provides no imports, the rest of the host API is used through its modules
+pub use imports::Imports;
+pub use instance::Instance;
+pub use value::Value;
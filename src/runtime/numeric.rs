@@ -0,0 +1,296 @@
+use num_traits::Float;
+
+use crate::{
+  bytes::{instructions::NumericOp, types::ValueType},
+  diagnostics::{ResultWithDiagnostics, RuntimeError},
+};
+
+use super::{stack::ValueStack, value::Value};
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+// https://webassembly.github.io/spec/core/exec/numerics.html, operands are grouped by the signature of the op
+pub(super) fn execute_numeric(op: NumericOp, stack: &mut ValueStack) -> ResultWithDiagnostics<()> {
+  let value = match op.signature().0 {
+    [ValueType::I32] => i32_unary(op, stack.pop_i32()?),
+    [ValueType::I64] => i64_unary(op, stack.pop_i64()?),
+    [ValueType::F32] => f32_unary(op, stack.pop_f32()?)?,
+    [ValueType::F64] => f64_unary(op, stack.pop_f64()?)?,
+    [ValueType::I32, ValueType::I32] => {
+      let rhs = stack.pop_i32()?;
+      i32_binary(op, stack.pop_i32()?, rhs)?
+    }
+    [ValueType::I64, ValueType::I64] => {
+      let rhs = stack.pop_i64()?;
+      i64_binary(op, stack.pop_i64()?, rhs)?
+    }
+    [ValueType::F32, ValueType::F32] => {
+      let rhs = stack.pop_f32()?;
+      f32_binary(op, stack.pop_f32()?, rhs)
+    }
+    [ValueType::F64, ValueType::F64] => {
+      let rhs = stack.pop_f64()?;
+      f64_binary(op, stack.pop_f64()?, rhs)
+    }
+    operands => unreachable!("{:?} takes {:?} operands", op, operands),
+  };
+  stack.push(value);
+  Ok(())
+}
+
+// the integral part of `value`, which must fit in `[lower, upper)`
+fn truncate(value: f64, lower: f64, upper: f64) -> Result<f64> {
+  if value.is_nan() {
+    return Err(RuntimeError::InvalidConversion { range: None });
+  }
+  let truncated = value.trunc();
+  if truncated < lower || truncated >= upper {
+    return Err(RuntimeError::IntegerOverflow { range: None });
+  }
+  Ok(truncated)
+}
+
+fn trunc_i32(value: f64) -> Result<Value> {
+  truncate(value, -2147483648.0, 2147483648.0).map(|value| Value::I32(value as i32))
+}
+
+fn trunc_u32(value: f64) -> Result<Value> {
+  truncate(value, 0.0, 4294967296.0).map(|value| Value::I32(value as u32 as i32))
+}
+
+fn trunc_i64(value: f64) -> Result<Value> {
+  truncate(value, -9223372036854775808.0, 9223372036854775808.0).map(|value| Value::I64(value as i64))
+}
+
+fn trunc_u64(value: f64) -> Result<Value> {
+  truncate(value, 0.0, 18446744073709551616.0).map(|value| Value::I64(value as u64 as i64))
+}
+
+// unlike `f32::min`, a NaN operand wins and -0 is smaller than +0
+fn min<F: Float>(lhs: F, rhs: F) -> F {
+  if lhs.is_nan() || rhs.is_nan() {
+    return F::nan();
+  }
+  if lhs == rhs {
+    return if lhs.is_sign_negative() { lhs } else { rhs };
+  }
+  lhs.min(rhs)
+}
+
+fn max<F: Float>(lhs: F, rhs: F) -> F {
+  if lhs.is_nan() || rhs.is_nan() {
+    return F::nan();
+  }
+  if lhs == rhs {
+    return if lhs.is_sign_positive() { lhs } else { rhs };
+  }
+  lhs.max(rhs)
+}
+
+fn i32_unary(op: NumericOp, value: i32) -> Value {
+  match op {
+    NumericOp::I32Eqz => Value::from(value == 0),
+    NumericOp::I32Clz => Value::I32(value.leading_zeros() as i32),
+    NumericOp::I32Ctz => Value::I32(value.trailing_zeros() as i32),
+    NumericOp::I32Popcnt => Value::I32(value.count_ones() as i32),
+    NumericOp::I64ExtendI32S => Value::I64(value as i64),
+    NumericOp::I64ExtendI32U => Value::I64(value as u32 as i64),
+    NumericOp::F32ConvertI32S => Value::F32(value as f32),
+    NumericOp::F32ConvertI32U => Value::F32(value as u32 as f32),
+    NumericOp::F64ConvertI32S => Value::F64(value as f64),
+    NumericOp::F64ConvertI32U => Value::F64(value as u32 as f64),
+    NumericOp::F32ReinterpretI32 => Value::F32(f32::from_bits(value as u32)),
+    op => unreachable!("{:?} is not an i32 unary op", op),
+  }
+}
+
+fn i64_unary(op: NumericOp, value: i64) -> Value {
+  match op {
+    NumericOp::I64Eqz => Value::from(value == 0),
+    NumericOp::I64Clz => Value::I64(value.leading_zeros() as i64),
+    NumericOp::I64Ctz => Value::I64(value.trailing_zeros() as i64),
+    NumericOp::I64Popcnt => Value::I64(value.count_ones() as i64),
+    NumericOp::I32WrapI64 => Value::I32(value as i32),
+    NumericOp::F32ConvertI64S => Value::F32(value as f32),
+    NumericOp::F32ConvertI64U => Value::F32(value as u64 as f32),
+    NumericOp::F64ConvertI64S => Value::F64(value as f64),
+    NumericOp::F64ConvertI64U => Value::F64(value as u64 as f64),
+    NumericOp::F64ReinterpretI64 => Value::F64(f64::from_bits(value as u64)),
+    op => unreachable!("{:?} is not an i64 unary op", op),
+  }
+}
+
+fn f32_unary(op: NumericOp, value: f32) -> Result<Value> {
+  let result = match op {
+    NumericOp::F32Abs => Value::F32(value.abs()),
+    NumericOp::F32Neg => Value::F32(-value),
+    NumericOp::F32Ceil => Value::F32(value.ceil()),
+    NumericOp::F32Floor => Value::F32(value.floor()),
+    NumericOp::F32Trunc => Value::F32(value.trunc()),
+    NumericOp::F32Nearest => Value::F32(value.round_ties_even()),
+    NumericOp::F32Sqrt => Value::F32(value.sqrt()),
+    NumericOp::I32TruncF32S => trunc_i32(value as f64)?,
+    NumericOp::I32TruncF32U => trunc_u32(value as f64)?,
+    NumericOp::I64TruncF32S => trunc_i64(value as f64)?,
+    NumericOp::I64TruncF32U => trunc_u64(value as f64)?,
+    NumericOp::F64PromoteF32 => Value::F64(value as f64),
+    NumericOp::I32ReinterpretF32 => Value::I32(value.to_bits() as i32),
+    op => unreachable!("{:?} is not an f32 unary op", op),
+  };
+  Ok(result)
+}
+
+fn f64_unary(op: NumericOp, value: f64) -> Result<Value> {
+  let result = match op {
+    NumericOp::F64Abs => Value::F64(value.abs()),
+    NumericOp::F64Neg => Value::F64(-value),
+    NumericOp::F64Ceil => Value::F64(value.ceil()),
+    NumericOp::F64Floor => Value::F64(value.floor()),
+    NumericOp::F64Trunc => Value::F64(value.trunc()),
+    NumericOp::F64Nearest => Value::F64(value.round_ties_even()),
+    NumericOp::F64Sqrt => Value::F64(value.sqrt()),
+    NumericOp::I32TruncF64S => trunc_i32(value)?,
+    NumericOp::I32TruncF64U => trunc_u32(value)?,
+    NumericOp::I64TruncF64S => trunc_i64(value)?,
+    NumericOp::I64TruncF64U => trunc_u64(value)?,
+    NumericOp::F32DemoteF64 => Value::F32(value as f32),
+    NumericOp::I64ReinterpretF64 => Value::I64(value.to_bits() as i64),
+    op => unreachable!("{:?} is not an f64 unary op", op),
+  };
+  Ok(result)
+}
+
+fn i32_binary(op: NumericOp, lhs: i32, rhs: i32) -> Result<Value> {
+  let (unsigned_lhs, unsigned_rhs) = (lhs as u32, rhs as u32);
+  let result = match op {
+    NumericOp::I32Eq => Value::from(lhs == rhs),
+    NumericOp::I32Ne => Value::from(lhs != rhs),
+    NumericOp::I32LtS => Value::from(lhs < rhs),
+    NumericOp::I32LtU => Value::from(unsigned_lhs < unsigned_rhs),
+    NumericOp::I32GtS => Value::from(lhs > rhs),
+    NumericOp::I32GtU => Value::from(unsigned_lhs > unsigned_rhs),
+    NumericOp::I32LeS => Value::from(lhs <= rhs),
+    NumericOp::I32LeU => Value::from(unsigned_lhs <= unsigned_rhs),
+    NumericOp::I32GeS => Value::from(lhs >= rhs),
+    NumericOp::I32GeU => Value::from(unsigned_lhs >= unsigned_rhs),
+    NumericOp::I32Add => Value::I32(lhs.wrapping_add(rhs)),
+    NumericOp::I32Sub => Value::I32(lhs.wrapping_sub(rhs)),
+    NumericOp::I32Mul => Value::I32(lhs.wrapping_mul(rhs)),
+    NumericOp::I32DivS | NumericOp::I32DivU | NumericOp::I32RemS | NumericOp::I32RemU if rhs == 0 => {
+      return Err(RuntimeError::IntegerDivideByZero { range: None });
+    }
+    NumericOp::I32DivS if lhs == i32::MIN && rhs == -1 => return Err(RuntimeError::IntegerOverflow { range: None }),
+    NumericOp::I32DivS => Value::I32(lhs / rhs),
+    NumericOp::I32DivU => Value::I32((unsigned_lhs / unsigned_rhs) as i32),
+    NumericOp::I32RemS => Value::I32(lhs.wrapping_rem(rhs)),
+    NumericOp::I32RemU => Value::I32((unsigned_lhs % unsigned_rhs) as i32),
+    NumericOp::I32And => Value::I32(lhs & rhs),
+    NumericOp::I32Or => Value::I32(lhs | rhs),
+    NumericOp::I32Xor => Value::I32(lhs ^ rhs),
+    NumericOp::I32Shl => Value::I32(lhs.wrapping_shl(unsigned_rhs)),
+    NumericOp::I32ShrS => Value::I32(lhs.wrapping_shr(unsigned_rhs)),
+    NumericOp::I32ShrU => Value::I32(unsigned_lhs.wrapping_shr(unsigned_rhs) as i32),
+    NumericOp::I32Rotl => Value::I32(lhs.rotate_left(unsigned_rhs)),
+    NumericOp::I32Rotr => Value::I32(lhs.rotate_right(unsigned_rhs)),
+    op => unreachable!("{:?} is not an i32 binary op", op),
+  };
+  Ok(result)
+}
+
+fn i64_binary(op: NumericOp, lhs: i64, rhs: i64) -> Result<Value> {
+  let (unsigned_lhs, unsigned_rhs) = (lhs as u64, rhs as u64);
+  let result = match op {
+    NumericOp::I64Eq => Value::from(lhs == rhs),
+    NumericOp::I64Ne => Value::from(lhs != rhs),
+    NumericOp::I64LtS => Value::from(lhs < rhs),
+    NumericOp::I64LtU => Value::from(unsigned_lhs < unsigned_rhs),
+    NumericOp::I64GtS => Value::from(lhs > rhs),
+    NumericOp::I64GtU => Value::from(unsigned_lhs > unsigned_rhs),
+    NumericOp::I64LeS => Value::from(lhs <= rhs),
+    NumericOp::I64LeU => Value::from(unsigned_lhs <= unsigned_rhs),
+    NumericOp::I64GeS => Value::from(lhs >= rhs),
+    NumericOp::I64GeU => Value::from(unsigned_lhs >= unsigned_rhs),
+    NumericOp::I64Add => Value::I64(lhs.wrapping_add(rhs)),
+    NumericOp::I64Sub => Value::I64(lhs.wrapping_sub(rhs)),
+    NumericOp::I64Mul => Value::I64(lhs.wrapping_mul(rhs)),
+    NumericOp::I64DivS | NumericOp::I64DivU | NumericOp::I64RemS | NumericOp::I64RemU if rhs == 0 => {
+      return Err(RuntimeError::IntegerDivideByZero { range: None });
+    }
+    NumericOp::I64DivS if lhs == i64::MIN && rhs == -1 => return Err(RuntimeError::IntegerOverflow { range: None }),
+    NumericOp::I64DivS => Value::I64(lhs / rhs),
+    NumericOp::I64DivU => Value::I64((unsigned_lhs / unsigned_rhs) as i64),
+    NumericOp::I64RemS => Value::I64(lhs.wrapping_rem(rhs)),
+    NumericOp::I64RemU => Value::I64((unsigned_lhs % unsigned_rhs) as i64),
+    NumericOp::I64And => Value::I64(lhs & rhs),
+    NumericOp::I64Or => Value::I64(lhs | rhs),
+    NumericOp::I64Xor => Value::I64(lhs ^ rhs),
+    NumericOp::I64Shl => Value::I64(lhs.wrapping_shl(rhs as u32)),
+    NumericOp::I64ShrS => Value::I64(lhs.wrapping_shr(rhs as u32)),
+    NumericOp::I64ShrU => Value::I64(unsigned_lhs.wrapping_shr(rhs as u32) as i64),
+    NumericOp::I64Rotl => Value::I64(lhs.rotate_left(rhs as u32)),
+    NumericOp::I64Rotr => Value::I64(lhs.rotate_right(rhs as u32)),
+    op => unreachable!("{:?} is not an i64 binary op", op),
+  };
+  Ok(result)
+}
+
+fn f32_binary(op: NumericOp, lhs: f32, rhs: f32) -> Value {
+  match op {
+    NumericOp::F32Eq => Value::from(lhs == rhs),
+    NumericOp::F32Ne => Value::from(lhs != rhs),
+    NumericOp::F32Lt => Value::from(lhs < rhs),
+    NumericOp::F32Gt => Value::from(lhs > rhs),
+    NumericOp::F32Le => Value::from(lhs <= rhs),
+    NumericOp::F32Ge => Value::from(lhs >= rhs),
+    NumericOp::F32Add => Value::F32(lhs + rhs),
+    NumericOp::F32Sub => Value::F32(lhs - rhs),
+    NumericOp::F32Mul => Value::F32(lhs * rhs),
+    NumericOp::F32Div => Value::F32(lhs / rhs),
+    NumericOp::F32Min => Value::F32(min(lhs, rhs)),
+    NumericOp::F32Max => Value::F32(max(lhs, rhs)),
+    NumericOp::F32Copysign => Value::F32(lhs.copysign(rhs)),
+    op => unreachable!("{:?} is not an f32 binary op", op),
+  }
+}
+
+fn f64_binary(op: NumericOp, lhs: f64, rhs: f64) -> Value {
+  match op {
+    NumericOp::F64Eq => Value::from(lhs == rhs),
+    NumericOp::F64Ne => Value::from(lhs != rhs),
+    NumericOp::F64Lt => Value::from(lhs < rhs),
+    NumericOp::F64Gt => Value::from(lhs > rhs),
+    NumericOp::F64Le => Value::from(lhs <= rhs),
+    NumericOp::F64Ge => Value::from(lhs >= rhs),
+    NumericOp::F64Add => Value::F64(lhs + rhs),
+    NumericOp::F64Sub => Value::F64(lhs - rhs),
+    NumericOp::F64Mul => Value::F64(lhs * rhs),
+    NumericOp::F64Div => Value::F64(lhs / rhs),
+    NumericOp::F64Min => Value::F64(min(lhs, rhs)),
+    NumericOp::F64Max => Value::F64(max(lhs, rhs)),
+    NumericOp::F64Copysign => Value::F64(lhs.copysign(rhs)),
+    op => unreachable!("{:?} is not an f64 binary op", op),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use num_traits::FromPrimitive;
+
+  use super::*;
+
+  #[test]
+  fn every_op_is_executed_by_its_signature() {
+    for opcode in 0x45..=0xbf {
+      let op = NumericOp::from_u8(opcode).unwrap();
+      let (operands, result) = op.signature();
+      let mut stack = ValueStack::default();
+      // ones so that divisions don't trap
+      for &operand in operands {
+        stack.push(Value::parse("1", operand).unwrap());
+      }
+      execute_numeric(op, &mut stack).unwrap();
+      assert_eq!(stack.len(), 1, "{}", op.name());
+      assert_eq!(stack.pop().unwrap().value_type(), result, "{}", op.name());
+    }
+  }
+}
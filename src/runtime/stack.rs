@@ -0,0 +1,76 @@
+use crate::{
+  bytes::types::ValueType,
+  diagnostics::{ResultWithDiagnostics, RuntimeError},
+};
+
+use super::value::Value;
+
+// operands of every active frame, validation guarantees their types so a mismatch is a bug in the interpreter
+#[derive(Debug, Default)]
+pub(super) struct ValueStack {
+  values: Vec<Value>,
+}
+
+fn mismatch(expected: ValueType, found: Option<Value>) -> RuntimeError {
+  let found = found.map_or("nothing".to_string(), |value| value.value_type().to_string());
+  RuntimeError::TypeMismatch { expected: expected.to_string(), found, range: None }
+}
+
+impl ValueStack {
+  pub fn len(&self) -> usize {
+    self.values.len()
+  }
+
+  pub fn push(&mut self, value: impl Into<Value>) {
+    self.values.push(value.into());
+  }
+
+  pub fn extend(&mut self, values: Vec<Value>) {
+    self.values.extend(values);
+  }
+
+  pub fn pop(&mut self) -> ResultWithDiagnostics<Value> {
+    self.values.pop().ok_or_else(|| {
+      let error =
+        RuntimeError::TypeMismatch { expected: "a value".to_string(), found: "nothing".to_string(), range: None };
+      error.into()
+    })
+  }
+
+  // the top `count` values, in the order they were pushed
+  pub fn pop_n(&mut self, count: usize) -> Vec<Value> {
+    self.values.split_off(self.values.len() - count)
+  }
+
+  pub fn truncate(&mut self, height: usize) {
+    self.values.truncate(height);
+  }
+
+  pub fn pop_i32(&mut self) -> ResultWithDiagnostics<i32> {
+    match self.values.pop() {
+      Some(Value::I32(value)) => Ok(value),
+      found => Err(mismatch(ValueType::I32, found).into()),
+    }
+  }
+
+  pub fn pop_i64(&mut self) -> ResultWithDiagnostics<i64> {
+    match self.values.pop() {
+      Some(Value::I64(value)) => Ok(value),
+      found => Err(mismatch(ValueType::I64, found).into()),
+    }
+  }
+
+  pub fn pop_f32(&mut self) -> ResultWithDiagnostics<f32> {
+    match self.values.pop() {
+      Some(Value::F32(value)) => Ok(value),
+      found => Err(mismatch(ValueType::F32, found).into()),
+    }
+  }
+
+  pub fn pop_f64(&mut self) -> ResultWithDiagnostics<f64> {
+    match self.values.pop() {
+      Some(Value::F64(value)) => Ok(value),
+      found => Err(mismatch(ValueType::F64, found).into()),
+    }
+  }
+}
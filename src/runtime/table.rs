@@ -0,0 +1,109 @@
+use crate::{bytes::types::TableType, diagnostics::RuntimeError};
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+// like other engines, larger tables fail instantiation instead of allocating gigabytes
+pub const MAX_TABLE_SIZE: u32 = 10_000_000;
+
+// https://webassembly.github.io/spec/core/exec/runtime.html#table-instances, only funcref tables hold function indexes
+#[derive(Debug)]
+pub struct Table {
+  pub table_type: TableType,
+  elements: Vec<Option<u32>>,
+}
+
+impl Table {
+  pub fn new(table_type: TableType) -> Result<Self> {
+    let size = table_type.limits.min;
+    let mut elements = vec![];
+    if size > MAX_TABLE_SIZE || elements.try_reserve_exact(size as usize).is_err() {
+      let size = size as u64 * std::mem::size_of::<Option<u32>>() as u64;
+      return Err(RuntimeError::OutOfMemory { size, range: None });
+    }
+    elements.resize(size as usize, None);
+    Ok(Self { table_type, elements })
+  }
+
+  pub fn size(&self) -> u32 {
+    self.elements.len() as u32
+  }
+
+  // `None` for an element no segment initialized
+  pub fn get(&self, index: u32) -> Result<Option<u32>> {
+    match self.elements.get(index as usize) {
+      Some(element) => Ok(*element),
+      None => Err(RuntimeError::TableOutOfBounds { index, range: None }),
+    }
+  }
+
+  // the whole segment must fit, the faulting index is the first one past the end
+  pub fn init(&mut self, offset: u32, func_indexes: &[u32]) -> Result<()> {
+    let end = offset as u64 + func_indexes.len() as u64;
+    if end > self.elements.len() as u64 {
+      let index = offset.max(self.size());
+      return Err(RuntimeError::TableOutOfBounds { index, range: None });
+    }
+    let start = offset as usize;
+    for (element, func_idx) in self.elements[start..start + func_indexes.len()].iter_mut().zip(func_indexes) {
+      *element = Some(*func_idx);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    bytes::module::Module,
+    diagnostics::Diagnostic,
+    runtime::{imports::Imports, instance::Instance, value::Value},
+  };
+
+  fn instantiate(text: &str) -> Result<Instance, Diagnostic> {
+    Instance::new(Module::new(&wat::parse_str(text).unwrap()).unwrap(), &Imports::new())
+  }
+
+  #[test]
+  fn call_indirect_checks_the_element_and_its_signature() {
+    let instance = instantiate(
+      r#"(module
+        (type $unary (func (param i32) (result i32)))
+        (type $same (func (param i32) (result i32)))
+        (table 4 funcref)
+        (elem (i32.const 1) $double $seven)
+        (func $double (param i32) (result i32) (i32.mul (local.get 0) (i32.const 2)))
+        (func $seven (result i32) (i32.const 7))
+        (func (export "call") (param i32) (result i32) (call_indirect (type $unary) (i32.const 21) (local.get 0)))
+        (func (export "call_same") (param i32) (result i32) (call_indirect (type $same) (i32.const 4) (local.get 0))))"#,
+    )
+    .unwrap();
+    let call = |name: &str, index: i32| instance.invoke(name, &[Value::I32(index)]).map_err(|error| error.message);
+    assert_eq!(call("call", 1), Ok(vec![Value::I32(42)]));
+    // signatures match structurally
+    assert_eq!(call("call_same", 1), Ok(vec![Value::I32(8)]));
+    assert_eq!(call("call", 0), Err("uninitialized element, index = 0".to_string()));
+    assert_eq!(call("call", 2), Err("call indirect: type index = 0".to_string()));
+    assert_eq!(call("call", 4), Err("table out of bounds, index = 4".to_string()));
+    assert_eq!(
+      call("call", -1),
+      Err("table out of bounds, index = 4294967295".to_string())
+    );
+  }
+
+  #[test]
+  fn element_segments_must_fit() {
+    let table = |elem: &str| instantiate(&format!("(module (table 2 funcref) (func $f) {})", elem)).map(|_| ());
+    assert!(table("(elem (i32.const 0) $f $f)").is_ok());
+    assert!(table("(elem (i32.const 2))").is_ok());
+    let error = table("(elem (i32.const 1) $f $f)").unwrap_err();
+    assert_eq!(error.message, "table out of bounds, index = 2");
+    let error = table("(elem (i32.const -1) $f)").unwrap_err();
+    assert_eq!(error.message, "table out of bounds, index = 4294967295");
+  }
+
+  #[test]
+  fn huge_tables_fail_instantiation() {
+    let error = instantiate("(module (table 4294967295 funcref))").unwrap_err();
+    assert_eq!(error.message, "out of memory, cannot allocate 34359738360 bytes");
+  }
+}
@@ -0,0 +1,106 @@
+use crate::{
+  bytes::types::{format_value_types, ValueType},
+  diagnostics::{ResultWithDiagnostics, RuntimeError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+  I32(i32),
+  I64(i64),
+  F32(f32),
+  F64(f64),
+}
+
+impl Value {
+  // the zero every local starts with
+  pub fn default_for(value_type: ValueType) -> Self {
+    match value_type {
+      ValueType::I32 => Value::I32(0),
+      ValueType::I64 => Value::I64(0),
+      ValueType::F32 => Value::F32(0.0),
+      ValueType::F64 => Value::F64(0.0),
+      ValueType::V128 | ValueType::FuncRef | ValueType::ExternRef => {
+        unreachable!("validation rejects {} locals", value_type)
+      }
+    }
+  }
+
+  pub fn value_type(&self) -> ValueType {
+    match self {
+      Value::I32(_) => ValueType::I32,
+      Value::I64(_) => ValueType::I64,
+      Value::F32(_) => ValueType::F32,
+      Value::F64(_) => ValueType::F64,
+    }
+  }
+
+  // a command line argument, integers may be written signed or unsigned
+  pub fn parse(text: &str, value_type: ValueType) -> Option<Self> {
+    match value_type {
+      ValueType::I32 => {
+        text.parse::<i32>().ok().or_else(|| text.parse::<u32>().ok().map(|value| value as i32)).map(Value::I32)
+      }
+      ValueType::I64 => {
+        text.parse::<i64>().ok().or_else(|| text.parse::<u64>().ok().map(|value| value as i64)).map(Value::I64)
+      }
+      ValueType::F32 => text.parse::<f32>().ok().map(Value::F32),
+      ValueType::F64 => text.parse::<f64>().ok().map(Value::F64),
+      ValueType::V128 | ValueType::FuncRef | ValueType::ExternRef => None,
+    }
+  }
+}
+
+impl std::fmt::Display for Value {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Value::I32(value) => write!(f, "{}", value),
+      Value::I64(value) => write!(f, "{}", value),
+      Value::F32(value) => write!(f, "{}", value),
+      Value::F64(value) => write!(f, "{}", value),
+    }
+  }
+}
+
+impl From<i32> for Value {
+  fn from(value: i32) -> Self {
+    Value::I32(value)
+  }
+}
+
+impl From<i64> for Value {
+  fn from(value: i64) -> Self {
+    Value::I64(value)
+  }
+}
+
+impl From<f32> for Value {
+  fn from(value: f32) -> Self {
+    Value::F32(value)
+  }
+}
+
+impl From<f64> for Value {
+  fn from(value: f64) -> Self {
+    Value::F64(value)
+  }
+}
+
+impl From<bool> for Value {
+  fn from(value: bool) -> Self {
+    Value::I32(value as i32)
+  }
+}
+
+// the values must have exactly the given types, e.g. the arguments of an exported function
+pub fn check_values(values: &[Value], types: &[ValueType]) -> ResultWithDiagnostics<()> {
+  let found: Vec<ValueType> = values.iter().map(Value::value_type).collect();
+  if found != types {
+    let error = RuntimeError::TypeMismatch {
+      expected: format_value_types(types),
+      found: format_value_types(&found),
+      range: None,
+    };
+    return Err(error.into());
+  }
+  Ok(())
+}
@@ -1,7 +1,8 @@
 #![allow(dead_code)]
 pub mod range;
-pub fn match_number(character: char) -> bool {
-  "1234567890.".contains(character)
+// https://webassembly.github.io/spec/core/text/values.html#text-idchar
+pub fn match_idchar(character: char) -> bool {
+  character.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(character)
 }
 pub fn highlight_red(text: &str) -> String {
   format!("\x1b[31m{}\x1b[0m", text)
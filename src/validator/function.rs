@@ -0,0 +1,333 @@
+use crate::{
+  bytes::{
+    instructions::{BlockType, Instruction},
+    types::{format_value_types, DecodedBody, ElementType, FuncType, ValueType},
+  },
+  diagnostics::{Diagnostic, TypeError},
+  utils::range::Range,
+};
+
+use super::validator::{validate_value_type, Context};
+
+type Result<T> = std::result::Result<T, Diagnostic>;
+
+// params and locals of one function, the same limit as wasmparser.
+// the binary format allows 2^32 locals in a few bytes, they are checked before being expanded
+const MAX_LOCALS: u64 = 50_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+  Function,
+  Block,
+  Loop,
+  If,
+  Else,
+}
+
+struct ControlFrame {
+  kind: FrameKind,
+  results: Vec<ValueType>,
+  height: usize,     // operands below this height belong to the enclosing frames
+  unreachable: bool, // after `br`, `return` or `unreachable` the stack is polymorphic
+}
+
+impl ControlFrame {
+  // the values a branch to this frame carries, a loop is re-entered without any in the MVP
+  fn label_types(&self) -> &[ValueType] {
+    match self.kind {
+      FrameKind::Loop => &[],
+      _ => &self.results,
+    }
+  }
+}
+
+// https://webassembly.github.io/spec/core/appendix/algorithm.html
+pub(super) struct FunctionValidator<'c> {
+  context: &'c Context,
+  locals: Vec<ValueType>,           // parameters first
+  operands: Vec<Option<ValueType>>, // `None` is a value of unknown type, popped from an unreachable stack
+  controls: Vec<ControlFrame>,
+  range: Option<Range>, // the instruction being validated
+}
+
+impl<'c> FunctionValidator<'c> {
+  pub fn new(context: &'c Context, func_type: &FuncType, body: &DecodedBody) -> Result<Self> {
+    let declared = body.locals.iter().map(|local| local.count as u64).sum::<u64>();
+    let count = func_type.params.len() as u64 + declared;
+    if count > MAX_LOCALS {
+      return Err(TypeError::TooManyLocals { count, limit: MAX_LOCALS, range: None }.into());
+    }
+    let mut locals = func_type.params.clone();
+    for local in &body.locals {
+      locals.extend(std::iter::repeat_n(local.value_type, local.count as usize));
+    }
+    let function =
+      ControlFrame { kind: FrameKind::Function, results: func_type.results.clone(), height: 0, unreachable: false };
+    Ok(Self { context, locals, operands: vec![], controls: vec![function], range: None })
+  }
+
+  pub fn validate(mut self, body: &DecodedBody) -> Result<()> {
+    for local in &body.locals {
+      validate_value_type(local.value_type, None)?;
+    }
+    for decoded in &body.body {
+      self.range = Some(decoded.range.clone());
+      if self.controls.is_empty() {
+        return Err(self.mismatch("end of function", "more instructions"));
+      }
+      self.validate_instruction(&decoded.instruction)?;
+    }
+    if !self.controls.is_empty() {
+      return Err(self.mismatch("end", "end of function"));
+    }
+    Ok(())
+  }
+
+  fn mismatch(&self, expected: &str, found: &str) -> Diagnostic {
+    let error =
+      TypeError::TypeMismatch { expected: expected.to_string(), found: found.to_string(), range: self.range.clone() };
+    error.into()
+  }
+
+  // adds the offset of the current instruction to errors about module items
+  fn locate(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+    diagnostic.range = diagnostic.range.or(self.range.clone());
+    diagnostic
+  }
+
+  fn unknown(&self, kind: &str, index: u32) -> Diagnostic {
+    TypeError::UnknownIndex { kind: kind.to_string(), index, range: self.range.clone() }.into()
+  }
+
+  fn frame(&self) -> &ControlFrame {
+    self.controls.last().unwrap()
+  }
+
+  fn push(&mut self, value_type: ValueType) {
+    self.operands.push(Some(value_type));
+  }
+
+  fn push_all(&mut self, types: &[ValueType]) {
+    types.iter().for_each(|value_type| self.push(*value_type));
+  }
+
+  fn pop_any(&mut self) -> Result<Option<ValueType>> {
+    let frame = self.frame();
+    if self.operands.len() == frame.height {
+      if frame.unreachable {
+        return Ok(None);
+      }
+      return Err(self.mismatch("a value", "nothing"));
+    }
+    Ok(self.operands.pop().unwrap())
+  }
+
+  fn pop(&mut self, expected: ValueType) -> Result<()> {
+    let frame = self.frame();
+    if self.operands.len() == frame.height && !frame.unreachable {
+      return Err(self.mismatch(&expected.to_string(), "nothing"));
+    }
+    match self.pop_any()? {
+      Some(found) if found != expected => Err(self.mismatch(&expected.to_string(), &found.to_string())),
+      _ => Ok(()),
+    }
+  }
+
+  fn pop_all(&mut self, types: &[ValueType]) -> Result<()> {
+    types.iter().rev().try_for_each(|value_type| self.pop(*value_type))
+  }
+
+  fn push_control(&mut self, kind: FrameKind, block_type: &BlockType) -> Result<()> {
+    let results = match block_type {
+      BlockType::Empty => vec![],
+      BlockType::Value(value_type) => {
+        validate_value_type(*value_type, self.range.clone())?;
+        vec![*value_type]
+      }
+    };
+    let frame = ControlFrame { kind, results, height: self.operands.len(), unreachable: false };
+    self.controls.push(frame);
+    Ok(())
+  }
+
+  // the stack must hold exactly the results of the frame
+  fn pop_control(&mut self) -> Result<ControlFrame> {
+    let frame = self.frame();
+    let values = &self.operands[frame.height..];
+    let fits = values.len() == frame.results.len() || (frame.unreachable && values.len() < frame.results.len());
+    let fits = fits
+      && values
+        .iter()
+        .rev()
+        .zip(frame.results.iter().rev())
+        .all(|(value, result)| value.is_none_or(|value| value == *result));
+    if !fits {
+      let found: Vec<String> =
+        values.iter().map(|value| value.map_or("unknown".to_string(), |value| value.to_string())).collect();
+      return Err(self.mismatch(&format_value_types(&frame.results), &format!("[{}]", found.join(" "))));
+    }
+    let frame = self.controls.pop().unwrap();
+    self.operands.truncate(frame.height);
+    Ok(frame)
+  }
+
+  fn set_unreachable(&mut self) {
+    let frame = self.controls.last_mut().unwrap();
+    self.operands.truncate(frame.height);
+    frame.unreachable = true;
+  }
+
+  // the types a branch to `depth` carries
+  fn label_types(&self, depth: u32) -> Result<Vec<ValueType>> {
+    let position = self.controls.len().checked_sub(depth as usize + 1).ok_or_else(|| self.unknown("label", depth))?;
+    Ok(self.controls[position].label_types().to_vec())
+  }
+
+  fn local(&self, local_idx: u32) -> Result<ValueType> {
+    self.locals.get(local_idx as usize).copied().ok_or_else(|| self.unknown("local", local_idx))
+  }
+
+  fn validate_instruction(&mut self, instruction: &Instruction) -> Result<()> {
+    match instruction {
+      Instruction::Unreachable => self.set_unreachable(),
+      Instruction::Nop => {}
+      Instruction::Block(block_type) => self.push_control(FrameKind::Block, block_type)?,
+      Instruction::Loop(block_type) => self.push_control(FrameKind::Loop, block_type)?,
+      Instruction::If(block_type) => {
+        self.pop(ValueType::I32)?;
+        self.push_control(FrameKind::If, block_type)?;
+      }
+      Instruction::Else => {
+        if self.frame().kind != FrameKind::If {
+          return Err(self.mismatch("if", "else"));
+        }
+        let frame = self.pop_control()?;
+        let frame = ControlFrame { kind: FrameKind::Else, height: self.operands.len(), unreachable: false, ..frame };
+        self.controls.push(frame);
+      }
+      Instruction::End => {
+        // without an `else`, the missing branch produces nothing
+        if self.frame().kind == FrameKind::If && !self.frame().results.is_empty() {
+          return Err(self.mismatch(&format_value_types(&self.frame().results), "[]"));
+        }
+        let frame = self.pop_control()?;
+        self.push_all(&frame.results);
+      }
+      Instruction::Br(depth) => {
+        let types = self.label_types(*depth)?;
+        self.pop_all(&types)?;
+        self.set_unreachable();
+      }
+      Instruction::BrIf(depth) => {
+        self.pop(ValueType::I32)?;
+        let types = self.label_types(*depth)?;
+        self.pop_all(&types)?;
+        self.push_all(&types);
+      }
+      Instruction::BrTable { labels, default } => {
+        self.pop(ValueType::I32)?;
+        let types = self.label_types(*default)?;
+        for label in labels {
+          let label_types = self.label_types(*label)?;
+          if label_types != types {
+            return Err(self.mismatch(&format_value_types(&types), &format_value_types(&label_types)));
+          }
+        }
+        self.pop_all(&types)?;
+        self.set_unreachable();
+      }
+      Instruction::Return => {
+        let results = self.controls[0].results.clone();
+        self.pop_all(&results)?;
+        self.set_unreachable();
+      }
+      Instruction::Call(func_idx) => {
+        let func_type = self.context.function_type(*func_idx).map_err(|error| self.locate(error))?;
+        self.pop_all(&func_type.params)?;
+        self.push_all(&func_type.results);
+      }
+      Instruction::CallIndirect(type_idx) => {
+        let table = self.context.table(0).map_err(|error| self.locate(error))?;
+        if table.element_type != ElementType::FuncRef {
+          return Err(self.mismatch("funcref", &ValueType::from(table.element_type).to_string()));
+        }
+        let func_type = self.context.func_type(*type_idx).map_err(|error| self.locate(error))?;
+        self.pop(ValueType::I32)?;
+        self.pop_all(&func_type.params)?;
+        self.push_all(&func_type.results);
+      }
+      Instruction::Drop => {
+        self.pop_any()?;
+      }
+      Instruction::Select => {
+        self.pop(ValueType::I32)?;
+        let first = self.pop_any()?;
+        let second = self.pop_any()?;
+        if let (Some(first), Some(second)) = (first, second) {
+          if first != second {
+            return Err(self.mismatch(&first.to_string(), &second.to_string()));
+          }
+        }
+        self.operands.push(first.or(second));
+      }
+      Instruction::LocalGet(local_idx) => {
+        let value_type = self.local(*local_idx)?;
+        self.push(value_type);
+      }
+      Instruction::LocalSet(local_idx) => {
+        let value_type = self.local(*local_idx)?;
+        self.pop(value_type)?;
+      }
+      Instruction::LocalTee(local_idx) => {
+        let value_type = self.local(*local_idx)?;
+        self.pop(value_type)?;
+        self.push(value_type);
+      }
+      Instruction::GlobalGet(global_idx) => {
+        let global = self.context.global(*global_idx).map_err(|error| self.locate(error))?;
+        self.push(global.value_type);
+      }
+      Instruction::GlobalSet(global_idx) => {
+        let global = self.context.global(*global_idx).map_err(|error| self.locate(error))?;
+        if !global.mutable {
+          return Err(TypeError::ImmutableGlobal { index: *global_idx, range: self.range.clone() }.into());
+        }
+        self.pop(global.value_type)?;
+      }
+      Instruction::Memory(op, memarg) => {
+        self.context.memory(0).map_err(|error| self.locate(error))?;
+        let natural = op.natural_alignment().trailing_zeros();
+        if memarg.align > natural {
+          let range = self.range.clone();
+          return Err(TypeError::InvalidAlignment { align: memarg.align, natural, range }.into());
+        }
+        if op.is_store() {
+          self.pop(op.value_type())?;
+          self.pop(ValueType::I32)?;
+        } else {
+          self.pop(ValueType::I32)?;
+          self.push(op.value_type());
+        }
+      }
+      Instruction::MemorySize => {
+        self.context.memory(0).map_err(|error| self.locate(error))?;
+        self.push(ValueType::I32);
+      }
+      Instruction::MemoryGrow => {
+        self.context.memory(0).map_err(|error| self.locate(error))?;
+        self.pop(ValueType::I32)?;
+        self.push(ValueType::I32);
+      }
+      Instruction::I32Const(_) => self.push(ValueType::I32),
+      Instruction::I64Const(_) => self.push(ValueType::I64),
+      Instruction::F32Const(_) => self.push(ValueType::F32),
+      Instruction::F64Const(_) => self.push(ValueType::F64),
+      Instruction::Numeric(op) => {
+        let (params, result) = op.signature();
+        self.pop_all(params)?;
+        self.push(result);
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,4 @@
+mod function;
+#[allow(clippy::module_inception)]
+mod validator;
+pub use validator::{validate_function, Context, Validator};
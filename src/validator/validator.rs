@@ -0,0 +1,411 @@
+use std::collections::HashSet;
+
+use crate::{
+  bytes::{
+    module::Module,
+    types::{
+      ConstExpr, ElementType, ExportDesc, FuncType, GlobalType, ImportDesc, Limits, MemoryType, TableType, ValueType,
+      MAX_PAGES,
+    },
+  },
+  diagnostics::{Diagnostic, TypeError},
+  utils::range::Range,
+};
+
+use super::function::FunctionValidator;
+
+type Result<T> = std::result::Result<T, Diagnostic>;
+
+pub(super) fn unknown_index(kind: &str, index: u32) -> Diagnostic {
+  TypeError::UnknownIndex { kind: kind.to_string(), index, range: None }.into()
+}
+
+// only the MVP number types, vectors and references are decoded but not supported
+pub(super) fn validate_value_type(value_type: ValueType, range: Option<Range>) -> Result<()> {
+  match value_type {
+    ValueType::I32 | ValueType::I64 | ValueType::F32 | ValueType::F64 => Ok(()),
+    ValueType::V128 | ValueType::FuncRef | ValueType::ExternRef => {
+      Err(TypeError::UnknownType { name: value_type.to_string(), range }.into())
+    }
+  }
+}
+
+fn validate_limits(limits: &Limits, max_pages: Option<u32>) -> Result<()> {
+  if let Some(bound) = max_pages {
+    if limits.min > bound || limits.max.is_some_and(|max| max > bound) {
+      let reason = format!("memory size must be at most {} pages (4GiB)", bound);
+      return Err(TypeError::InvalidLimits { reason, range: None }.into());
+    }
+  }
+  if limits.max.is_some_and(|max| max < limits.min) {
+    let reason = "size minimum must not be greater than maximum".to_string();
+    return Err(TypeError::InvalidLimits { reason, range: None }.into());
+  }
+  Ok(())
+}
+
+// the type of every item instructions can refer to, imported items come first in each index space
+#[derive(Debug)]
+pub struct Context {
+  pub types: Vec<FuncType>,
+  pub functions: Vec<u32>, // type index of each function
+  pub tables: Vec<TableType>,
+  pub memories: Vec<MemoryType>,
+  pub globals: Vec<GlobalType>,
+  pub imported_globals: usize,
+}
+
+impl Context {
+  fn new(module: &Module) -> Self {
+    let mut context = Context {
+      types: module.type_section.clone().unwrap_or_default(),
+      functions: vec![],
+      tables: vec![],
+      memories: vec![],
+      globals: vec![],
+      imported_globals: 0,
+    };
+    for import in module.import_section.iter().flatten() {
+      match &import.desc {
+        ImportDesc::Func(type_idx) => context.functions.push(*type_idx),
+        ImportDesc::Table(table_type) => context.tables.push(table_type.clone()),
+        ImportDesc::Memory(memory_type) => context.memories.push(memory_type.clone()),
+        ImportDesc::Global(global_type) => context.globals.push(global_type.clone()),
+      }
+    }
+    context.imported_globals = context.globals.len();
+    context.functions.extend(module.function_section.iter().flatten());
+    context.tables.extend(module.table_section.iter().flatten().cloned());
+    context.memories.extend(module.memory_section.iter().flatten().cloned());
+    context.globals.extend(module.global_section.iter().flatten().map(|global| global.global_type.clone()));
+    context
+  }
+
+  pub fn func_type(&self, type_idx: u32) -> Result<&FuncType> {
+    self
+      .types
+      .get(type_idx as usize)
+      .ok_or_else(|| TypeError::UnknownType { name: type_idx.to_string(), range: None }.into())
+  }
+
+  pub fn function_type(&self, func_idx: u32) -> Result<&FuncType> {
+    let type_idx = self.functions.get(func_idx as usize).ok_or_else(|| unknown_index("function", func_idx))?;
+    self.func_type(*type_idx)
+  }
+
+  pub fn global(&self, global_idx: u32) -> Result<&GlobalType> {
+    self.globals.get(global_idx as usize).ok_or_else(|| unknown_index("global", global_idx))
+  }
+
+  pub fn table(&self, table_idx: u32) -> Result<&TableType> {
+    self.tables.get(table_idx as usize).ok_or_else(|| unknown_index("table", table_idx))
+  }
+
+  pub fn memory(&self, memory_idx: u32) -> Result<&MemoryType> {
+    self.memories.get(memory_idx as usize).ok_or_else(|| unknown_index("memory", memory_idx))
+  }
+}
+
+// https://webassembly.github.io/spec/core/valid/modules.html, stops at the first invalid construct
+pub struct Validator<'a> {
+  module: &'a Module,
+  context: Context,
+}
+
+impl<'a> Validator<'a> {
+  pub fn new(module: &'a Module) -> Self {
+    Self { module, context: Context::new(module) }
+  }
+
+  pub fn validate(&self) -> Result<()> {
+    self.validate_module()?;
+    let functions = self.module.function_section.as_ref().map_or(0, Vec::len);
+    (0..functions).try_for_each(|index| validate_function(self.module, &self.context, index))
+  }
+
+  // everything but the function bodies, which can be validated one by one with `validate_function`
+  pub fn validate_module(&self) -> Result<()> {
+    let context = &self.context;
+    for func_type in &context.types {
+      func_type
+        .params
+        .iter()
+        .chain(&func_type.results)
+        .try_for_each(|value_type| validate_value_type(*value_type, None))?;
+    }
+    for global in &context.globals {
+      validate_value_type(global.value_type, None)?;
+    }
+    for type_idx in &context.functions {
+      context.func_type(*type_idx)?;
+    }
+    if context.tables.len() > 1 {
+      return Err(TypeError::MultipleItems { kind: "table".to_string(), range: None }.into());
+    }
+    if context.memories.len() > 1 {
+      return Err(TypeError::MultipleItems { kind: "memory".to_string(), range: None }.into());
+    }
+    for table in &context.tables {
+      validate_limits(&table.limits, None)?;
+    }
+    for memory in &context.memories {
+      validate_limits(&memory.limits, Some(MAX_PAGES))?;
+    }
+    for global in self.module.global_section.iter().flatten() {
+      self.validate_const_expr(&global.init, global.global_type.value_type)?;
+    }
+    self.validate_exports()?;
+    self.validate_start()?;
+    self.validate_segments()
+  }
+
+  // what `validate_function` needs once the module itself is known to be valid
+  pub fn into_context(self) -> Context {
+    self.context
+  }
+
+  // only constants and immutable imported globals are allowed
+  fn validate_const_expr(&self, expr: &ConstExpr, expected: ValueType) -> Result<()> {
+    let found = match expr {
+      ConstExpr::I32Const(_) => ValueType::I32,
+      ConstExpr::I64Const(_) => ValueType::I64,
+      ConstExpr::F32Const(_) => ValueType::F32,
+      ConstExpr::F64Const(_) => ValueType::F64,
+      ConstExpr::GlobalGet(global_idx) => {
+        let global = self.context.global(*global_idx)?;
+        if *global_idx as usize >= self.context.imported_globals || global.mutable {
+          return Err(TypeError::ConstantExpressionRequired { range: None }.into());
+        }
+        global.value_type
+      }
+    };
+    if found != expected {
+      let error = TypeError::TypeMismatch { expected: expected.to_string(), found: found.to_string(), range: None };
+      return Err(error.into());
+    }
+    Ok(())
+  }
+
+  fn validate_exports(&self) -> Result<()> {
+    let context = &self.context;
+    let mut names = HashSet::new();
+    for export in self.module.export_section.iter().flatten() {
+      match export.desc {
+        ExportDesc::Func(func_idx) => context.function_type(func_idx).map(|_| ())?,
+        ExportDesc::Table(table_idx) => context.table(table_idx).map(|_| ())?,
+        ExportDesc::Memory(memory_idx) => context.memory(memory_idx).map(|_| ())?,
+        ExportDesc::Global(global_idx) => context.global(global_idx).map(|_| ())?,
+      }
+      if !names.insert(export.name.as_str()) {
+        return Err(TypeError::DuplicateExport { name: export.name.clone(), range: None }.into());
+      }
+    }
+    Ok(())
+  }
+
+  // the start function takes and returns nothing
+  fn validate_start(&self) -> Result<()> {
+    let Some(func_idx) = self.module.start_section else {
+      return Ok(());
+    };
+    let func_type = self.context.function_type(func_idx)?;
+    if !func_type.params.is_empty() || !func_type.results.is_empty() {
+      let found = func_type.to_string();
+      let error = TypeError::TypeMismatch { expected: "[] -> []".to_string(), found, range: None };
+      return Err(error.into());
+    }
+    Ok(())
+  }
+
+  fn validate_segments(&self) -> Result<()> {
+    for element in self.module.element_section.iter().flatten() {
+      let table = self.context.table(element.table_idx)?;
+      if table.element_type != ElementType::FuncRef {
+        let found = ValueType::from(table.element_type).to_string();
+        let error = TypeError::TypeMismatch { expected: "funcref".to_string(), found, range: None };
+        return Err(error.into());
+      }
+      self.validate_const_expr(&element.offset, ValueType::I32)?;
+      for func_idx in &element.init {
+        self.context.function_type(*func_idx)?;
+      }
+    }
+    for data in self.module.data_section.iter().flatten() {
+      self.context.memory(data.memory_idx)?;
+      self.validate_const_expr(&data.offset, ValueType::I32)?;
+    }
+    Ok(())
+  }
+}
+
+// `index` is the position of the function in the code section, its body is decoded if it was not yet
+pub fn validate_function(module: &Module, context: &Context, index: usize) -> Result<()> {
+  let imported = context.functions.len() - module.function_section.as_ref().map_or(0, Vec::len);
+  let Some(type_idx) = module.function_section.as_ref().and_then(|functions| functions.get(index)) else {
+    return Err(unknown_index("function", (imported + index) as u32));
+  };
+  let func_type = context.func_type(*type_idx)?;
+  let body = module.function_body(index)?;
+  let validator = FunctionValidator::new(context, func_type, body);
+  validator.and_then(|validator| validator.validate(body)).map_err(|mut diagnostic| {
+    let name = module.display_function((imported + index) as u32);
+    diagnostic.message = format!("{} in {}", diagnostic.message, name);
+    diagnostic
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn validate(text: &str) -> Result<()> {
+    let module = Module::new(&wat::parse_str(text).unwrap()).unwrap();
+    Validator::new(&module).validate()
+  }
+
+  #[test]
+  fn number_types_are_valid() {
+    validate("(module (global f64 (f64.const 1)) (func (param i32 i64) (result f32) (local f64) f32.const 0))")
+      .unwrap();
+  }
+
+  #[test]
+  fn non_mvp_value_types_are_unknown() {
+    let cases = [
+      ("(module (func (param funcref)))", "unknown type `funcref`"),
+      (
+        "(module (func (result externref) unreachable))",
+        "unknown type `externref`",
+      ),
+      ("(module (func (local v128)))", "unknown type `v128` in func[0]"),
+      (
+        "(module (import \"m\" \"g\" (global funcref)))",
+        "unknown type `funcref`",
+      ),
+      (
+        "(module (func (block (result v128) unreachable) drop))",
+        "unknown type `v128` in func[0]",
+      ),
+    ];
+    for (text, message) in cases {
+      assert_eq!(validate(text).unwrap_err().message, message, "{}", text);
+    }
+  }
+
+  fn messages(cases: &[(&str, &str)]) {
+    for (text, message) in cases {
+      assert_eq!(validate(text).unwrap_err().message, *message, "{}", text);
+    }
+  }
+
+  #[test]
+  fn stack_underflow() {
+    messages(&[
+      (
+        "(module (func i32.add drop))",
+        "expected `i32`, but found `nothing` in func[0]",
+      ),
+      // values below a block belong to the enclosing frame
+      (
+        "(module (func (result i32) i32.const 1 (block (result i32) i32.add)))",
+        "expected `i32`, but found `nothing` in func[0]",
+      ),
+      (
+        "(module (func drop))",
+        "expected `a value`, but found `nothing` in func[0]",
+      ),
+    ]);
+  }
+
+  #[test]
+  fn branch_depths_must_refer_to_a_label() {
+    messages(&[
+      ("(module (func br 1))", "unknown label 1 in func[0]"),
+      (
+        "(module (func (block (br_if 2 (i32.const 0)))))",
+        "unknown label 2 in func[0]",
+      ),
+      (
+        "(module (func (block (br_table 0 1 2 (i32.const 0)))))",
+        "unknown label 2 in func[0]",
+      ),
+    ]);
+    validate("(module (func (block (loop (br_table 0 1 2 (i32.const 0))))))").unwrap();
+  }
+
+  #[test]
+  fn if_without_else_has_no_result() {
+    messages(&[(
+      "(module (func (result i32) (if (result i32) (i32.const 1) (then (i32.const 2)))))",
+      "expected `[i32]`, but found `[]` in func[0]",
+    )]);
+    validate("(module (func (result i32) (if (result i32) (i32.const 1) (then (i32.const 2)) (else (i32.const 3)))))")
+      .unwrap();
+  }
+
+  #[test]
+  fn unreachable_code_is_polymorphic() {
+    validate("(module (func (result i32) unreachable i32.add))").unwrap();
+    validate("(module (func (result i32) i32.const 1 return i64.const 0 drop))").unwrap();
+    validate("(module (func (block (result f64) (br 0 (f64.const 1)) i32.add drop) drop))").unwrap();
+    // values pushed after the branch still have their type
+    messages(&[
+      (
+        "(module (func unreachable i32.const 1 i64.add drop))",
+        "expected `i64`, but found `i32` in func[0]",
+      ),
+      (
+        "(module (func (result i32) (block (result i32) br 0 i64.const 0)))",
+        "expected `i32`, but found `nothing` in func[0]",
+      ),
+    ]);
+  }
+
+  #[test]
+  fn results_must_match_at_end() {
+    messages(&[
+      (
+        "(module (func (result i32) i64.const 1))",
+        "expected `[i32]`, but found `[i64]` in func[0]",
+      ),
+      (
+        "(module (func (result i32)))",
+        "expected `[i32]`, but found `[]` in func[0]",
+      ),
+      (
+        "(module (func i32.const 1))",
+        "expected `[]`, but found `[i32]` in func[0]",
+      ),
+      (
+        "(module (func (block (result i32) i64.const 0) drop))",
+        "expected `[i32]`, but found `[i64]` in func[0]",
+      ),
+    ]);
+  }
+
+  // (module (func (local <count> i32))), encoded by hand since the count is the point
+  fn module_with_locals(count: u32) -> Vec<u8> {
+    let mut locals = vec![0x01];
+    let mut value = count;
+    while value >= 0x80 {
+      locals.push((value & 0x7f) as u8 | 0x80);
+      value >>= 7;
+    }
+    locals.extend([value as u8, 0x7f, 0x0b]);
+    let mut bytes = b"\0asm\x01\0\0\0\x01\x04\x01\x60\0\0\x03\x02\x01\0\x0a".to_vec();
+    bytes.extend([locals.len() as u8 + 2, 0x01, locals.len() as u8]);
+    bytes.extend(locals);
+    bytes
+  }
+
+  #[test]
+  fn too_many_locals() {
+    let validate = |bytes: &[u8]| Validator::new(&Module::new(bytes).unwrap()).validate();
+    assert_eq!(module_with_locals(u32::MAX).len(), 30);
+    validate(&module_with_locals(50_000)).unwrap();
+    let message = "too many locals, found 50001 but at most 50000 are allowed in func[0]";
+    assert_eq!(validate(&module_with_locals(50_001)).unwrap_err().message, message);
+    let message = "too many locals, found 4294967295 but at most 50000 are allowed in func[0]";
+    assert_eq!(validate(&module_with_locals(u32::MAX)).unwrap_err().message, message);
+  }
+}